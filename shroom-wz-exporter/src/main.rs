@@ -3,7 +3,6 @@ use std::path::{Path, PathBuf};
 use clap::Parser;
 
 use shroom_wz::{
-    file::WzReaderFile,
     val::{ObjectVal, WzValue},
     version::WzVersion,
     WzReaderMmap,
@@ -20,9 +19,14 @@ pub mod skill;
 pub mod skill2;
 
 fn load_wz(p: impl AsRef<Path>) -> anyhow::Result<WzReaderMmap> {
-    shroom_wz::WzReader::open_file_mmap(p, shroom_wz::version::WzRegion::GMS, WzVersion(95))
+    Ok(shroom_wz::WzReader::open_file_mmap(
+        p,
+        shroom_wz::version::WzRegion::GMS,
+        WzVersion(95),
+    )?)
 }
 
+#[allow(dead_code)]
 fn gen_skill() -> anyhow::Result<()> {
     #[allow(deprecated)]
     let file = std::env::home_dir()
@@ -31,7 +35,7 @@ fn gen_skill() -> anyhow::Result<()> {
 
     let mut file = load_wz(file)?;
 
-    let imgs = file.traverse_images().collect::<anyhow::Result<Vec<_>>>()?;
+    let imgs = file.traverse_images().collect::<Result<Vec<_>, _>>()?;
     let out_dir = PathBuf::from("out/skill");
 
     let mut schema = Schema::new();
@@ -79,12 +83,12 @@ fn main() -> anyhow::Result<()> {
         .join("Documents/open-rust-ms/Skill.wz");
 
     let mut file = load_wz(file)?;
-    let imgs = file.traverse_images().collect::<anyhow::Result<Vec<_>>>()?;
+    let imgs = file.traverse_images().collect::<Result<Vec<_>, _>>()?;
 
     for img in imgs.iter() {
         let (path, img) = img;
 
-        let mut img_reader = file.img_reader(&img)?;
+        let mut img_reader = file.img_reader(img)?;
         let val = WzValue::read(&mut img_reader)?;
         let val = val.as_object().unwrap();
         if val.get("skill").is_none() {
@@ -115,7 +119,7 @@ fn main() -> anyhow::Result<()> {
 
     //gen_skill()?;
 
-    //let imgs = file.img_iter().collect::<anyhow::Result<Vec<_>>>()?;
+    //let imgs = file.img_iter().collect::<Result<Vec<_>, _>>()?;
 
     Ok(())
 }
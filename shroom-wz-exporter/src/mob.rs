@@ -1,71 +1,112 @@
-use shroom_wz::val::ObjectVal;
+use serde::Deserialize;
+use shroom_wz::val::{from_object, ObjectVal};
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Mob {
     pub level: i16,
+    #[serde(rename = "maxHP")]
     pub max_hp: i32,
+    #[serde(rename = "maxMP")]
     pub max_mp: i32,
     pub hp_recovery: i32,
     pub mp_recovery: i32,
+    #[serde(rename = "PADamage")]
     pub pad: i32,
+    #[serde(rename = "PDDamage")]
     pub pdd: i32,
+    #[serde(rename = "MADamage")]
     pub mad: i32,
+    #[serde(rename = "MDDamage")]
     pub mdd: i32,
+    #[serde(rename = "MDRate")]
     pub mdr: i32,
+    #[serde(rename = "PDRate")]
     pub pdr: i32,
+    #[serde(rename = "eva")]
     pub evasion: i32,
     pub acc: i32,
 
     pub exp: i32,
+    // Keys which most mobs lack default to 0 or false
+    #[serde(rename = "boss", default)]
     pub is_boss: bool,
+    #[serde(default)]
     pub dead_buff: i32,
+    #[serde(default)]
     pub hp_gauge_hide: bool,
+    #[serde(rename = "removeAfter")]
     pub remove_time: i32,
+    #[serde(rename = "hpTagBgcolor", default)]
     pub hp_tag_bg_color: i32,
+    #[serde(default)]
     pub hp_tag_color: i32,
+    #[serde(default)]
     pub invincible: bool,
     pub speed: i32,
+    #[serde(default)]
     pub fly_speed: i32,
+    #[serde(default)]
     pub chase_speed: i32,
+    #[serde(default)]
     pub fixed_damage: i32,
+    #[serde(default)]
     pub do_not_remove: bool,
+    #[serde(default)]
     pub self_destruct_action_type: i32,
+    #[serde(default)]
     pub self_destruct_remove_after: i32,
+    #[serde(default)]
     pub cannot_evade: i32,
 }
 
 impl Mob {
     pub fn from_obj(obj: &ObjectVal) -> anyhow::Result<Self> {
         let info: &ObjectVal = obj.must_get_into("info")?;
-        Ok(Self {
-            level: info.must_get_into("level")?,
-            max_hp: info.must_get_into("maxHP")?,
-            max_mp: info.must_get_into("maxMP")?,
-            hp_recovery: info.must_get_into("hpRecovery")?,
-            mp_recovery: info.must_get_into("mpRecovery")?,
-            pad: info.must_get_into("PADamage")?,
-            pdd: info.must_get_into("PDDamage")?,
-            mad: info.must_get_into("MADamage")?,
-            mdd: info.must_get_into("MDDamage")?,
-            mdr: info.must_get_into("MDRate")?,
-            pdr: info.must_get_into("PDRate")?,
-            evasion: info.must_get_into("eva")?,
-            acc: info.must_get_into("acc")?,
-            exp: info.must_get_into("exp")?,
-            is_boss: info.must_get_into::<i32>("boss")? > 0,
-            dead_buff: info.must_get_into("deadBuff")?,
-            hp_gauge_hide: info.must_get_into::<i32>("hpGaugeHide")? > 0,
-            remove_time: info.must_get_into("removeAfter")?,
-            hp_tag_bg_color: info.must_get_into("hpTagBgcolor")?,
-            hp_tag_color: info.must_get_into("hpTagColor")?,
-            invincible: info.must_get_into::<i32>("invincible")? > 0,
-            speed: info.must_get_into("speed")?,
-            fly_speed: info.must_get_into("flySpeed")?,
-            chase_speed: info.must_get_into("chaseSpeed")?,
-            fixed_damage: info.must_get_into("fixedDamage")?,
-            do_not_remove: info.must_get_into::<i32>("doNotRemove")? > 0,
-            self_destruct_action_type: info.must_get_into("selfDestructActionType")?,
-            self_destruct_remove_after: info.must_get_into("selfDestructRemoveAfter")?,
-            cannot_evade: info.must_get_into("cannotEvade")?,
-        })
+        Ok(from_object(info)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shroom_wz::val::{ObjectVal, WzValue};
+
+    use super::Mob;
+
+    #[test]
+    fn minimal_info() {
+        let info = [
+            "level",
+            "maxHP",
+            "maxMP",
+            "hpRecovery",
+            "mpRecovery",
+            "PADamage",
+            "PDDamage",
+            "MADamage",
+            "MDDamage",
+            "MDRate",
+            "PDRate",
+            "eva",
+            "acc",
+            "exp",
+            "removeAfter",
+            "speed",
+        ]
+        .into_iter()
+        .map(|k| (k.to_string(), WzValue::Int(1)))
+        .collect();
+        let obj = ObjectVal(
+            [("info".to_string(), WzValue::Object(ObjectVal(info)))]
+                .into_iter()
+                .collect(),
+        );
+
+        let mob = Mob::from_obj(&obj).unwrap();
+        assert_eq!(mob.level, 1);
+        assert!(!mob.is_boss);
+        assert!(!mob.invincible);
+        assert_eq!(mob.fly_speed, 0);
+        assert_eq!(mob.hp_tag_bg_color, 0);
     }
 }
@@ -46,6 +46,7 @@ fn replace_boolean(table: &mut toml::Table, key: &str) {
     }
 }
 
+#[allow(dead_code)]
 fn replace_num(table: &mut toml::Table, key: &str) {
     if let Some(v) = table.get_mut(key) {
         if let Some(num) = v.as_str().and_then(|v| v.parse::<i64>().ok()) {
@@ -92,12 +93,10 @@ fn flatten_singular(tbl: &mut toml::Table, key: &str) -> Option<toml::Value> {
 
 pub fn process_skill_value(table: &mut toml::Table) -> ProcessResult {
     table.remove("origin");
-    table.remove("z");
     table.remove("mob");
     table.remove("hit");
     table.remove("summon");
-    table.remove("a0");
-    table.remove("a1");
+    // The alpha fade a0/a1 and z of frames are kept, see `AnimationFrame`
 
     replace_boolean(table, "disabled");
     replace_boolean(table, "disable");
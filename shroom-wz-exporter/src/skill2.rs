@@ -129,7 +129,7 @@ impl Skill {
     pub fn from_value(id: u32, val: &ObjectVal) -> anyhow::Result<Skill> {
         let c: &ObjectVal = val.must_get_into("common")?;
 
-        if let Ok(level) = val.must_get_into::<&ObjectVal>("level") {
+        if let Ok(_level) = val.must_get_into::<&ObjectVal>("level") {
             dbg!(id);
         }
 
@@ -138,7 +138,7 @@ impl Skill {
         let range = if let Some(lt) = c.get("lt") {
             let lt: &Vec2Val = lt.try_into()?;
             let rb: &Vec2Val = c.must_get_into("rb")?;
-            Some((lt.clone(), rb.clone()))
+            Some((*lt, *rb))
         } else {
             None
         };
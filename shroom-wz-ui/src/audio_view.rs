@@ -21,7 +21,7 @@ impl PartialEq for AudioData {
 
 impl Eq for AudioData {}
 
-#[inline_props]
+#[component]
 pub fn AudioView(cx: Scope, audio: Rc<AudioData>) -> Element {
     let audio_ref = use_ref::<Option<HtmlAudioElement>>(cx, || None);
 
@@ -36,7 +36,8 @@ pub fn AudioView(cx: Scope, audio: Rc<AudioData>) -> Element {
             js_sys::Uint8Array::new(&unsafe { js_sys::Uint8Array::view(&audio.data) }.into());
         let array = js_sys::Array::new();
         array.push(&uint8arr.buffer());
-        let bag = BlobPropertyBag::new().type_("audio/mpeg").to_owned();
+        let bag = BlobPropertyBag::new();
+        bag.set_type(audio.format.mime_type());
         let blob = Blob::new_with_u8_array_sequence_and_options(&array, &bag).unwrap();
         let url = Url::create_object_url_with_blob(&blob).unwrap();
 
@@ -46,7 +46,7 @@ fn image_to_imgdata(img: &RgbaImage) -> web_sys::ImageData {
         .expect("Img data")
 }
 
-#[inline_props]
+#[component]
 pub fn ImageView(cx: Scope, image: Rc<RgbaImage>) -> Element {
     let canvas_ctx = use_state::<Option<CanvasContext>>(cx, || None);
 
@@ -72,7 +72,7 @@ pub fn ImageView(cx: Scope, image: Rc<RgbaImage>) -> Element {
     })
 }
 
-#[inline_props]
+#[component]
 pub fn AnimationView(cx: Scope, anim_data: Rc<WzAnimationData>) -> Element {
     let canvas_ctx = use_state::<Option<CanvasContext>>(cx, || None);
     let frame_ix = use_state(cx, || 0);
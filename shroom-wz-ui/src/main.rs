@@ -1,4 +1,4 @@
-#![allow(non_snake_case)]
+#![allow(non_snake_case, mismatched_lifetime_syntaxes)]
 
 pub mod audio_view;
 pub mod image_view;
@@ -26,33 +26,40 @@ fn get_selected_file_from_input(file_input_id: &str) -> anyhow::Result<gloo::fil
         .unwrap();
     let files: gloo::file::FileList = el.files().expect("must have FileList").into();
     files
-        .get(0)
+        .first()
         .ok_or_else(|| anyhow::format_err!("should contain one file"))
         .cloned()
 }
 
-async fn read_wz_data(file_input_id: &str, version: WzVersion) -> anyhow::Result<WzData> {
+async fn read_wz_data(
+    file_input_id: &str,
+    version: Option<WzVersion>,
+) -> anyhow::Result<WzData> {
     let file = get_selected_file_from_input(file_input_id)?;
     let data = read_as_bytes(&file).await?;
     WzData::from_file(&file.name(), Cursor::new(data), version)
 }
 
-fn parse_version(form_data: &FormData) -> anyhow::Result<WzVersion> {
+/// Empty version means the version is detected
+fn parse_version(form_data: &FormData) -> anyhow::Result<Option<WzVersion>> {
     let version = form_data
         .values
         .get("version")
         .and_then(|v| v.first())
         .ok_or(anyhow!("Must have version"))?;
+    if version.trim().is_empty() {
+        return Ok(None);
+    }
     let version: usize = version.parse().map_err(|_| anyhow!("Invalid version"))?;
-    Ok(version.into())
+    Ok(Some(version.into()))
 }
 
-#[inline_props]
+#[component]
 fn FileForm(cx: Scope, wz: UseState<Option<Rc<WzData>>>) -> Element {
     const FILE_INPUT_ID: &str = "wz-file-input";
     let alert_error = use_state(cx, || None);
 
-    let load_file = |version: WzVersion| {
+    let load_file = |version: Option<WzVersion>| {
         to_owned!(wz);
         to_owned![alert_error];
         cx.spawn({
@@ -114,7 +121,7 @@ fn FileForm(cx: Scope, wz: UseState<Option<Rc<WzData>>>) -> Element {
                     r#type: "number",
                     class: "input input-bordered w-full max-w-xs",
                     name: "version",
-                    value: "95",
+                    placeholder: "Auto",
                 }
             },
             input {
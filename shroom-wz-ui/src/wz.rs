@@ -1,10 +1,4 @@
-use std::{
-    borrow::Cow,
-    cell::RefCell,
-    collections::{HashMap, VecDeque},
-    io::Cursor,
-    rc::Rc,
-};
+use std::{borrow::Cow, cell::RefCell, collections::HashMap, io::Cursor, rc::Rc};
 
 use dioxus::prelude::*;
 use id_tree::{NodeId, Tree};
@@ -93,14 +87,15 @@ impl PartialEq for WzAnimationData {
 impl Eq for WzAnimationData {}
 
 impl WzData {
-    #[cfg(feature = "mmap")]
-    fn load(file: impl AsRef<Path>) -> anyhow::Result<Self> {
-        let mut file = re_wz::WzReader::open_file_mmap(file, WzRegion::GMS, 95.into())?;
-        Self::from_file(file)
-    }
-
-    pub fn from_file(filename: &str, file: WzFile, version: WzVersion) -> anyhow::Result<Self> {
-        let mut file = shroom_wz::WzReader::open(file, WzRegion::GMS, version)?;
+    pub fn from_file(
+        filename: &str,
+        file: WzFile,
+        version: Option<WzVersion>,
+    ) -> anyhow::Result<Self> {
+        let mut file = match version {
+            Some(version) => shroom_wz::WzReader::open(file, WzRegion::GMS, version)?,
+            None => shroom_wz::WzReader::open_detect(file)?,
+        };
         let tree = WzTree::from_reader(&mut file, Some(filename))?;
         Ok(Self {
             tree,
@@ -126,7 +121,7 @@ impl WzData {
         // Safety: The cache holds the RC alive until
         // It's dropped from the cache
         // Since the cache is never dropped It means It lives as long as &self does
-        Ok(unsafe { std::mem::transmute(tree.as_ref()) })
+        Ok(unsafe { std::mem::transmute::<&WzValueTree, &WzValueTree>(tree.as_ref()) })
     }
 
     fn load_anim(&self, img: &WzImgHeader, anim: Animation) -> anyhow::Result<WzAnimationData> {
@@ -139,11 +134,12 @@ impl WzData {
     }
 
     fn load_canvas(&self, img: &WzImgHeader, canvas: &WzCanvas) -> anyhow::Result<RgbaImage> {
-        self.reader
+        Ok(self
+            .reader
             .borrow_mut()
             .img_reader(img)?
             .read_canvas(canvas)?
-            .to_rgba_image()
+            .to_rgba_image()?)
     }
 
     fn load_sound(&self, img: &WzImgHeader, sound: &WzSound) -> anyhow::Result<AudioData> {
@@ -154,7 +150,7 @@ impl WzData {
             .read_sound(sound)?;
 
         Ok(AudioData {
-            data,
+            data: sound.to_file_bytes(&data),
             format: sound.clone(),
         })
     }
@@ -168,7 +164,7 @@ pub enum WzContentData {
     None,
 }
 
-#[inline_props]
+#[component]
 fn WzContentView(cx: Scope, content: UseState<WzContentData>) -> Element {
     cx.render(match content.get() {
         WzContentData::Image(img) => rsx!(div {
@@ -206,7 +202,7 @@ fn WzContentView(cx: Scope, content: UseState<WzContentData>) -> Element {
     })
 }
 
-#[inline_props]
+#[component]
 fn WzImgView<'wz>(
     cx: Scope<'wz>,
     wz: &'wz WzData,
@@ -224,7 +220,7 @@ fn WzImgView<'wz>(
     })
 }
 
-#[inline_props]
+#[component]
 fn WzView<'wz>(cx: Scope<'wz>, wz: &'wz WzData) -> Element {
     let tree = wz.tree.get_tree();
 
@@ -232,18 +228,14 @@ fn WzView<'wz>(cx: Scope<'wz>, wz: &'wz WzData) -> Element {
     let content = use_state(cx, || WzContentData::None);
 
     let selected_img = use_memo(cx, (selected_img_node.get(),), move |(node,)| {
-        let Some(node) = node else {
-            return None;
-        };
+        let node = node?;
 
         let img_data = wz.tree.get_tree().get(&node).unwrap().data();
-        let img = match img_data {
-            WzDirNode::Img(img) => img,
-            WzDirNode::Link(link) => &link.link.link_img,
-            _ => return None,
-        };
-
-        Some(img.clone())
+        match img_data {
+            WzDirNode::Img(img) => Some(img.clone()),
+            WzDirNode::Link(link) => Some(link.img_header()),
+            _ => None,
+        }
     });
 
     let on_select_node = |(tree, node_id, node): (
@@ -254,9 +246,19 @@ fn WzView<'wz>(cx: Scope<'wz>, wz: &'wz WzData) -> Element {
         match node.value {
             WzValue::Canvas(canvas) => {
                 // Check if the parent is an object
-                if let Some(parent) = tree.ancestor_ids(&node_id).unwrap().next() {
-                    let parent = tree.get(parent).unwrap().data();
-                    if let Ok(anim) = Animation::from_obj_value(parent.value.as_object().unwrap()) {
+                let mut parents = tree
+                    .ancestors(&node_id)
+                    .unwrap()
+                    .map(|node| node.data())
+                    .collect::<Vec<_>>();
+                // Frames can be links, which are resolved from the image root
+                if let Some(root) = parents.pop() {
+                    let path = parents
+                        .iter()
+                        .rev()
+                        .map(|node| node.name)
+                        .collect::<Vec<_>>();
+                    if let Ok(anim) = Animation::from_path(root.value, &path.join("/")) {
                         let anim_data = wz.load_anim(selected_img.as_ref().unwrap(), anim).unwrap();
                         content.set(WzContentData::Animation(Rc::new(anim_data)));
                         return;
@@ -308,7 +310,7 @@ fn WzView<'wz>(cx: Scope<'wz>, wz: &'wz WzData) -> Element {
     })
 }
 
-#[inline_props]
+#[component]
 pub fn WzApp(cx: Scope, wz: UseState<Option<Rc<WzData>>>) -> Element {
     cx.render(rsx! {
         WzView {
@@ -1,8 +1,21 @@
-use std::vec;
+use std::{io::Write, vec};
 
+use binrw::PosValue;
 use image::{Rgba, RgbaImage};
 
-use crate::l1::canvas::{WzCanvas, WzCanvasDepth};
+use crate::{
+    crypto::WzCrypto,
+    error::{WzError, WzResult},
+    l1::{
+        canvas::{WzCanvas, WzCanvasDepth, WzCanvasScaling},
+        prop::WzProperty,
+    },
+    ty::WzInt,
+    util::WriteExt,
+};
+
+/// Size of the chunks of encrypted canvas data
+const CANVAS_CHUNK_SIZE: usize = 0x1000;
 
 const fn bit_pix<const N: u32>(v: u32, shift: u8) -> u8 {
     let mask: u32 = (1 << N) - 1;
@@ -28,12 +41,65 @@ fn bgr565_to_rgba8(v: u16) -> Rgba<u8> {
 }
 
 fn bgra8_to_rgba8(v: u32) -> Rgba<u8> {
-    v.to_le_bytes().into()
+    let [b, g, r, a] = v.to_le_bytes();
+    [r, g, b, a].into()
+}
+
+fn argb1555_to_rgba8(v: u16) -> Rgba<u8> {
+    let b = bit_pix::<5>(v as u32, 0);
+    let g = bit_pix::<5>(v as u32, 5);
+    let r = bit_pix::<5>(v as u32, 10);
+    let a = if v & 0x8000 != 0 { 0xff } else { 0 };
+
+    [r, g, b, a].into()
+}
+
+fn decode_pixels<T: bytemuck::Pod>(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    f: impl Fn(T) -> Rgba<u8>,
+) -> RgbaImage {
+    let n = size_of::<T>();
+    RgbaImage::from_fn(width, height, |x, y| {
+        let i = (x + y * width) as usize * n;
+        f(bytemuck::pod_read_unaligned(&data[i..i + n]))
+    })
+}
+
+fn decode_blocks(format: texpresso::Format, data: &[u8], width: u32, height: u32) -> RgbaImage {
+    let mut buf = vec![0u8; (width * height * 4) as usize];
+    format.decompress(data, width as usize, height as usize, &mut buf);
+    RgbaImage::from_raw(width, height, buf).expect("buffer must fit the image")
+}
+
+fn rgba8_to_bgra4(p: &Rgba<u8>) -> [u8; 2] {
+    let [r, g, b, a] = p.0.map(|c| c as u16 >> 4);
+    (b | g << 4 | r << 8 | a << 12).to_le_bytes()
+}
+
+fn rgba8_to_argb1555(p: &Rgba<u8>) -> [u8; 2] {
+    let [r, g, b, a] = p.0.map(|c| c as u16);
+    (b >> 3 | (g >> 3) << 5 | (r >> 3) << 10 | (a >> 7) << 15).to_le_bytes()
+}
+
+fn rgba8_to_bgr565(p: &Rgba<u8>) -> [u8; 2] {
+    let [r, g, b, _] = p.0.map(|c| c as u16);
+    (b >> 3 | (g >> 2) << 5 | (r >> 3) << 11).to_le_bytes()
+}
+
+fn encode_blocks(format: texpresso::Format, img: &RgbaImage) -> Vec<u8> {
+    let (w, h) = (img.width() as usize, img.height() as usize);
+    let mut buf = vec![0u8; format.compressed_size(w, h)];
+    format.compress(img.as_raw(), w, h, texpresso::Params::default(), &mut buf);
+    buf
 }
 
 pub struct Canvas {
     data: Vec<u8>,
     depth: WzCanvasDepth,
+    /// Factor the stored bitmap is scaled down by
+    scale: u32,
     pub width: u32,
     pub height: u32,
 }
@@ -43,62 +109,340 @@ impl Canvas {
         Self {
             data,
             depth: wz_canvas.depth,
+            scale: wz_canvas.scale_factor(),
             width: wz_canvas.width.0 as u32,
             height: wz_canvas.height.0 as u32,
         }
     }
 
-    pub fn to_rgba_image(&self) -> anyhow::Result<image::RgbaImage> {
-        match self.depth {
-            WzCanvasDepth::BGRA4444 => {
-                let data: &[u16] = bytemuck::cast_slice(&self.data);
-                Ok(RgbaImage::from_fn(self.width, self.height, |x, y| {
-                    bgra4_to_rgba8(data[(x + y * self.width) as usize])
-                }))
-            }
-            WzCanvasDepth::BGRA8888 => {
-                let data: &[u32] = bytemuck::cast_slice(&self.data);
-                Ok(RgbaImage::from_fn(self.width, self.height, |x, y| {
-                    bgra8_to_rgba8(data[(x + y * self.width) as usize])
-                }))
+    /// Dimensions of the stored bitmap
+    pub fn scaled_dim(&self) -> (u32, u32) {
+        (
+            self.width.div_ceil(self.scale),
+            self.height.div_ceil(self.scale),
+        )
+    }
+
+    pub fn to_rgba_image(&self) -> WzResult<image::RgbaImage> {
+        let (w, h) = self.scaled_dim();
+        if (self.data.len() as u64) < self.depth.data_size(w, h) {
+            return Err(WzError::Truncated {
+                pos: self.data.len() as u64,
+                path: None,
+            });
+        }
+
+        let img = match self.depth {
+            WzCanvasDepth::BGRA4444 | WzCanvasDepth::BGRA4444Block4 => {
+                decode_pixels(&self.data, w, h, bgra4_to_rgba8)
             }
-            WzCanvasDepth::BGR565 => {
-                let data: &[u16] = bytemuck::cast_slice(&self.data);
-                Ok(RgbaImage::from_fn(self.width, self.height, |x, y| {
-                    bgr565_to_rgba8(data[(x + y * self.width) as usize])
-                }))
+            WzCanvasDepth::BGRA8888 => decode_pixels(&self.data, w, h, bgra8_to_rgba8),
+            WzCanvasDepth::ARGB1555 => decode_pixels(&self.data, w, h, argb1555_to_rgba8),
+            WzCanvasDepth::BGR565 | WzCanvasDepth::BGR565Block16 => {
+                decode_pixels(&self.data, w, h, bgr565_to_rgba8)
             }
-            WzCanvasDepth::DXT3 => {
-                let mut buf = vec![0u8; (self.width * self.height * 4) as usize];
-                texpresso::Format::Bc3.decompress(
-                    &self.data,
-                    self.width as usize,
-                    self.height as usize,
-                    &mut buf,
-                );
-                Ok(RgbaImage::from_raw(self.width, self.height, buf).unwrap())
+            WzCanvasDepth::DXT3 => decode_blocks(texpresso::Format::Bc2, &self.data, w, h),
+            WzCanvasDepth::DXT5 => decode_blocks(texpresso::Format::Bc3, &self.data, w, h),
+        };
+
+        if self.scale == 1 {
+            return Ok(img);
+        }
+        // Upscale the stored bitmap back to the canvas size
+        Ok(RgbaImage::from_fn(self.width, self.height, |x, y| {
+            *img.get_pixel(x / self.scale, y / self.scale)
+        }))
+    }
+
+    /// Size of the stored bitmap
+    pub fn canvas_size(&self) -> u64 {
+        let (w, h) = self.scaled_dim();
+        self.depth.data_size(w, h)
+    }
+
+    /// Encodes the image with the depth,
+    /// it's stored scaled down by the factor of the scaling and the depth
+    pub fn from_rgba_image(img: &RgbaImage, depth: WzCanvasDepth, scale: WzCanvasScaling) -> Self {
+        let (width, height) = img.dimensions();
+        let factor = scale.get_factor().max(depth.block_factor());
+        let scaled = if factor == 1 {
+            img.clone()
+        } else {
+            RgbaImage::from_fn(width.div_ceil(factor), height.div_ceil(factor), |x, y| {
+                *img.get_pixel(x * factor, y * factor)
+            })
+        };
+
+        let data = match depth {
+            WzCanvasDepth::BGRA4444 | WzCanvasDepth::BGRA4444Block4 => {
+                scaled.pixels().flat_map(rgba8_to_bgra4).collect()
             }
-            WzCanvasDepth::DXT5 => {
-                let mut buf = vec![0u8; (self.width * self.height * 4) as usize];
-                texpresso::Format::Bc5.decompress(
-                    &self.data,
-                    self.width as usize,
-                    self.height as usize,
-                    &mut buf,
-                );
-                Ok(RgbaImage::from_raw(self.width, self.height, buf).unwrap())
+            WzCanvasDepth::BGRA8888 => scaled
+                .pixels()
+                .flat_map(|p| {
+                    let [r, g, b, a] = p.0;
+                    [b, g, r, a]
+                })
+                .collect(),
+            WzCanvasDepth::ARGB1555 => scaled.pixels().flat_map(rgba8_to_argb1555).collect(),
+            WzCanvasDepth::BGR565 | WzCanvasDepth::BGR565Block16 => {
+                scaled.pixels().flat_map(rgba8_to_bgr565).collect()
             }
+            WzCanvasDepth::DXT3 => encode_blocks(texpresso::Format::Bc2, &scaled),
+            WzCanvasDepth::DXT5 => encode_blocks(texpresso::Format::Bc3, &scaled),
+        };
+
+        Self {
+            data,
+            depth,
+            scale: factor,
+            width,
+            height,
         }
     }
 
-    pub fn canvas_size(&self) -> u32 {
-        self.height * self.width * self.depth.depth_size()
+    /// Stored bitmap, before compression
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn depth(&self) -> WzCanvasDepth {
+        self.depth
+    }
+
+    /// Compresses the bitmap into a canvas header and the payload, which has to
+    /// follow the header. With `crypto` the payload is encrypted in chunks
+    pub fn to_wz_canvas(
+        &self,
+        property: Option<WzProperty>,
+        crypto: Option<&WzCrypto>,
+    ) -> WzResult<(WzCanvas, Vec<u8>)> {
+        let mut compressed = Vec::new();
+        compressed.compress_flate(&self.data)?;
+
+        // Unknown byte in front of the data
+        let mut payload = vec![0];
+        match crypto {
+            Some(crypto) => {
+                payload.write_chunked_data(crypto, compressed.chunks_mut(CANVAS_CHUNK_SIZE))?;
+            }
+            None => payload.write_all(&compressed)?,
+        }
+
+        let canvas = WzCanvas {
+            unknown: 0,
+            has_property: property.is_some() as u8,
+            property,
+            width: WzInt(self.width as i32),
+            height: WzInt(self.height as i32),
+            depth: self.depth,
+            // Block depths imply their scale
+            scale: WzCanvasScaling(if self.scale == self.depth.block_factor() {
+                0
+            } else {
+                self.scale.trailing_zeros() as u8
+            }),
+            unknown1: 0,
+            len: PosValue {
+                val: payload.len() as u32,
+                pos: 0,
+            },
+        };
+        Ok((canvas, payload))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::canvas::bit_pix;
+    use std::io::{Cursor, Write};
+
+    use binrw::{BinWrite, PosValue};
+    use image::{Rgba, RgbaImage};
+
+    use crate::{
+        canvas::bit_pix,
+        crypto::WzCrypto,
+        l1::{
+            canvas::{WzCanvas, WzCanvasDepth, WzCanvasScaling},
+            obj::WzObject,
+            WzUOLStr,
+        },
+        ty::{WzInt, WzStr},
+        util::{WzContext, WzStrTable},
+        version::{WzRegion, WzVersion},
+        WzReader,
+    };
+
+    use super::Canvas;
+
+    fn canvas(width: i32, height: i32, depth: WzCanvasDepth, scale: u8, data: Vec<u8>) -> Canvas {
+        let canvas = WzCanvas {
+            unknown: 0,
+            has_property: 0,
+            property: None,
+            width: WzInt(width),
+            height: WzInt(height),
+            depth,
+            scale: WzCanvasScaling(scale),
+            unknown1: 0,
+            len: PosValue { val: 0, pos: 0 },
+        };
+        assert_eq!(canvas.scaled_bitmap_size(), data.len() as u64);
+        Canvas::from_data(data, &canvas)
+    }
+
+    #[test]
+    fn pixel_formats() {
+        let img = canvas(1, 1, WzCanvasDepth::BGRA8888, 0, vec![1, 2, 3, 4]);
+        assert_eq!(img.to_rgba_image().unwrap().into_raw(), [3, 2, 1, 4]);
+
+        let img = canvas(1, 1, WzCanvasDepth::BGRA4444, 0, vec![0x21, 0x43]);
+        assert_eq!(img.to_rgba_image().unwrap().into_raw(), [48, 32, 16, 64]);
+
+        // Red with and without alpha
+        let img = canvas(
+            2,
+            1,
+            WzCanvasDepth::ARGB1555,
+            0,
+            vec![0x00, 0xFC, 0x00, 0x7C],
+        );
+        assert_eq!(
+            img.to_rgba_image().unwrap().into_raw(),
+            [248, 0, 0, 255, 248, 0, 0, 0]
+        );
+
+        let img = canvas(1, 1, WzCanvasDepth::BGR565, 0, vec![0x1F, 0x00]);
+        assert_eq!(img.to_rgba_image().unwrap().into_raw(), [0, 0, 248, 255]);
+
+        // Truncated data
+        let img = canvas(1, 1, WzCanvasDepth::BGR565, 0, vec![0x1F, 0x00]);
+        let img = Canvas {
+            data: vec![0],
+            ..img
+        };
+        assert!(img.to_rgba_image().is_err());
+    }
+
+    #[test]
+    fn block_formats() {
+        let color = Rgba([0xFF, 0x00, 0x00, 0xFF]);
+        let src = RgbaImage::from_pixel(6, 5, color);
+        for (depth, format) in [
+            (WzCanvasDepth::DXT3, texpresso::Format::Bc2),
+            (WzCanvasDepth::DXT5, texpresso::Format::Bc3),
+        ] {
+            let mut data = vec![0; format.compressed_size(6, 5)];
+            format.compress(src.as_raw(), 6, 5, texpresso::Params::default(), &mut data);
+            let img = canvas(6, 5, depth, 0, data).to_rgba_image().unwrap();
+            assert_eq!(img, src);
+        }
+    }
+
+    fn read_back(img: &RgbaImage, depth: WzCanvasDepth, scale: u8, encrypt: bool) -> RgbaImage {
+        let crypto = WzCrypto::from_region(WzRegion::GMS, WzVersion(95), 0);
+        let str_table = WzStrTable::default();
+        let ctx = WzContext::new(&crypto, &str_table);
+        let canvas = Canvas::from_rgba_image(img, depth, WzCanvasScaling(scale));
+        let (hdr, payload) = canvas
+            .to_wz_canvas(None, encrypt.then_some(&crypto))
+            .unwrap();
+
+        // Image with the canvas as root
+        let mut w = Cursor::new(Vec::new());
+        WzUOLStr::StrTypeName(WzStr::new("Canvas".to_string()))
+            .write_le_args(&mut w, ctx)
+            .unwrap();
+        WzObject::Canvas(hdr).write_le_args(&mut w, ctx).unwrap();
+        w.write_all(&payload).unwrap();
+        w.set_position(0);
+
+        let mut r = WzReader::open_img(w, WzRegion::GMS, WzVersion(95)).unwrap();
+        let mut img = r.root_img_reader().unwrap();
+        let WzObject::Canvas(hdr) = img.read_root_obj().unwrap() else {
+            panic!("must be a canvas");
+        };
+        img.read_canvas(&hdr).unwrap().to_rgba_image().unwrap()
+    }
+
+    #[test]
+    fn encode() {
+        // 4x4 blocks with a single color, so block compression is lossless
+        let colors = [
+            Rgba([0xFF, 0x00, 0x00, 0xFF]),
+            Rgba([0x00, 0xFF, 0x00, 0xFF]),
+            Rgba([0x00, 0x00, 0xFF, 0xFF]),
+            Rgba([0xFF, 0xFF, 0xFF, 0x00]),
+        ];
+        let src = RgbaImage::from_fn(8, 8, |x, y| colors[(x / 4 + y / 4 * 2) as usize]);
+
+        for depth in [
+            WzCanvasDepth::BGRA4444,
+            WzCanvasDepth::BGRA8888,
+            WzCanvasDepth::ARGB1555,
+            WzCanvasDepth::BGR565,
+            WzCanvasDepth::DXT3,
+            WzCanvasDepth::DXT5,
+        ] {
+            for encrypt in [false, true] {
+                let img = read_back(&src, depth, 0, encrypt);
+                assert_eq!(img.dimensions(), (8, 8));
+                for (a, b) in img.pixels().zip(src.pixels()) {
+                    let channels = if depth == WzCanvasDepth::BGR565 { 3 } else { 4 };
+                    for c in 0..channels {
+                        assert!(a[c].abs_diff(b[c]) <= 16, "{depth:?}: {a:?} != {b:?}");
+                    }
+                }
+            }
+        }
+
+        // Stored as one pixel per 4x4 block
+        let img = read_back(&src, WzCanvasDepth::BGRA8888, 2, true);
+        assert_eq!(img, src);
+        let img = read_back(&src, WzCanvasDepth::BGRA4444Block4, 0, false);
+        assert_eq!(img.dimensions(), (8, 8));
+        assert_eq!(*img.get_pixel(7, 7), Rgba([0xF0, 0xF0, 0xF0, 0]));
+    }
+
+    #[test]
+    fn scaled_formats() {
+        // One BGRA4444 color per 4x4 block
+        let img = canvas(
+            5,
+            5,
+            WzCanvasDepth::BGRA4444Block4,
+            0,
+            vec![0x0F, 0xF0, 0xF0, 0xF0, 0x00, 0xFF, 0x0F, 0xF0],
+        )
+        .to_rgba_image()
+        .unwrap();
+        assert_eq!(img.dimensions(), (5, 5));
+        assert_eq!(*img.get_pixel(3, 3), Rgba([0, 0, 240, 240]));
+        assert_eq!(*img.get_pixel(4, 0), Rgba([0, 240, 0, 240]));
+        assert_eq!(*img.get_pixel(0, 4), Rgba([240, 0, 0, 240]));
+        assert_eq!(*img.get_pixel(4, 4), Rgba([0, 0, 240, 240]));
+
+        // One BGR565 color per 16x16 block
+        let img = canvas(17, 16, WzCanvasDepth::BGR565Block16, 0, vec![0x1F, 0, 0, 0])
+            .to_rgba_image()
+            .unwrap();
+        assert_eq!(img.dimensions(), (17, 16));
+        assert_eq!(*img.get_pixel(15, 15), Rgba([0, 0, 248, 255]));
+        assert_eq!(*img.get_pixel(16, 0), Rgba([0, 0, 0, 255]));
+
+        // Canvas scaling works with any depth
+        let img = canvas(
+            32,
+            16,
+            WzCanvasDepth::BGRA8888,
+            4,
+            vec![1, 2, 3, 4, 5, 6, 7, 8],
+        )
+        .to_rgba_image()
+        .unwrap();
+        assert_eq!(img.dimensions(), (32, 16));
+        assert_eq!(*img.get_pixel(31, 15), Rgba([7, 6, 5, 8]));
+    }
 
     #[test]
     fn bit_pix_() {
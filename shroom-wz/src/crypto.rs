@@ -62,7 +62,7 @@ impl WzCrypto {
     }
 
     fn fill_key<const N: usize>(&self, key: &mut [u8; N]) {
-        assert!(N % WZ_IV_LEN == 0);
+        assert!(N.is_multiple_of(WZ_IV_LEN));
         let mut cur_key = self.iv;
 
         let chunks = as_chunks_mut::<N, WZ_IV_LEN>(key);
@@ -0,0 +1,374 @@
+use std::{fmt, io};
+
+use crate::version::WzVersion;
+
+pub type WzResult<T> = Result<T, WzError>;
+
+/// Formats the optional property path of an error
+struct PathSuffix<'a>(&'a Option<String>);
+
+impl fmt::Display for PathSuffix<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(path) => write!(f, " in {path}"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Errors while reading or writing archives and images
+///
+/// Positions are relative to the stream which was read, so for images
+/// they are relative to the start of the image. The path is the property
+/// path inside the image, if it's known.
+#[derive(Debug, thiserror::Error)]
+pub enum WzError {
+    #[error("wrong version {found} at {pos}, expected: {expected:?}")]
+    WrongVersion {
+        pos: u64,
+        found: u16,
+        expected: Option<WzVersion>,
+    },
+    #[error("bad magic {found} at {pos}{}", PathSuffix(.path))]
+    BadMagic {
+        pos: u64,
+        found: String,
+        path: Option<String>,
+    },
+    #[error("bad offset {offset} at {pos}{}", PathSuffix(.path))]
+    BadOffset {
+        pos: u64,
+        offset: u64,
+        path: Option<String>,
+    },
+    #[error("invalid string encoding at {pos}{}", PathSuffix(.path))]
+    InvalidString { pos: u64, path: Option<String> },
+    #[error("unknown object type {ty} at {pos}{}", PathSuffix(.path))]
+    UnknownObject {
+        pos: u64,
+        ty: String,
+        path: Option<String>,
+    },
+    #[error("unsupported canvas depth {depth} at {pos}{}", PathSuffix(.path))]
+    UnsupportedDepth {
+        pos: u64,
+        depth: i32,
+        path: Option<String>,
+    },
+    #[error("truncated data at {pos}{}", PathSuffix(.path))]
+    Truncated { pos: u64, path: Option<String> },
+    #[error("malformed data at {pos}{}: {msg}", PathSuffix(.path))]
+    Malformed {
+        pos: u64,
+        msg: String,
+        path: Option<String>,
+    },
+    #[error("unexpected value{}, expected: {expected}", PathSuffix(.path))]
+    UnexpectedValue {
+        expected: &'static str,
+        path: Option<String>,
+    },
+    #[error("invalid link {link}{}: {msg}", PathSuffix(.path))]
+    InvalidLink {
+        link: String,
+        msg: &'static str,
+        path: Option<String>,
+    },
+    #[error("{path} not found")]
+    NotFound { path: String },
+    #[error("can't deserialize value{}: {msg}", PathSuffix(.path))]
+    Deserialize { msg: String, path: Option<String> },
+    #[error("invalid file name {name:?}{}", PathSuffix(.path))]
+    InvalidName { name: String, path: Option<String> },
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+}
+
+impl WzError {
+    pub fn malformed(pos: u64, msg: impl Into<String>) -> Self {
+        Self::Malformed {
+            pos,
+            msg: msg.into(),
+            path: None,
+        }
+    }
+
+    /// Unexpected eof is reported as truncated data at `pos`
+    pub fn io_at(err: io::Error, pos: u64) -> Self {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => Self::Truncated { pos, path: None },
+            _ => Self::Io(err),
+        }
+    }
+
+    /// Converts a binrw error, `pos` is used for io errors which carry no position
+    pub fn from_binrw(err: binrw::Error, pos: u64) -> Self {
+        match err {
+            binrw::Error::BadMagic { pos, found } => Self::BadMagic {
+                pos,
+                found: format!("{found:?}"),
+                path: None,
+            },
+            binrw::Error::AssertFail { pos, message } => Self::malformed(pos, message),
+            binrw::Error::Io(err) => Self::io_at(err, pos),
+            binrw::Error::Custom { pos, err } => match err.downcast::<WzError>() {
+                Ok(err) => err.with_pos(pos),
+                Err(err) => Self::malformed(pos, err.to_string()),
+            },
+            binrw::Error::NoVariantMatch { pos } => Self::UnknownObject {
+                pos,
+                ty: "unknown tag".to_string(),
+                path: None,
+            },
+            // Enums are tagged by magic, so only report the error of the matching variant
+            binrw::Error::EnumErrors {
+                pos,
+                variant_errors,
+            } => variant_errors
+                .into_iter()
+                .map(|(_, err)| err)
+                .find(|err| !matches!(err.root_cause(), binrw::Error::BadMagic { .. }))
+                .map(|err| Self::from_binrw(err, pos))
+                .unwrap_or_else(|| Self::UnknownObject {
+                    pos,
+                    ty: "unknown tag".to_string(),
+                    path: None,
+                }),
+            binrw::Error::Backtrace(bt) => Self::from_binrw(*bt.error, pos),
+            err => Self::malformed(pos, err.to_string()),
+        }
+    }
+
+    /// Wraps the error, so it can be returned from binrw implementations
+    pub fn into_binrw(self) -> binrw::Error {
+        binrw::Error::Custom {
+            pos: self.pos().unwrap_or(0),
+            err: Box::new(self),
+        }
+    }
+
+    /// Byte position of the error, if it's known
+    pub fn pos(&self) -> Option<u64> {
+        match self {
+            Self::WrongVersion { pos, .. }
+            | Self::BadMagic { pos, .. }
+            | Self::BadOffset { pos, .. }
+            | Self::InvalidString { pos, .. }
+            | Self::UnknownObject { pos, .. }
+            | Self::UnsupportedDepth { pos, .. }
+            | Self::Truncated { pos, .. }
+            | Self::Malformed { pos, .. } => Some(*pos),
+            Self::UnexpectedValue { .. }
+            | Self::InvalidLink { .. }
+            | Self::NotFound { .. }
+            | Self::Deserialize { .. }
+            | Self::InvalidName { .. }
+            | Self::Io(_) => None,
+        }
+    }
+
+    /// Property path of the error, if it's known
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            Self::BadMagic { path, .. }
+            | Self::BadOffset { path, .. }
+            | Self::InvalidString { path, .. }
+            | Self::UnknownObject { path, .. }
+            | Self::UnsupportedDepth { path, .. }
+            | Self::Truncated { path, .. }
+            | Self::Malformed { path, .. }
+            | Self::UnexpectedValue { path, .. }
+            | Self::InvalidLink { path, .. }
+            | Self::Deserialize { path, .. }
+            | Self::InvalidName { path, .. } => path.as_deref(),
+            Self::NotFound { path } => Some(path),
+            Self::WrongVersion { .. } | Self::Io(_) => None,
+        }
+    }
+
+    fn path_mut(&mut self) -> Option<&mut Option<String>> {
+        match self {
+            Self::BadMagic { path, .. }
+            | Self::BadOffset { path, .. }
+            | Self::InvalidString { path, .. }
+            | Self::UnknownObject { path, .. }
+            | Self::UnsupportedDepth { path, .. }
+            | Self::Truncated { path, .. }
+            | Self::Malformed { path, .. }
+            | Self::UnexpectedValue { path, .. }
+            | Self::InvalidLink { path, .. }
+            | Self::Deserialize { path, .. }
+            | Self::InvalidName { path, .. } => Some(path),
+            Self::NotFound { .. } | Self::WrongVersion { .. } | Self::Io(_) => None,
+        }
+    }
+
+    fn with_pos(mut self, new_pos: u64) -> Self {
+        match &mut self {
+            Self::WrongVersion { pos, .. }
+            | Self::BadMagic { pos, .. }
+            | Self::BadOffset { pos, .. }
+            | Self::InvalidString { pos, .. }
+            | Self::UnknownObject { pos, .. }
+            | Self::UnsupportedDepth { pos, .. }
+            | Self::Truncated { pos, .. }
+            | Self::Malformed { pos, .. } => *pos = new_pos,
+            Self::UnexpectedValue { .. }
+            | Self::InvalidLink { .. }
+            | Self::NotFound { .. }
+            | Self::Deserialize { .. }
+            | Self::InvalidName { .. }
+            | Self::Io(_) => {}
+        }
+        self
+    }
+
+    /// Prepends the name of the parent property to the path,
+    /// called while the error propagates up to the root
+    pub fn in_parent(mut self, parent: &str) -> Self {
+        if let Some(path) = self.path_mut() {
+            *path = Some(match path.take() {
+                Some(path) => format!("{parent}/{path}"),
+                None => parent.to_string(),
+            });
+        }
+        self
+    }
+}
+
+impl serde::de::Error for WzError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self::Deserialize {
+            msg: msg.to_string(),
+            path: None,
+        }
+    }
+}
+
+impl From<binrw::Error> for WzError {
+    fn from(err: binrw::Error) -> Self {
+        Self::from_binrw(err, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor};
+
+    use binrw::{BinWrite, PosValue};
+
+    use crate::{
+        crypto::WzCrypto,
+        l0::WzDirNode,
+        l1::{
+            prop::{WzObj, WzPropValue, WzProperty, WzPropertyEntry},
+            WzUOLStr,
+        },
+        link::tests::canvas,
+        ty::{WzInt, WzStr, WzVec},
+        util::{WzContext, WzStrTable},
+        val::WzValue,
+        version::{WzRegion, WzVersion},
+        writer::{tests::int_img, WzWriterDir},
+        WzReader, WzWriter,
+    };
+
+    use super::WzError;
+
+    #[test]
+    fn path_and_pos() {
+        let err = WzError::io_at(io::ErrorKind::UnexpectedEof.into(), 12)
+            .in_parent("stand")
+            .in_parent("0100100.img");
+        assert!(matches!(err, WzError::Truncated { .. }));
+        assert_eq!(err.pos(), Some(12));
+        assert_eq!(err.path(), Some("0100100.img/stand"));
+        assert_eq!(err.to_string(), "truncated data at 12 in 0100100.img/stand");
+
+        let err = WzError::from_binrw(WzError::malformed(1, "test").into_binrw(), 0);
+        assert_eq!(err.pos(), Some(1));
+    }
+
+    #[test]
+    fn corrupt() {
+        let region = WzRegion::GMS;
+        let ver = WzVersion(95);
+        let mut root = WzWriterDir::new("Root");
+        root.add_img("Base.img", int_img(region, 2));
+        let mut w = Cursor::new(Vec::new());
+        WzWriter::new(region, ver).write(&mut w, &root).unwrap();
+
+        // Image exceeds the truncated file
+        let mut data = w.into_inner();
+        data.truncate(data.len() - 2);
+        let mut r = WzReader::open(Cursor::new(data), region, ver).unwrap();
+        let root = r.read_root_dir().unwrap();
+        let WzDirNode::Img(img) = &root.entries.0[0] else {
+            panic!("Expected img");
+        };
+        assert!(matches!(r.img_reader(img), Err(WzError::BadOffset { .. })));
+
+        // Truncated image
+        let mut data = int_img(region, 1);
+        data.truncate(data.len() - 1);
+        let mut img = WzReader::open_img(Cursor::new(data), region, ver).unwrap();
+        let err = WzValue::read(&mut img.root_img_reader().unwrap()).unwrap_err();
+        assert!(matches!(err, WzError::Truncated { .. }), "{err:?}");
+
+        // Unknown object type in a sub property
+        let crypto = WzCrypto::from_region(region, ver, 0);
+        let str_table = WzStrTable::default();
+        let ctx = WzContext::new(&crypto, &str_table);
+        let mut obj = Cursor::new(Vec::new());
+        WzUOLStr::StrTypeName(WzStr::new("Unknown".to_string()))
+            .write_le_args(&mut obj, ctx)
+            .unwrap();
+        let obj = obj.into_inner();
+
+        let mut w = Cursor::new(Vec::new());
+        WzUOLStr::StrTypeName(WzStr::new("Property".to_string()))
+            .write_le_args(&mut w, ctx)
+            .unwrap();
+        WzProperty {
+            unknown: 0,
+            entries: WzVec(vec![WzPropertyEntry {
+                name: WzUOLStr::Str(WzStr::new("stand".to_string())),
+                val: WzPropValue::Obj(WzObj {
+                    len: PosValue {
+                        val: obj.len() as u32,
+                        pos: 0,
+                    },
+                }),
+            }]),
+        }
+        .write_le_args(&mut w, ctx)
+        .unwrap();
+        w.get_mut().extend_from_slice(&obj);
+
+        let mut img = WzReader::open_img(w, region, ver).unwrap();
+        let err = WzValue::read(&mut img.root_img_reader().unwrap()).unwrap_err();
+        let WzError::UnknownObject { ty, .. } = &err else {
+            panic!("Expected unknown object: {err:?}");
+        };
+        assert_eq!(ty, "Unknown");
+        assert_eq!(err.path(), Some("stand"));
+        let mut img = img.root_img_reader().unwrap();
+        let root = img.read_root_obj().unwrap();
+        let err = img.read_path(&root, "stand/0").unwrap_err();
+        assert_eq!(err.path(), Some("stand"));
+
+        // Canvas size way beyond what the data decompresses to
+        let data = canvas(ctx, [1, 2, 3, 4], &[]);
+        let mut img = WzReader::open_img(Cursor::new(data), region, ver).unwrap();
+        let mut img = img.root_img_reader().unwrap();
+        let WzValue::Canvas(mut huge) = WzValue::read(&mut img).unwrap() else {
+            panic!("Expected canvas");
+        };
+        huge.canvas.width = WzInt(32768);
+        huge.canvas.height = WzInt(32767);
+        let Err(err) = huge.read_canvas(&mut img) else {
+            panic!("Expected an error");
+        };
+        assert!(matches!(err, WzError::Truncated { .. }), "{err:?}");
+    }
+}
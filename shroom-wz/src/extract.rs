@@ -0,0 +1,295 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use rayon::prelude::*;
+
+use crate::{
+    error::WzResult,
+    file::{WzIO, WzImgReader},
+    util::{append_ext, file_name, join_path, BoundedReader},
+    val::WzValue,
+    WzReader,
+};
+
+/// Result of a single image, so one broken image doesn't stop the extraction
+#[derive(Debug)]
+pub struct ImgResult<T> {
+    /// Path of the image within the archive like `Mob/0100100.img`
+    pub path: String,
+    pub result: WzResult<T>,
+}
+
+fn img_path(path: &str) -> &str {
+    path.strip_prefix("/root/").unwrap_or(path)
+}
+
+/// Decodes every image of the archive with `f` on the rayon thread pool,
+/// use `ThreadPool::install` to run it on a custom pool
+///
+/// Directories which can't be read are reported as failed results with the
+/// path of the directory, the remaining images are still decoded.
+pub fn par_extract<R, T, F>(r: &mut WzReader<R>, f: F) -> WzResult<Vec<ImgResult<T>>>
+where
+    R: WzIO + Clone + Send + Sync,
+    T: Send,
+    F: Fn(&str, WzImgReader<BoundedReader<R>>) -> WzResult<T> + Sync,
+{
+    let mut imgs = Vec::new();
+    let mut failed = Vec::new();
+    for img in r.traverse_images() {
+        match img {
+            Ok(img) => imgs.push(img),
+            Err(err) => failed.push(ImgResult {
+                path: err.path().map(img_path).unwrap_or_default().to_string(),
+                result: Err(err),
+            }),
+        }
+    }
+
+    let r = &*r;
+    let mut results: Vec<_> = imgs
+        .into_par_iter()
+        .map(|(path, hdr)| {
+            let path = img_path(&path).to_string();
+            let result = r
+                .img_reader_owned_with_path(&path, &hdr)
+                .and_then(|img| f(&path, img));
+            ImgResult { path, result }
+        })
+        .collect();
+    results.extend(failed);
+    Ok(results)
+}
+
+/// Reads every image into a `WzValue`
+pub fn par_read_values<R>(r: &mut WzReader<R>) -> WzResult<Vec<ImgResult<WzValue>>>
+where
+    R: WzIO + Clone + Send + Sync,
+{
+    par_extract(r, |_, mut img| WzValue::read(&mut img))
+}
+
+/// Writes every image as `<out_dir>/<path>.json`,
+/// images with names which would leave `out_dir` fail
+pub fn par_export_json<R>(
+    r: &mut WzReader<R>,
+    out_dir: impl AsRef<Path>,
+    skip_canvas: bool,
+) -> WzResult<Vec<ImgResult<PathBuf>>>
+where
+    R: WzIO + Clone + Send + Sync,
+{
+    let out_dir = out_dir.as_ref();
+    par_extract(r, |path, img| {
+        let out = append_ext(&join_path(out_dir, path)?, "json");
+        if let Some(parent) = out.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let ser = img.into_serializer(skip_canvas)?;
+        let mut w = BufWriter::new(File::create(&out)?);
+        serde_json::to_writer(&mut w, &ser).map_err(io::Error::from)?;
+        w.flush()?;
+        Ok(out)
+    })
+}
+
+/// Writes every canvas as `<out_dir>/<path>/<property path>.png`,
+/// images with names which would leave `out_dir` fail,
+/// the result is the number of written canvases per image
+pub fn par_export_png<R>(
+    r: &mut WzReader<R>,
+    out_dir: impl AsRef<Path>,
+) -> WzResult<Vec<ImgResult<usize>>>
+where
+    R: WzIO + Clone + Send + Sync,
+{
+    let out_dir = out_dir.as_ref();
+    par_extract(r, |path, mut img| {
+        let val = WzValue::read(&mut img)?;
+        export_canvases(&mut img, &val, &join_path(out_dir, path)?)
+    })
+}
+
+fn export_canvases<R: WzIO>(
+    img: &mut WzImgReader<R>,
+    val: &WzValue,
+    path: &Path,
+) -> WzResult<usize> {
+    let (obj, canvas) = match val {
+        WzValue::Object(obj) => (Some(obj), None),
+        WzValue::Canvas(canvas) => (
+            canvas.sub.as_deref().and_then(WzValue::as_object),
+            Some(canvas),
+        ),
+        _ => return Ok(0),
+    };
+
+    let mut n = 0;
+    if let Some(canvas) = canvas {
+        let image = canvas.read_canvas(img)?.to_rgba_image()?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        image
+            .save(append_ext(path, "png"))
+            .map_err(io::Error::other)?;
+        n += 1;
+    }
+
+    for (k, v) in obj.iter().flat_map(|obj| obj.0.iter()) {
+        n += file_name(k)
+            .and_then(|name| export_canvases(img, v, &path.join(name)))
+            .map_err(|err| err.in_parent(k))?;
+    }
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::{
+        crypto::WzCrypto,
+        error::WzError,
+        l0::WzDirNode,
+        link::tests::{canvas, img},
+        util::{WzContext, WzStrTable},
+        version::{WzRegion, WzVersion},
+        writer::{tests::int_img, WzWriterDir},
+        WzReader, WzReaderShared, WzWriter,
+    };
+
+    #[test]
+    fn extract_values() {
+        let region = WzRegion::GMS;
+        let mut root = WzWriterDir::new("Root");
+        let mut mob = WzWriterDir::new("Mob");
+        for i in 0..32 {
+            mob.add_img(format!("{i}.img"), int_img(region, i));
+        }
+        root.add_dir(mob);
+        // Broken image
+        root.add_img("Broken.img", vec![0xFF; 4]);
+
+        let mut w = Cursor::new(Vec::new());
+        WzWriter::new(region, WzVersion(95))
+            .write(&mut w, &root)
+            .unwrap();
+        let mut r = WzReaderShared::open_shared(w.into_inner(), region, WzVersion(95)).unwrap();
+
+        let vals = super::par_read_values(&mut r).unwrap();
+        assert_eq!(vals.len(), 33);
+        for img in vals.iter() {
+            if img.path == "Broken.img" {
+                assert!(img.result.is_err());
+                continue;
+            }
+            let val = img.result.as_ref().unwrap();
+            let level = val.get_path("level").unwrap().as_i32().unwrap();
+            assert_eq!(img.path, format!("Mob/{level}.img"));
+        }
+
+        let out_dir =
+            std::env::temp_dir().join(format!("shroom-wz-extract-{}", std::process::id()));
+        let files = super::par_export_json(&mut r, &out_dir, true).unwrap();
+        let json = std::fs::read_to_string(out_dir.join("Mob/3.img.json")).unwrap();
+        assert_eq!(json, r#"{"level":3}"#);
+        assert_eq!(files.iter().filter(|img| img.result.is_err()).count(), 1);
+
+        let pngs = super::par_export_png(&mut r, &out_dir).unwrap();
+        assert!(pngs
+            .iter()
+            .filter_map(|img| img.result.as_ref().ok())
+            .all(|n| *n == 0));
+        std::fs::remove_dir_all(out_dir).unwrap();
+    }
+
+    #[test]
+    fn broken_dir() {
+        let region = WzRegion::GMS;
+        let ver = WzVersion(95);
+        let mut root = WzWriterDir::new("Root");
+        let mut mob = WzWriterDir::new("Mob");
+        mob.add_img("0.img", int_img(region, 0));
+        root.add_dir(mob);
+        let mut npc = WzWriterDir::new("Npc");
+        npc.add_img("0.img", int_img(region, 1));
+        root.add_dir(npc);
+        let mut w = Cursor::new(Vec::new());
+        WzWriter::new(region, ver).write(&mut w, &root).unwrap();
+
+        // The entry count of Npc exceeds the entries
+        let mut data = w.into_inner();
+        let mut r = WzReader::open(Cursor::new(data.clone()), region, ver).unwrap();
+        let dir = r.read_root_dir().unwrap().entries.0[1].clone();
+        let WzDirNode::Dir(npc) = dir else {
+            panic!("Expected dir");
+        };
+        data[npc.offset.0 as usize] = 0x7F;
+
+        let mut r = WzReaderShared::open_shared(data, region, ver).unwrap();
+        let vals = super::par_read_values(&mut r).unwrap();
+        assert_eq!(vals.len(), 2);
+        assert_eq!(vals[0].path, "Mob/0.img");
+        assert!(vals[0].result.is_ok());
+        assert_eq!(vals[1].path, "Npc");
+        assert!(vals[1].result.is_err());
+    }
+
+    #[test]
+    fn export_png() {
+        let region = WzRegion::GMS;
+        let crypto = WzCrypto::from_region(region, WzVersion(95), 0);
+        let str_table = WzStrTable::default();
+        let ctx = WzContext::new(&crypto, &str_table);
+
+        let mut root = WzWriterDir::new("Root");
+        root.add_img(
+            "Canvas.img",
+            img(
+                ctx,
+                &[
+                    ("a.1", canvas(ctx, [1, 2, 3, 255], &[])),
+                    ("a.2", canvas(ctx, [4, 5, 6, 255], &[])),
+                ],
+            ),
+        );
+        root.add_img("Evil.img", img(ctx, &[("..", canvas(ctx, [0; 4], &[]))]));
+        let mut evil = WzWriterDir::new("..");
+        evil.add_img("Evil.img", int_img(region, 1));
+        root.add_dir(evil);
+
+        let mut w = Cursor::new(Vec::new());
+        WzWriter::new(region, WzVersion(95))
+            .write(&mut w, &root)
+            .unwrap();
+        let mut r = WzReaderShared::open_shared(w.into_inner(), region, WzVersion(95)).unwrap();
+
+        let out_dir = std::env::temp_dir().join(format!("shroom-wz-png-{}", std::process::id()));
+        let mut pngs = super::par_export_png(&mut r, &out_dir).unwrap();
+        pngs.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(pngs[0].path, "../Evil.img");
+        assert!(matches!(
+            pngs[0].result,
+            Err(WzError::InvalidName { ref name, .. }) if name == ".."
+        ));
+        assert_eq!(pngs[1].result.as_ref().unwrap(), &2);
+        assert_eq!(pngs[2].result.as_ref().unwrap_err().path(), Some(".."));
+
+        // Siblings which only differ after the last dot don't overwrite each other
+        let a1 = image::open(out_dir.join("Canvas.img/a.1.png")).unwrap();
+        let a2 = image::open(out_dir.join("Canvas.img/a.2.png")).unwrap();
+        assert_eq!(a1.to_rgba8().get_pixel(0, 0).0, [3, 2, 1, 255]);
+        assert_eq!(a2.to_rgba8().get_pixel(0, 0).0, [6, 5, 4, 255]);
+        assert!(!out_dir.join("Canvas.img/a.png").exists());
+
+        let json = super::par_export_json(&mut r, &out_dir, true).unwrap();
+        assert_eq!(json.iter().filter(|img| img.result.is_err()).count(), 1);
+        assert!(!out_dir.join("../Evil.img.json").exists());
+        std::fs::remove_dir_all(out_dir).unwrap();
+    }
+}
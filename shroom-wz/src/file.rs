@@ -1,9 +1,9 @@
 use std::{
-    collections::VecDeque,
+    borrow::Cow,
+    collections::{HashSet, VecDeque},
     fs::File,
     io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom},
     path::Path,
-    rc::Rc,
     sync::Arc,
 };
 
@@ -12,25 +12,39 @@ use binrw::{BinRead, PosValue};
 use crate::{
     canvas::Canvas,
     crypto::WzCrypto,
-    l0::{WzDir, WzDirHeader, WzDirNode, WzHeader, WzImgHeader, WzLinkData},
+    error::{WzError, WzResult},
+    l0::{WzDir, WzDirHeader, WzDirNode, WzHeader, WzImgHeader},
     l1::{
-        canvas::WzCanvas,
+        canvas::{WzCanvas, WzCanvasLink},
         obj::WzObject,
         prop::{WzObj, WzPropValue},
         ser::WzImgSerializer,
         sound::WzSound,
     },
-    ty::{WzInt, WzOffset},
-    util::{BufReadExt, SubReader, WzContext, WzStrTable},
+    link::{WzLinkedCanvas, WzOutlinkResolver},
+    list::WzList,
+    ty::WzOffset,
+    util::{read_vec, BoundedReader, BufReadExt, SharedData, SubReader, WzContext, WzStrTable},
+    val::WzValue,
     version::{WzRegion, WzVersion},
+    writer::WzImgWriter,
 };
+/// Encrypted version which is also a valid start of a root dir
+/// with more than 127 entries in archives without version
+const WZ_AMBIGUOUS_VERSION: u16 = 0x80;
+
+/// Limit for chains of canvas links
+const MAX_CANVAS_LINKS: usize = 16;
+
 pub trait WzIO: BufRead + Seek {}
 impl<T> WzIO for T where T: BufRead + Seek {}
 
 pub struct WzImgReader<R> {
     r: R,
-    crypto: Rc<WzCrypto>,
+    crypto: Arc<WzCrypto>,
     str_table: WzStrTable,
+    /// Whether canvas data is chunked and encrypted, guessed from the data if unknown
+    encrypted_canvas: Option<bool>,
 }
 
 impl<R> WzImgReader<R>
@@ -43,13 +57,19 @@ where
         }
     }
 
-    pub fn read_path(&mut self, root: &WzObject, path: &str) -> anyhow::Result<WzObject> {
-        let mut cur = root.clone();
+    pub fn read_path(&mut self, root: &WzObject, path: &str) -> WzResult<WzObject> {
+        let mut cur = root;
         let mut obj_storage = None;
 
+        let not_found = || WzError::NotFound {
+            path: path.to_string(),
+        };
+        // End of the walked prefix of the path
+        let mut walked = 0;
         for part in path.split('/') {
+            walked += part.len();
             let WzObject::Property(ref prop) = cur else {
-                anyhow::bail!("Invalid prop: {cur:?}");
+                return Err(not_found());
             };
 
             let next = prop
@@ -57,30 +77,33 @@ where
                 .0
                 .iter()
                 .find(|x| x.name.as_ref().as_str() == part)
-                .ok_or_else(|| anyhow::format_err!("Invalid {path}"))?;
+                .ok_or_else(not_found)?;
 
             let obj = match &next.val {
                 WzPropValue::Obj(ref obj) => obj,
-                _ => anyhow::bail!("Invalid obj: {cur:?}"),
+                _ => return Err(not_found()),
             };
-            obj_storage = Some(self.read_obj(obj)?);
+            obj_storage = Some(
+                self.read_obj(obj)
+                    .map_err(|err| err.in_parent(&path[..walked]))?,
+            );
             cur = obj_storage.as_ref().unwrap();
+            // Skip the separator
+            walked += 1;
         }
 
-        obj_storage.ok_or_else(|| anyhow::format_err!("Invalid {path}"))
+        obj_storage.ok_or_else(not_found)
     }
 
     /// Read the root object for that image
-    pub fn read_root_obj(&mut self) -> anyhow::Result<WzObject> {
+    pub fn read_root_obj(&mut self) -> WzResult<WzObject> {
         self.r.rewind()?;
-        Ok(WzObject::read_le_args(
-            &mut self.r,
-            WzContext::new(&self.crypto, &self.str_table),
-        )?)
+        WzObject::read_le_args(&mut self.r, WzContext::new(&self.crypto, &self.str_table))
+            .map_err(|err| WzError::from_binrw(err, 0))
     }
 
     /// Read an object with the given object header
-    pub fn read_obj(&mut self, obj: &WzObj) -> anyhow::Result<WzObject> {
+    pub fn read_obj(&mut self, obj: &WzObj) -> WzResult<WzObject> {
         // Check for root
         let ix = if obj.len.pos == 0 && obj.len.val == 0 {
             0
@@ -90,161 +113,566 @@ where
 
         // Skip first index
         self.r.seek(SeekFrom::Start(ix))?;
-        Ok(WzObject::read_le_args(
-            &mut self.r,
-            WzContext::new(&self.crypto, &self.str_table),
-        )?)
+        WzObject::read_le_args(&mut self.r, WzContext::new(&self.crypto, &self.str_table))
+            .map_err(|err| WzError::from_binrw(err, ix))
     }
 
-    fn read_canvas_from<T: BufRead>(mut r: T, canvas: &WzCanvas) -> anyhow::Result<Canvas> {
-        let sz = canvas.bitmap_size() as usize;
-        let mut img_buf = Vec::with_capacity(sz);
+    fn read_canvas_from<T: BufRead>(mut r: T, canvas: &WzCanvas) -> io::Result<Canvas> {
+        let sz = canvas.scaled_bitmap_size() as usize;
+        let mut img_buf = Vec::new();
         r.decompress_flate_size(&mut img_buf, sz)?;
         Ok(Canvas::from_data(img_buf, canvas))
     }
 
-    pub fn read_canvas(&mut self, canvas: &WzCanvas) -> anyhow::Result<Canvas> {
+    /// Reads the bitmap of the canvas, `_inlink`s are followed
+    pub fn read_canvas(&mut self, canvas: &WzCanvas) -> WzResult<Canvas> {
+        let canvas = self.resolve_inlink(canvas)?;
+        self.read_canvas_bitmap(&canvas)
+    }
+
+    /// Like `read_canvas`, but `_outlink`s are read with the `resolver`
+    pub fn read_canvas_with(
+        &mut self,
+        canvas: &WzCanvas,
+        resolver: &mut impl WzOutlinkResolver,
+    ) -> WzResult<Canvas> {
+        let canvas = self.resolve_inlink(canvas)?;
+        match canvas.link() {
+            Some(WzCanvasLink::Outlink(path)) => resolver.read_outlink(path),
+            _ => self.read_canvas_bitmap(&canvas),
+        }
+    }
+
+    /// Follows the `_inlink`s to the canvas, which stores the bitmap or an `_outlink`
+    pub fn resolve_inlink<'a>(&mut self, canvas: &'a WzCanvas) -> WzResult<Cow<'a, WzCanvas>> {
+        let mut canvas = Cow::Borrowed(canvas);
+        for _ in 0..MAX_CANVAS_LINKS {
+            let Some(WzCanvasLink::Inlink(link)) = canvas.link() else {
+                return Ok(canvas);
+            };
+            let err = |msg| WzError::InvalidLink {
+                link: link.to_string(),
+                msg,
+                path: None,
+            };
+
+            let root = self.read_root_obj()?;
+            let target = match self.read_path(&root, link) {
+                Ok(WzObject::Canvas(target)) => target,
+                Ok(_) => return Err(err("target is no canvas")),
+                Err(WzError::NotFound { .. }) => return Err(err("target not found")),
+                Err(e) => return Err(e),
+            };
+            canvas = Cow::Owned(target);
+        }
+
+        Err(WzError::InvalidLink {
+            link: format!("{:?}", canvas.link()),
+            msg: "too many links",
+            path: None,
+        })
+    }
+
+    fn read_canvas_bitmap(&mut self, canvas: &WzCanvas) -> WzResult<Canvas> {
         let len = canvas.data_len();
         let off = canvas.data_offset();
+        // Reject sizes which don't fit, before allocating the bitmap
+        if canvas.width.0 <= 0 || canvas.height.0 <= 0 || canvas.bitmap_size() > u32::MAX as u64 {
+            return Err(WzError::malformed(
+                canvas.len.pos,
+                format!(
+                    "Invalid canvas size: {}x{}",
+                    canvas.width.0, canvas.height.0
+                ),
+            ));
+        }
         self.r.seek(SeekFrom::Start(off))?;
+        self.read_canvas_data(canvas, len)
+            .map_err(|err| WzError::io_at(err, off))
+    }
 
-        let hdr = self.r.peek_u16()?;
-        // Match header
-        match hdr & 0xFF {
-            0x78 => {
-                let mut sub = (&mut self.r).take(len as u64);
-                Self::read_canvas_from(&mut sub, canvas)
-                // TODO maybe advance r here not sure
-            }
-            _ => {
-                let buf = self.r.read_chunked_data(&self.crypto, len)?;
-                Self::read_canvas_from(Cursor::new(buf), canvas)
-            }
+    fn read_canvas_data(&mut self, canvas: &WzCanvas, len: usize) -> io::Result<Canvas> {
+        let encrypted = match self.encrypted_canvas {
+            Some(encrypted) => encrypted,
+            // Match zlib header
+            None => self.r.peek_u16()? & 0xFF != 0x78,
+        };
+        if encrypted {
+            let buf = self.r.read_chunked_data(&self.crypto, len)?;
+            Self::read_canvas_from(Cursor::new(buf), canvas)
+        } else {
+            let mut sub = (&mut self.r).take(len as u64);
+            Self::read_canvas_from(&mut sub, canvas)
+            // TODO maybe advance r here not sure
         }
     }
 
-    pub fn read_sound(&mut self, sound: &WzSound) -> anyhow::Result<Vec<u8>> {
-        let offset = sound.offset.pos;
-        let ln = sound.data_size();
+    /// Reads the raw sound data, see `WzSound::to_file_bytes` for a playable file
+    pub fn read_sound(&mut self, sound: &WzSound) -> WzResult<Vec<u8>> {
+        self.read_raw(sound.offset.pos, sound.raw_size())
+    }
+
+    /// Reads `len` bytes at `offset` of the image
+    pub fn read_raw(&mut self, offset: u64, len: usize) -> WzResult<Vec<u8>> {
         let old = self.r.stream_position()?;
         self.r.seek(SeekFrom::Start(offset))?;
-        let mut data = vec![0; ln];
-        self.r.read_exact(&mut data)?;
+        let data = read_vec(&mut self.r, len).map_err(|err| WzError::io_at(err, offset))?;
         self.r.seek(SeekFrom::Start(old))?;
 
         Ok(data)
     }
 
-    pub fn into_serializer(self, skip_canvas: bool) -> anyhow::Result<WzImgSerializer<R>> {
+    /// Writes the value as image data with the crypto of this image,
+    /// canvas and sound payloads are copied from this image
+    pub fn write_value(&mut self, val: &WzValue) -> WzResult<Vec<u8>> {
+        let crypto = self.crypto.clone();
+        WzImgWriter::write(&crypto, val, self)
+    }
+
+    pub fn into_serializer(self, skip_canvas: bool) -> WzResult<WzImgSerializer<R>> {
         WzImgSerializer::new(self, skip_canvas)
     }
 }
 
+/// `List.wz` of the archive and the offsets of the listed images
+#[derive(Debug)]
+struct WzListedImgs {
+    list: Arc<WzList>,
+    archive: String,
+    offsets: HashSet<u64>,
+}
+
 #[derive(Debug)]
 pub struct WzReader<R> {
     inner: R,
-    crypto: Rc<WzCrypto>,
+    crypto: Arc<WzCrypto>,
     str_table: WzStrTable,
     data_offset: u64,
+    region: WzRegion,
+    version: WzVersion,
+    version_header: bool,
+    list: Option<WzListedImgs>,
 }
 
 pub type SubWzReader<'a, R> = WzReader<SubReader<'a, R>>;
 pub type WzReaderFile = WzReader<BufReader<File>>;
+/// Reader over shared data, which hands out image readers for multiple threads
+pub type WzReaderShared = WzReader<Cursor<SharedData>>;
 
 impl WzReaderFile {
     pub fn open_file(
         path: impl AsRef<Path>,
         region: WzRegion,
         version: WzVersion,
-    ) -> anyhow::Result<Self> {
+    ) -> WzResult<Self> {
         Self::open(BufReader::new(File::open(path)?), region, version)
     }
+
+    pub fn open_file_auto(path: impl AsRef<Path>, region: WzRegion) -> WzResult<Self> {
+        Self::open_auto(BufReader::new(File::open(path)?), region)
+    }
 }
 
 impl<R> WzReader<R>
 where
     R: WzIO,
 {
-    pub fn open(mut rdr: R, region: WzRegion, ver: WzVersion) -> anyhow::Result<Self> {
-        let hdr = WzHeader::read_le(&mut rdr)?;
-        rdr.seek(SeekFrom::Start(hdr.data_offset as u64))?;
+    pub fn open(mut rdr: R, region: WzRegion, ver: WzVersion) -> WzResult<Self> {
+        let (hdr, encrypted_version) = Self::read_header(&mut rdr)?;
+        let mut r = Self::from_parts(rdr, region, ver, hdr.data_offset);
+        match encrypted_version {
+            // Either the version or a versionless root with a multiple of 256 entries
+            Some(WZ_AMBIGUOUS_VERSION) => {
+                let versioned =
+                    ver.encrypted_version() == WZ_AMBIGUOUS_VERSION && r.check_version()?;
+                if !versioned {
+                    r.version_header = false;
+                    if !r.check_version()? {
+                        return Err(WzError::WrongVersion {
+                            pos: hdr.data_offset as u64,
+                            found: WZ_AMBIGUOUS_VERSION,
+                            expected: Some(ver),
+                        });
+                    }
+                }
+            }
+            Some(v) if v == ver.encrypted_version() => {}
+            None => r.version_header = false,
+            Some(v) => {
+                return Err(WzError::WrongVersion {
+                    pos: hdr.data_offset as u64,
+                    found: v,
+                    expected: Some(ver),
+                })
+            }
+        }
+
+        Ok(r)
+    }
+
+    /// Opens the archive and detects the version,
+    /// by trying all candidates which match the encrypted version
+    pub fn open_auto(mut rdr: R, region: WzRegion) -> WzResult<Self> {
+        let (hdr, encrypted_version) = Self::read_header(&mut rdr)?;
+        let mut r = Self::from_parts(rdr, region, WzVersion(0), hdr.data_offset);
+        r.detect_version(encrypted_version)?;
+        Ok(r)
+    }
 
-        let encrypted_version = u16::read_le(&mut rdr)?;
-        if ver.encrypted_version() != encrypted_version {
-            anyhow::bail!("Wrong version: {}, expected: {ver:?}", encrypted_version);
+    /// Opens the archive and detects both the region and the version
+    pub fn open_detect(mut rdr: R) -> WzResult<Self> {
+        let (hdr, encrypted_version) = Self::read_header(&mut rdr)?;
+        let mut r = Self::from_parts(rdr, WzRegion::GMS, WzVersion(0), hdr.data_offset);
+        // Names don't depend on the version, so the region can be detected first,
+        // 0x80 might also be the entry count of a versionless root dir
+        let layouts: &[bool] = match encrypted_version {
+            Some(WZ_AMBIGUOUS_VERSION) => &[true, false],
+            Some(_) => &[true],
+            None => &[false],
+        };
+        let mut best: Option<(usize, WzRegion, bool)> = None;
+        for &version_header in layouts {
+            r.version_header = version_header;
+            let found = r.best_region(|r| {
+                let root = r.read_root_dir()?;
+                Ok(root
+                    .entries
+                    .0
+                    .iter()
+                    .filter_map(|node| node.name().map(str::to_string))
+                    .collect())
+            });
+            if let Some((score, region)) = found {
+                if score > best.map_or(0, |(best_score, _, _)| best_score) {
+                    best = Some((score, region, version_header));
+                }
+            }
         }
 
-        Ok(Self {
-            inner: rdr,
-            crypto: WzCrypto::from_region(region, ver, hdr.data_offset).into(),
-            data_offset: hdr.data_offset as u64,
-            str_table: WzStrTable::default(),
-        })
+        let Some((_, region, version_header)) = best else {
+            return Err(WzError::malformed(r.data_offset, "Unable to detect region"));
+        };
+        r.version_header = version_header;
+        r.set_crypto(region, r.version);
+        r.detect_version(encrypted_version)?;
+        Ok(r)
+    }
+
+    pub fn open_img(rdr: R, region: WzRegion, ver: WzVersion) -> WzResult<Self> {
+        Ok(Self::from_parts(rdr, region, ver, 0))
+    }
+
+    /// Opens a standalone image and detects the region,
+    /// by decoding the names of the root property
+    pub fn open_img_detect(rdr: R, ver: WzVersion) -> WzResult<Self> {
+        let mut r = Self::from_parts(rdr, WzRegion::GMS, ver, 0);
+        r.detect_region(|r| {
+            let mut img = r.root_img_reader()?;
+            let WzObject::Property(prop) = img.read_root_obj()? else {
+                return Err(WzError::UnexpectedValue {
+                    expected: "Property",
+                    path: None,
+                });
+            };
+            Ok(prop
+                .entries
+                .0
+                .iter()
+                .map(|entry| entry.name.as_ref().to_string())
+                .collect())
+        })?;
+        Ok(r)
     }
 
-    pub fn open_img(rdr: R, region: WzRegion, ver: WzVersion) -> anyhow::Result<Self> {
-        let data_offset = 0;
+    /// Reads the header and the encrypted version,
+    /// later archives have no encrypted version and start with the root dir directly
+    fn read_header(rdr: &mut R) -> WzResult<(WzHeader, Option<u16>)> {
+        let hdr = WzHeader::read_le(rdr).map_err(|err| WzError::from_binrw(err, 0))?;
+        let pos = hdr.data_offset as u64;
+        rdr.seek(SeekFrom::Start(pos))?;
+        let encrypted_version = u16::read_le(rdr).map_err(|err| WzError::from_binrw(err, pos))?;
+        // The encrypted version is a single byte, the root dir starts with
+        // the entry count and a non-zero entry type
+        Ok((
+            hdr,
+            (encrypted_version <= 0xFF).then_some(encrypted_version),
+        ))
+    }
 
-        Ok(Self {
+    fn from_parts(rdr: R, region: WzRegion, ver: WzVersion, data_offset: u32) -> Self {
+        Self {
             inner: rdr,
             crypto: WzCrypto::from_region(region, ver, data_offset).into(),
             data_offset: data_offset as u64,
             str_table: WzStrTable::default(),
-        })
+            region,
+            version: ver,
+            version_header: true,
+            list: None,
+        }
     }
 
-    fn sub_reader(&mut self, offset: u64, size: u64) -> SubReader<'_, R> {
+    fn set_crypto(&mut self, region: WzRegion, ver: WzVersion) {
+        self.crypto = WzCrypto::from_region(region, ver, self.data_offset as u32).into();
+        self.str_table = WzStrTable::default();
+        self.region = region;
+        self.version = ver;
+    }
+
+    fn detect_version(&mut self, encrypted_version: Option<u16>) -> WzResult<()> {
+        if let Some(encrypted_version) = encrypted_version {
+            self.version_header = true;
+            for ver in WzVersion::candidates(encrypted_version) {
+                self.set_crypto(self.region, ver);
+                if self.check_version()? {
+                    return Ok(());
+                }
+            }
+
+            if encrypted_version != WZ_AMBIGUOUS_VERSION {
+                return Err(WzError::WrongVersion {
+                    pos: self.data_offset,
+                    found: encrypted_version,
+                    expected: None,
+                });
+            }
+        }
+
+        // Without an encrypted version every version hash is a candidate
+        self.version_header = false;
+        for ver in WzVersion::all() {
+            self.set_crypto(self.region, ver);
+            if self.check_version()? {
+                return Ok(());
+            }
+        }
+
+        Err(WzError::malformed(
+            self.data_offset,
+            "No version found for archive without version",
+        ))
+    }
+
+    /// Checks if the offsets of the root dir and its sub dirs are within the file,
+    /// a wrong version hash yields random offsets
+    fn check_version(&mut self) -> io::Result<bool> {
+        let len = self.inner.seek(SeekFrom::End(0))?;
+        let valid_range = self.data_offset..len;
+        let valid_offsets = |dir: &WzDir| {
+            dir.entries.0.iter().all(|node| {
+                let (offset, img_size) = match node {
+                    WzDirNode::Dir(dir) => (dir.offset, 0),
+                    WzDirNode::Img(img) => (img.offset, img.blob_size.0),
+                    WzDirNode::Link(link) => (link.offset, link.blob_size.0),
+                    WzDirNode::Nil(_) => return true,
+                };
+                let Ok(img_size) = u64::try_from(img_size) else {
+                    return false;
+                };
+                valid_range.contains(&(offset.0 as u64)) && offset.0 as u64 + img_size <= len
+            })
+        };
+
+        let Ok(root) = self.read_root_dir() else {
+            return Ok(false);
+        };
+        if !valid_offsets(&root) {
+            return Ok(false);
+        }
+
+        for node in root.entries.0.iter() {
+            if let WzDirNode::Dir(dir) = node {
+                match self.read_dir_node(dir) {
+                    Ok(sub) if valid_offsets(&sub) => {}
+                    _ => return Ok(false),
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Decodes sample names with every region and picks the one with the best score,
+    /// the wrong key yields garbage names or invalid strings
+    fn detect_region(
+        &mut self,
+        sample: impl Fn(&mut Self) -> WzResult<Vec<String>>,
+    ) -> WzResult<()> {
+        let Some((_, region)) = self.best_region(sample) else {
+            return Err(WzError::malformed(0, "Unable to detect region"));
+        };
+        self.set_crypto(region, self.version);
+        Ok(())
+    }
+
+    /// Returns the region with the best score of the sample names and the score
+    fn best_region(
+        &mut self,
+        sample: impl Fn(&mut Self) -> WzResult<Vec<String>>,
+    ) -> Option<(usize, WzRegion)> {
+        let mut best: Option<(usize, WzRegion)> = None;
+        for region in WzRegion::ALL {
+            self.set_crypto(region, self.version);
+            let score = sample(self).map_or(0, |names| name_score(&names));
+            if score > best.map_or(0, |(best_score, _)| best_score) {
+                best = Some((score, region));
+            }
+        }
+        best
+    }
+
+    /// Version used to decrypt the offsets
+    pub fn version(&self) -> WzVersion {
+        self.version
+    }
+
+    /// Region of the crypto context
+    pub fn region(&self) -> WzRegion {
+        self.region
+    }
+
+    /// Sets the `List.wz` of older clients to decide which canvases are encrypted,
+    /// the archive name like `Map` prefixes the image paths
+    ///
+    /// The images are traversed once, so every image reader can look up
+    /// the decision by the offset of its header.
+    pub fn set_list(&mut self, list: Arc<WzList>, archive_name: impl Into<String>) -> WzResult<()> {
+        let archive = archive_name.into();
+        let mut offsets = HashSet::new();
+        for img in self.traverse_images() {
+            let (path, hdr) = img?;
+            let path = path.strip_prefix("/root/").unwrap_or(&path);
+            if list.contains(&format!("{archive}/{path}")) {
+                offsets.insert(u64::from(hdr.offset));
+            }
+        }
+        self.list = Some(WzListedImgs {
+            list,
+            archive,
+            offsets,
+        });
+        Ok(())
+    }
+
+    /// Later archives have no encrypted version after the header
+    pub fn is_versionless(&self) -> bool {
+        !self.version_header
+    }
+
+    fn sub_reader(&mut self, offset: u64, size: u64) -> io::Result<SubReader<'_, R>> {
         SubReader::new(&mut self.inner, offset, size)
     }
 
     pub fn root_offset(&self) -> WzOffset {
-        WzOffset(self.data_offset as u32 + 2)
+        // Skip encrypted version at the start
+        let version_len = if self.version_header { 2 } else { 0 };
+        WzOffset(self.data_offset as u32 + version_len)
     }
 
-    pub fn read_root_dir(&mut self) -> anyhow::Result<WzDir> {
-        // Skip encrypted version at the start
+    pub fn read_root_dir(&mut self) -> WzResult<WzDir> {
         self.read_dir(self.root_offset().0 as u64)
     }
 
-    pub fn read_dir_node(&mut self, hdr: &WzDirHeader) -> anyhow::Result<WzDir> {
+    pub fn read_dir_node(&mut self, hdr: &WzDirHeader) -> WzResult<WzDir> {
         self.read_dir(hdr.offset.0 as u64)
     }
 
-    fn read_dir(&mut self, offset: u64) -> anyhow::Result<WzDir> {
+    fn read_dir(&mut self, offset: u64) -> WzResult<WzDir> {
+        let len = self.inner.seek(SeekFrom::End(0))?;
+        if offset < self.data_offset || offset >= len {
+            return Err(WzError::BadOffset {
+                pos: offset,
+                offset,
+                path: None,
+            });
+        }
         self.set_pos(offset)?;
-        Ok(WzDir::read_le_args(
+        WzDir::read_le_args(
             &mut self.inner,
             WzContext::new(&self.crypto, &self.str_table),
-        )?)
+        )
+        .map_err(|err| WzError::from_binrw(err, offset))
     }
 
     pub fn root_img_reader(&mut self) -> io::Result<WzImgReader<SubReader<'_, R>>> {
         // Get size by seeking to end
         let end = self.inner.seek(SeekFrom::End(0))?;
         let off = 0;
-        self.set_pos(off)?;
         let crypto = self.crypto.clone();
-        let str_table = self.str_table.clone();
+        let str_table = self.str_table.with_base(off);
 
         Ok(WzImgReader {
-            r: self.sub_reader(off, end),
+            r: self.sub_reader(off, end)?,
             crypto,
             str_table,
+            encrypted_canvas: None,
         })
     }
 
-    pub fn img_reader(&mut self, hdr: &WzImgHeader) -> io::Result<WzImgReader<SubReader<'_, R>>> {
-        let off = hdr.offset.into();
-        self.set_pos(off)?;
+    /// Offset and size of the image, if it's within the file
+    fn img_range(r: &mut R, hdr: &WzImgHeader) -> WzResult<(u64, u64)> {
+        let len = r.seek(SeekFrom::End(0))?;
+        let off = u64::from(hdr.offset);
+        match u64::try_from(hdr.blob_size.0) {
+            Ok(size) if off + size <= len => Ok((off, size)),
+            _ => Err(WzError::BadOffset {
+                pos: off,
+                offset: off,
+                path: Some(hdr.name.to_string()),
+            }),
+        }
+    }
+
+    /// Image reader for the header, if a list is set it decides the canvas decryption
+    pub fn img_reader(&mut self, hdr: &WzImgHeader) -> WzResult<WzImgReader<SubReader<'_, R>>> {
+        let (off, size) = Self::img_range(&mut self.inner, hdr)?;
         let crypto = self.crypto.clone();
-        let str_table = self.str_table.clone();
+        let str_table = self.str_table.with_base(off);
+        let encrypted_canvas = self.listed_encrypted_canvas(hdr);
 
         Ok(WzImgReader {
-            r: self.sub_reader(off, hdr.blob_size.0 as u64),
+            r: self.sub_reader(off, size)?,
             crypto,
             str_table,
+            encrypted_canvas,
         })
     }
+
+    /// Image reader for the image at `path` like `Obj/acc1.img`,
+    /// canvases are only decrypted if the image is in the list
+    pub fn img_reader_with_path(
+        &mut self,
+        path: &str,
+        hdr: &WzImgHeader,
+    ) -> WzResult<WzImgReader<SubReader<'_, R>>> {
+        let encrypted_canvas = self.list_encrypted_canvas(path);
+        let mut img = self.img_reader(hdr)?;
+        img.encrypted_canvas = encrypted_canvas;
+        Ok(img)
+    }
+
+    fn list_encrypted_canvas(&self, path: &str) -> Option<bool> {
+        self.list
+            .as_ref()
+            .map(|l| l.list.contains(&format!("{}/{path}", l.archive)))
+    }
+
+    /// Looks up the image by its offset, so it also works without the path
+    fn listed_encrypted_canvas(&self, hdr: &WzImgHeader) -> Option<bool> {
+        self.list
+            .as_ref()
+            .map(|l| l.offsets.contains(&u64::from(hdr.offset)))
+    }
+
+    /// Read the raw encoded data of an image
+    pub fn read_img_data(&mut self, hdr: &WzImgHeader) -> WzResult<Vec<u8>> {
+        let (off, size) = Self::img_range(&mut self.inner, hdr)?;
+        self.set_pos(off)?;
+        let mut data = vec![0; size as usize];
+        self.inner.read_exact(&mut data)?;
+        Ok(data)
+    }
+
     /*
         pub fn link_img_reader(
             &mut self,
@@ -271,17 +699,19 @@ where
         WzImgTraverser { r: self, q }
     }
 
-    pub fn read_path(&mut self, root: &WzDirNode, path: &str) -> anyhow::Result<WzDirNode> {
+    pub fn read_path(&mut self, root: &WzDirNode, path: &str) -> WzResult<WzDirNode> {
         let mut cur = root.clone();
 
         for part in path.split('/') {
             let WzDirNode::Dir(dir) = cur else {
-                anyhow::bail!("Invalid dir: {cur:?}");
+                return Err(WzError::NotFound {
+                    path: path.to_string(),
+                });
             };
 
             let dir = self.read_dir_node(&dir)?;
-            let next = dir.get(part).ok_or_else(|| {
-                anyhow::format_err!("Invalid {path}: {part} not found in {dir:?}")
+            let next = dir.get(part).ok_or_else(|| WzError::NotFound {
+                path: path.to_string(),
             })?;
             cur = next.clone();
         }
@@ -289,25 +719,131 @@ where
         Ok(cur)
     }
 
+    /// Header of the image at `path` like `Mob/8800000.img`, linked images are resolved
+    pub fn img_header_by_path(&mut self, path: &str) -> WzResult<WzImgHeader> {
+        let root = WzDirNode::Dir(WzDirHeader::root("root", 1, self.root_offset()));
+        match self.read_path(&root, path)? {
+            WzDirNode::Img(hdr) => Ok(hdr),
+            WzDirNode::Link(link) => Ok(link.img_header()),
+            _ => Err(WzError::NotFound {
+                path: path.to_string(),
+            }),
+        }
+    }
+
+    /// Reads the canvas at `path` like `Mob/8800000.img/stand/0`, `_inlink`s are followed
+    /// and an `_outlink` is returned, so the caller can open the other archive
+    pub fn read_canvas_path(&mut self, path: &str) -> WzResult<WzLinkedCanvas> {
+        let (img_path, prop_path) = path
+            .find(".img/")
+            .map(|ix| (&path[..ix + 4], &path[ix + 5..]))
+            .ok_or_else(|| WzError::NotFound {
+                path: path.to_string(),
+            })?;
+
+        let hdr = self.img_header_by_path(img_path)?;
+        let mut img = self.img_reader_with_path(img_path, &hdr)?;
+        let root = img.read_root_obj()?;
+        let WzObject::Canvas(canvas) = img.read_path(&root, prop_path)? else {
+            return Err(WzError::UnexpectedValue {
+                expected: "Canvas",
+                path: Some(path.to_string()),
+            });
+        };
+
+        let canvas = img.resolve_inlink(&canvas)?;
+        Ok(match canvas.link() {
+            Some(WzCanvasLink::Outlink(link)) => WzLinkedCanvas::Outlink(link.to_string()),
+            _ => WzLinkedCanvas::Bitmap(img.read_canvas_bitmap(&canvas)?),
+        })
+    }
+
     fn set_pos(&mut self, p: u64) -> io::Result<()> {
         self.inner.seek(SeekFrom::Start(p))?;
         Ok(())
     }
 }
 
+impl<R> WzReader<R>
+where
+    R: WzIO + Clone,
+{
+    /// Image reader with its own copy of the reader,
+    /// so images can be read from multiple threads
+    pub fn img_reader_owned(&self, hdr: &WzImgHeader) -> WzResult<WzImgReader<BoundedReader<R>>> {
+        let mut inner = self.inner.clone();
+        let (off, size) = Self::img_range(&mut inner, hdr)?;
+
+        Ok(WzImgReader {
+            r: BoundedReader::new(inner, off, size)?,
+            crypto: self.crypto.clone(),
+            str_table: self.str_table.with_base(off),
+            encrypted_canvas: self.listed_encrypted_canvas(hdr),
+        })
+    }
+
+    /// Owned image reader for the image at `path`, see `img_reader_with_path`
+    pub fn img_reader_owned_with_path(
+        &self,
+        path: &str,
+        hdr: &WzImgHeader,
+    ) -> WzResult<WzImgReader<BoundedReader<R>>> {
+        let mut img = self.img_reader_owned(hdr)?;
+        img.encrypted_canvas = self.list_encrypted_canvas(path);
+        Ok(img)
+    }
+}
+
+impl WzReaderShared {
+    pub fn open_shared(
+        data: impl AsRef<[u8]> + Send + Sync + 'static,
+        region: WzRegion,
+        version: WzVersion,
+    ) -> WzResult<Self> {
+        Self::open(Cursor::new(SharedData::new(data)), region, version)
+    }
+
+    pub fn open_shared_auto(
+        data: impl AsRef<[u8]> + Send + Sync + 'static,
+        region: WzRegion,
+    ) -> WzResult<Self> {
+        Self::open_auto(Cursor::new(SharedData::new(data)), region)
+    }
+}
+
+/// Printable ascii names score highest, any other printable name still scores
+fn name_score(names: &[String]) -> usize {
+    names
+        .iter()
+        .map(|name| {
+            if name.is_empty() {
+                0
+            } else if name.chars().all(|c| c.is_ascii_graphic() || c == ' ') {
+                2
+            } else if name
+                .chars()
+                .all(|c| !c.is_control() && c != char::REPLACEMENT_CHARACTER)
+            {
+                1
+            } else {
+                0
+            }
+        })
+        .sum()
+}
+
 pub struct WzImgTraverser<'r, R> {
     r: &'r mut WzReader<R>,
     q: VecDeque<(Arc<String>, WzDirNode)>,
 }
 
 impl<'r, R: WzIO> WzImgTraverser<'r, R> {
-    fn handle_dir(
-        &mut self,
-        root_name: &str,
-        dir: &WzDirHeader,
-    ) -> anyhow::Result<(Arc<String>, WzDir)> {
-        let node = self.r.read_dir_node(dir)?;
+    fn handle_dir(&mut self, root_name: &str, dir: &WzDirHeader) -> WzResult<(Arc<String>, WzDir)> {
         let node_name = Arc::new(format!("{}/{}", root_name, dir.name.as_str()));
+        let node = self
+            .r
+            .read_dir_node(dir)
+            .map_err(|err| err.in_parent(&node_name))?;
 
         self.q.extend(
             node.entries
@@ -324,7 +860,7 @@ impl<'r, R> Iterator for WzImgTraverser<'r, R>
 where
     R: WzIO,
 {
-    type Item = anyhow::Result<(String, WzImgHeader)>;
+    type Item = WzResult<(String, WzImgHeader)>;
 
     fn next(&mut self) -> Option<Self::Item> {
         while let Some((root_name, node)) = self.q.pop_front() {
@@ -339,7 +875,7 @@ where
                     return Some(Ok((name, img)));
                 }
                 WzDirNode::Link(link) => {
-                    let img = link.link.link_img;
+                    let img = link.img_header();
                     let name = format!("{}/{}", root_name, img.name.as_str());
                     return Some(Ok((name, img)));
                 }
@@ -360,6 +896,8 @@ pub mod mmap {
     use memmap2::Mmap;
 
     use crate::{
+        error::WzResult,
+        file::WzReaderShared,
         version::{WzRegion, WzVersion},
         WzReader,
     };
@@ -371,14 +909,280 @@ pub mod mmap {
             path: impl AsRef<Path>,
             region: WzRegion,
             version: WzVersion,
-        ) -> anyhow::Result<Self> {
+        ) -> WzResult<Self> {
             let file = File::open(path)?;
             let mmap = unsafe { Mmap::map(&file)? };
             Self::new(mmap, region, version)
         }
 
-        pub fn new(mmap: Mmap, region: WzRegion, version: WzVersion) -> anyhow::Result<Self> {
+        pub fn open_file_mmap_auto(path: impl AsRef<Path>, region: WzRegion) -> WzResult<Self> {
+            let file = File::open(path)?;
+            let mmap = unsafe { Mmap::map(&file)? };
+            Self::open_auto(Cursor::new(mmap), region)
+        }
+
+        pub fn new(mmap: Mmap, region: WzRegion, version: WzVersion) -> WzResult<Self> {
             Self::open(Cursor::new(mmap), region, version)
         }
+
+        /// Maps the file for a reader which can be shared between threads
+        pub fn open_file_mmap_shared(
+            path: impl AsRef<Path>,
+            region: WzRegion,
+            version: WzVersion,
+        ) -> WzResult<WzReaderShared> {
+            let file = File::open(path)?;
+            let mmap = unsafe { Mmap::map(&file)? };
+            WzReaderShared::open_shared(mmap, region, version)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, sync::Arc};
+
+    use crate::{
+        crypto::WzCrypto,
+        error::{WzError, WzResult},
+        link::tests::{canvas, img},
+        list::WzList,
+        util::{WzContext, WzStrTable},
+        val::WzValue,
+        version::{WzRegion, WzVersion},
+        writer::{tests::int_img, WzWriterDir},
+        WzReader, WzReaderShared, WzWriter,
+    };
+
+    #[test]
+    fn list_decides_canvas_crypto() {
+        let region = WzRegion::GMS;
+        let ver = WzVersion(95);
+        let crypto = WzCrypto::from_region(region, ver, 0);
+        let str_table = WzStrTable::default();
+        let ctx = WzContext::new(&crypto, &str_table);
+        // Plain zlib data, which the guess would read as unencrypted
+        let data = img(ctx, &[("icon", canvas(ctx, [1, 2, 3, 4], &[]))]);
+
+        let mut root = WzWriterDir::new("Root");
+        let mut obj = WzWriterDir::new("Obj");
+        obj.add_img("acc1.img", data.clone());
+        obj.add_img("acc2.img", data);
+        root.add_dir(obj);
+        let mut w = Cursor::new(Vec::new());
+        WzWriter::new(region, ver).write(&mut w, &root).unwrap();
+        let mut r = WzReaderShared::open_shared(w.into_inner(), region, ver).unwrap();
+
+        let imgs = r.traverse_images().collect::<WzResult<Vec<_>>>().unwrap();
+        assert_eq!(r.img_reader(&imgs[0].1).unwrap().encrypted_canvas, None);
+
+        let list = WzList::new(vec!["Map/Obj/acc1.img".to_string()]);
+        r.set_list(Arc::new(list), "Map").unwrap();
+        for (path, hdr) in imgs.iter() {
+            let listed = path.ends_with("acc1.img");
+            let owned = r.img_reader_owned(hdr).unwrap();
+            assert_eq!(owned.encrypted_canvas, Some(listed), "{path}");
+
+            let mut img = r.img_reader(hdr).unwrap();
+            assert_eq!(img.encrypted_canvas, Some(listed), "{path}");
+            let val = WzValue::read(&mut img).unwrap();
+            let canvas = val.get_path("icon").unwrap().as_canvas().unwrap();
+            let pixels = canvas
+                .read_canvas(&mut img)
+                .and_then(|canvas| canvas.to_rgba_image());
+            // Listed images are decrypted, which fails for the plain data
+            assert_eq!(pixels.is_err(), listed, "{path}");
+            if !listed {
+                assert_eq!(pixels.unwrap().into_raw(), [3, 2, 1, 4]);
+            }
+        }
+    }
+
+    #[test]
+    fn detect_version() {
+        let region = WzRegion::GMS;
+        let mut root = WzWriterDir::new("Root");
+        let mut mob = WzWriterDir::new("Mob");
+        mob.add_img("100100.img", int_img(region, 1));
+        root.add_dir(mob);
+        root.add_img("Base.img", int_img(region, 2));
+
+        for ver in [WzVersion(83), WzVersion(95), WzVersion(176)] {
+            let mut w = Cursor::new(Vec::new());
+            WzWriter::new(region, ver).write(&mut w, &root).unwrap();
+            w.set_position(0);
+
+            let mut r = WzReader::open_auto(w, region).unwrap();
+            assert_eq!(r.version(), ver);
+            assert!(r.read_root_dir().is_ok());
+        }
+    }
+
+    #[test]
+    fn versionless() {
+        let region = WzRegion::GMS;
+        let ver = WzVersion(176);
+        let mut small = WzWriterDir::new("Root");
+        small.add_img("Base.img", int_img(region, 2));
+        // More than 127 entries make the count start with 0x80
+        let mut large = WzWriterDir::new("Root");
+        for i in 0..200 {
+            large.add_img(format!("{i}.img"), int_img(region, i));
+        }
+        // A multiple of 256 entries reads like the encrypted version 0x80
+        let mut ambiguous = WzWriterDir::new("Root");
+        for i in 0..256 {
+            ambiguous.add_img(format!("{i}.img"), int_img(region, i));
+        }
+
+        for root in [small, large, ambiguous] {
+            let mut w = Cursor::new(Vec::new());
+            WzWriter::new_versionless(region, ver)
+                .write(&mut w, &root)
+                .unwrap();
+
+            w.set_position(0);
+            let mut r = WzReader::open(w.clone(), region, ver).unwrap();
+            assert!(r.is_versionless());
+            assert_eq!(
+                r.read_root_dir().unwrap().entries.0.len(),
+                root.entries.len()
+            );
+
+            w.set_position(0);
+            let mut r = WzReader::open_auto(w, region).unwrap();
+            assert!(r.is_versionless());
+            assert_eq!(r.version(), ver);
+            let rewritten = WzWriterDir::from_reader(&mut r).unwrap();
+            assert_eq!(rewritten.entries.len(), root.entries.len());
+        }
+    }
+
+    #[test]
+    fn ambiguous_version() {
+        let region = WzRegion::GMS;
+        let ver = WzVersion::all()
+            .find(|v| v.encrypted_version() == 0x80)
+            .unwrap();
+        let mut root = WzWriterDir::new("Root");
+        root.add_img("Base.img", int_img(region, 2));
+        let mut w = Cursor::new(Vec::new());
+        WzWriter::new(region, ver).write(&mut w, &root).unwrap();
+
+        w.set_position(0);
+        let r = WzReader::open(w.clone(), region, ver).unwrap();
+        assert!(!r.is_versionless());
+        w.set_position(0);
+        let r = WzReader::open_auto(w.clone(), region).unwrap();
+        assert!(!r.is_versionless());
+        assert_eq!(r.version(), ver);
+
+        // A mismatched version isn't mistaken for a versionless archive
+        w.set_position(0);
+        let err = WzReader::open(w, region, WzVersion(95)).unwrap_err();
+        assert!(matches!(err, WzError::WrongVersion { found: 0x80, .. }));
+
+        let mut root = WzWriterDir::new("Root");
+        for i in 0..256 {
+            root.add_img(format!("{i}.img"), int_img(region, i));
+        }
+        let mut w = Cursor::new(Vec::new());
+        WzWriter::new_versionless(region, WzVersion(176))
+            .write(&mut w, &root)
+            .unwrap();
+        w.set_position(0);
+        let err = WzReader::open(w, region, WzVersion(95)).unwrap_err();
+        assert!(matches!(err, WzError::WrongVersion { found: 0x80, .. }));
+    }
+
+    #[test]
+    fn detect_region() {
+        for region in WzRegion::ALL {
+            let mut root = WzWriterDir::new("Root");
+            let mut mob = WzWriterDir::new("Mob");
+            mob.add_img("100100.img", int_img(region, 1));
+            root.add_dir(mob);
+            root.add_img("Base.img", int_img(region, 2));
+
+            let mut w = Cursor::new(Vec::new());
+            WzWriter::new(region, WzVersion(95))
+                .write(&mut w, &root)
+                .unwrap();
+            w.set_position(0);
+
+            let r = WzReader::open_detect(w).unwrap();
+            assert_eq!(r.region(), region);
+            assert_eq!(r.version(), WzVersion(95));
+
+            // Versionless, a multiple of 256 entries reads like the encrypted version 0x80
+            let mut ambiguous = WzWriterDir::new("Root");
+            for i in 0..256 {
+                ambiguous.add_img(format!("{i}.img"), int_img(region, i));
+            }
+            for root in [&root, &ambiguous] {
+                let mut w = Cursor::new(Vec::new());
+                WzWriter::new_versionless(region, WzVersion(176))
+                    .write(&mut w, root)
+                    .unwrap();
+                w.set_position(0);
+
+                let r = WzReader::open_detect(w).unwrap();
+                assert_eq!(r.region(), region);
+                assert!(r.is_versionless());
+                assert_eq!(r.version(), WzVersion(176));
+            }
+
+            // Versioned with the encrypted version 0x80
+            let ver = WzVersion::all()
+                .find(|v| v.encrypted_version() == 0x80)
+                .unwrap();
+            let mut w = Cursor::new(Vec::new());
+            WzWriter::new(region, ver).write(&mut w, &root).unwrap();
+            w.set_position(0);
+
+            let r = WzReader::open_detect(w).unwrap();
+            assert_eq!(r.region(), region);
+            assert!(!r.is_versionless());
+            assert_eq!(r.version(), ver);
+
+            let mut img =
+                WzReader::open_img_detect(Cursor::new(int_img(region, 1)), WzVersion(95)).unwrap();
+            assert_eq!(img.region(), region);
+            let val = WzValue::read(&mut img.root_img_reader().unwrap()).unwrap();
+            assert_eq!(val.get_path("level").unwrap().as_i32(), Some(1));
+        }
+    }
+
+    #[test]
+    fn parallel_read() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<WzReaderShared>();
+        assert_send_sync::<WzValue>();
+
+        let region = WzRegion::GMS;
+        let mut root = WzWriterDir::new("Root");
+        for i in 0..64 {
+            root.add_img(format!("{i}.img"), int_img(region, i));
+        }
+        let mut w = Cursor::new(Vec::new());
+        WzWriter::new(region, WzVersion(95))
+            .write(&mut w, &root)
+            .unwrap();
+
+        let mut r = WzReaderShared::open_shared(w.into_inner(), region, WzVersion(95)).unwrap();
+        let imgs = r.traverse_images().collect::<Result<Vec<_>, _>>().unwrap();
+        let r = &r;
+        std::thread::scope(|s| {
+            for chunk in imgs.chunks(16) {
+                s.spawn(move || {
+                    for (name, hdr) in chunk {
+                        let mut img = r.img_reader_owned(hdr).unwrap();
+                        let val = WzValue::read(&mut img).unwrap();
+                        let level = val.get_path("level").unwrap().as_i32().unwrap();
+                        assert_eq!(name, &format!("/root/{level}.img"));
+                    }
+                });
+            }
+        });
     }
 }
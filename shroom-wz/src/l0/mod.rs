@@ -1,14 +1,14 @@
 pub mod tree;
 use std::io;
 
-use crate::util::WzContext;
+use crate::{error::WzError, util::WzContext};
 use binrw::{binrw, BinRead, BinWrite, NullString};
 
 use crate::ty::{WzInt, WzOffset, WzStr, WzVec};
 
 #[binrw]
 #[brw(little)]
-#[br(magic = b"PKG1")]
+#[brw(magic = b"PKG1")]
 #[derive(Debug)]
 pub struct WzHeader {
     pub file_size: u64,
@@ -88,10 +88,16 @@ impl BinRead for WzLinkData {
         let old_pos = reader.stream_position()?;
         reader.seek(io::SeekFrom::Start(args.crypto.offset_link(offset)))?;
 
+        let ty_pos = reader.stream_position()?;
         let ty = u8::read_options(reader, endian, ())?;
         if ty != 4 {
-            // TODO: support dirs? and return a proper erro
-            panic!("Expected link type Img, got {}", ty);
+            // TODO: support dirs?
+            return Err(WzError::UnknownObject {
+                pos: ty_pos,
+                ty: format!("link type {ty}"),
+                path: None,
+            }
+            .into_binrw());
         }
 
         let link_img = WzImgHeader::read_options(reader, endian, args)?;
@@ -108,11 +114,12 @@ impl BinWrite for WzLinkData {
 
     fn write_options<W: io::Write + io::Seek>(
         &self,
-        _writer: &mut W,
-        _endian: binrw::Endian,
+        writer: &mut W,
+        endian: binrw::Endian,
         _args: Self::Args<'_>,
     ) -> binrw::BinResult<()> {
-        todo!()
+        // Only the offset is stored here, the linked entry must be written by the caller
+        self.offset.write_options(writer, endian, ())
     }
 }
 
@@ -128,15 +135,27 @@ pub struct WzLinkHeader {
     pub offset: WzOffset,
 }
 
+impl WzLinkHeader {
+    /// Header of the linked image, only the name is taken from the link target
+    pub fn img_header(&self) -> WzImgHeader {
+        WzImgHeader {
+            name: self.link.link_img.name.clone(),
+            blob_size: self.blob_size,
+            checksum: self.checksum,
+            offset: self.offset,
+        }
+    }
+}
+
 #[derive(BinRead, BinWrite, Debug, Clone, PartialEq)]
 #[brw(little, import_raw(ctx: WzContext<'_>))]
 pub enum WzDirNode {
     //01 XX 00 00 00 00 00 OFFSET (4 bytes)
-    #[br(magic(1u8))]
+    #[brw(magic(1u8))]
     Nil([u8; 10]),
-    #[br(magic(2u8))]
+    #[brw(magic(2u8))]
     Link(#[brw(args_raw(ctx))] WzLinkHeader),
-    #[br(magic(3u8))]
+    #[brw(magic(3u8))]
     Dir(#[brw(args_raw(ctx))] WzDirHeader),
     #[brw(magic(4u8))]
     Img(#[brw(args_raw(ctx))] WzImgHeader),
@@ -1,8 +1,8 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::VecDeque;
 
-use id_tree::{InsertBehavior, Node, NodeId, Tree};
+use id_tree::{InsertBehavior, Node, Tree};
 
-use crate::{file::WzIO, WzReader};
+use crate::{error::WzResult, file::WzIO, WzReader};
 
 use super::{WzDirHeader, WzDirNode, WzImgHeader};
 
@@ -12,19 +12,21 @@ pub struct WzTree {
 }
 
 impl WzTree {
-    pub fn from_reader<R: WzIO>(r: &mut WzReader<R>, name: Option<&str>) -> anyhow::Result<Self> {
+    pub fn from_reader<R: WzIO>(r: &mut WzReader<R>, name: Option<&str>) -> WzResult<Self> {
         let mut tree = Tree::new();
 
         let off = r.root_offset();
 
-        let root_id = tree.insert(
-            Node::new(WzDirNode::Dir(WzDirHeader::root(
-                name.unwrap_or("Root"),
-                1,
-                off,
-            ))),
-            InsertBehavior::AsRoot,
-        )?;
+        let root_id = tree
+            .insert(
+                Node::new(WzDirNode::Dir(WzDirHeader::root(
+                    name.unwrap_or("Root"),
+                    1,
+                    off,
+                ))),
+                InsertBehavior::AsRoot,
+            )
+            .unwrap();
         let root = r.read_root_dir()?;
         let mut q = VecDeque::new();
         q.push_back((root_id, root));
@@ -38,11 +40,8 @@ impl WzTree {
                     )
                     .unwrap();
 
-                match val {
-                    WzDirNode::Dir(dir) => {
-                        q.push_back((new_node, r.read_dir_node(dir)?));
-                    }
-                    _ => {}
+                if let WzDirNode::Dir(dir) = val {
+                    q.push_back((new_node, r.read_dir_node(dir)?));
                 }
             }
         }
@@ -1,28 +1,29 @@
-use binrw::{binread, PosValue};
+use binrw::{binrw, PosValue};
 
+use crate::error::WzError;
 use crate::ty::WzInt;
 use crate::util::WzContext;
 
-use super::prop::WzProperty;
+use super::prop::{WzPropValue, WzProperty};
 
 #[derive(Debug, Clone)]
 pub struct WzCanvasScaling(pub u8);
 
 impl WzCanvasScaling {
+    /// The bitmap is stored scaled down by `2^scale`
     pub fn get_factor(&self) -> u32 {
-        // 2_pow(scale)
-        2 >> self.0
+        1 << self.0
     }
 }
 
 impl TryFrom<u8> for WzCanvasScaling {
-    type Error = anyhow::Error;
+    type Error = WzError;
 
     fn try_from(value: u8) -> Result<Self, Self::Error> {
         let n = value;
         Ok(Self(match n {
-            0 | 4 => n,
-            _ => anyhow::bail!("Invalid scaling: {n}"),
+            0..=4 => n,
+            _ => return Err(WzError::malformed(0, format!("Invalid scaling: {n}"))),
         }))
     }
 }
@@ -32,38 +33,85 @@ impl From<WzCanvasScaling> for u8 {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum WzCanvasDepth {
     BGRA4444,
     BGRA8888,
+    /// 1 bit alpha
+    ARGB1555,
     BGR565,
+    /// Depth 3, sometimes called grayscale DXT3,
+    /// stores one BGRA4444 color per 4x4 block
+    BGRA4444Block4,
+    /// Depth 517, stores one BGR565 color per 16x16 block
+    BGR565Block16,
+    /// BC2
     DXT3,
+    /// BC3
     DXT5,
 }
 
 impl WzCanvasDepth {
+    /// Bytes per stored pixel, block compressed formats use 1
     pub fn depth_size(&self) -> u32 {
         match self {
             WzCanvasDepth::BGRA4444 => 2,
             WzCanvasDepth::BGRA8888 => 4,
+            WzCanvasDepth::ARGB1555 => 2,
             WzCanvasDepth::BGR565 => 2,
+            WzCanvasDepth::BGRA4444Block4 => 2,
+            WzCanvasDepth::BGR565Block16 => 2,
             WzCanvasDepth::DXT3 => 1,
             WzCanvasDepth::DXT5 => 1,
         }
     }
+
+    /// Scale which is implied by the depth, on top of the canvas scaling
+    pub fn block_factor(&self) -> u32 {
+        match self {
+            WzCanvasDepth::BGRA4444Block4 => 4,
+            WzCanvasDepth::BGR565Block16 => 16,
+            _ => 1,
+        }
+    }
+
+    pub fn is_block_compressed(&self) -> bool {
+        matches!(self, WzCanvasDepth::DXT3 | WzCanvasDepth::DXT5)
+    }
+
+    /// Size of a stored bitmap with the given dimensions,
+    /// block compressed formats are padded to 4x4 blocks
+    pub fn data_size(&self, width: u32, height: u32) -> u64 {
+        let (w, h) = if self.is_block_compressed() {
+            (width.div_ceil(4) * 4, height.div_ceil(4) * 4)
+        } else {
+            (width, height)
+        };
+        w as u64 * h as u64 * self.depth_size() as u64
+    }
 }
 
 impl TryFrom<WzInt> for WzCanvasDepth {
-    type Error = anyhow::Error;
+    type Error = WzError;
 
+    /// The position of the error is set by the reader
     fn try_from(value: WzInt) -> Result<Self, Self::Error> {
-        Ok(match value.0 as u16 {
+        Ok(match value.0 {
             1 => Self::BGRA4444,
             2 => Self::BGRA8888,
+            3 => Self::BGRA4444Block4,
+            257 => Self::ARGB1555,
             513 => Self::BGR565,
+            517 => Self::BGR565Block16,
             1026 => Self::DXT3,
             2050 => Self::DXT5,
-            depth => anyhow::bail!("Invalid canvas depth: {depth}"),
+            depth => {
+                return Err(WzError::UnsupportedDepth {
+                    pos: 0,
+                    depth,
+                    path: None,
+                })
+            }
         })
     }
 }
@@ -73,28 +121,36 @@ impl From<WzCanvasDepth> for WzInt {
         WzInt(match val {
             WzCanvasDepth::BGRA4444 => 1,
             WzCanvasDepth::BGRA8888 => 2,
+            WzCanvasDepth::BGRA4444Block4 => 3,
+            WzCanvasDepth::ARGB1555 => 257,
             WzCanvasDepth::BGR565 => 513,
+            WzCanvasDepth::BGR565Block16 => 517,
             WzCanvasDepth::DXT3 => 1026,
             WzCanvasDepth::DXT5 => 2050,
         })
     }
 }
 
-#[binread]
+/// Canvas header, when written the caller has to append the `len` bytes of payload
+#[binrw]
 #[brw(little, import_raw(ctx: WzContext<'_>))]
 #[derive(Debug, Clone)]
 pub struct WzCanvas {
     pub unknown: u8,
     pub has_property: u8,
     #[br(if(has_property.eq(&1)), args_raw(ctx))]
+    #[bw(args_raw(ctx))]
     pub property: Option<WzProperty>,
     pub width: WzInt,
     pub height: WzInt,
     #[br(try_map = |x: WzInt| x.try_into())]
+    #[bw(map = |x: &WzCanvasDepth| WzInt::from(*x))]
     pub depth: WzCanvasDepth,
     #[br(try_map = |x: u8| x.try_into())]
+    #[bw(map = |x: &WzCanvasScaling| x.0)]
     pub scale: WzCanvasScaling,
     pub unknown1: u32,
+    #[bw(map = |x: &PosValue<u32>| x.val)]
     pub len: PosValue<u32>,
 }
 
@@ -111,8 +167,14 @@ impl WzCanvas {
         self.width.0 as u32
     }
 
-    pub fn bitmap_size(&self) -> u32 {
-        self.pixels() * self.depth.depth_size()
+    /// Size of the bitmap without scaling
+    pub fn bitmap_size(&self) -> u64 {
+        self.depth.data_size(self.width(), self.height())
+    }
+
+    /// Factor the bitmap is scaled down by, including the factor implied by the depth
+    pub fn scale_factor(&self) -> u32 {
+        self.scale.get_factor().max(self.depth.block_factor())
     }
 
     pub fn scaled_pixels(&self) -> u32 {
@@ -120,22 +182,48 @@ impl WzCanvas {
     }
 
     pub fn scaled_height(&self) -> u32 {
-        self.height() / self.scale.get_factor()
+        self.height().div_ceil(self.scale_factor())
     }
 
     pub fn scaled_width(&self) -> u32 {
-        self.width() / self.scale.get_factor()
+        self.width().div_ceil(self.scale_factor())
     }
 
-    pub fn scaled_bitmap_size(&self) -> u32 {
-        self.scaled_pixels() * self.depth.depth_size()
+    /// Size of the stored bitmap, which is scaled down
+    pub fn scaled_bitmap_size(&self) -> u64 {
+        self.depth
+            .data_size(self.scaled_width(), self.scaled_height())
     }
 
     pub fn data_len(&self) -> usize {
-        self.len.val as usize - 1
+        (self.len.val as usize).saturating_sub(1)
     }
 
     pub fn data_offset(&self) -> u64 {
         self.len.pos + 4 + 1
     }
+
+    /// Link to the canvas which stores the actual bitmap,
+    /// linked canvases only contain a placeholder
+    pub fn link(&self) -> Option<WzCanvasLink<'_>> {
+        self.property.as_ref()?.entries.0.iter().find_map(|entry| {
+            let WzPropValue::Str(ref link) = entry.val else {
+                return None;
+            };
+            let link = link.as_ref().as_str();
+            match entry.name.as_ref().as_str() {
+                "_inlink" => Some(WzCanvasLink::Inlink(link)),
+                "_outlink" | "source" => Some(WzCanvasLink::Outlink(link)),
+                _ => None,
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WzCanvasLink<'a> {
+    /// `_inlink`, path of the canvas in the same image like `stand/0`
+    Inlink(&'a str),
+    /// `_outlink` or `source`, path starting with the archive like `Mob/8800000.img/stand/0`
+    Outlink(&'a str),
 }
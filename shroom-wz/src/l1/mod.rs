@@ -1,9 +1,10 @@
 pub mod obj;
 pub mod tree;
-use std::rc::Rc;
+use std::sync::Arc;
 
 use binrw::{binread, BinRead, BinWrite, FilePtr};
 
+use crate::error::WzError;
 use crate::ty::WzStr;
 use crate::util::WzContext;
 
@@ -13,7 +14,10 @@ pub mod ser;
 pub mod sound;
 
 #[derive(Debug, Clone)]
-pub struct WzOffsetStr(Rc<WzStr>);
+pub struct WzOffsetStr {
+    pub offset: u32,
+    pub str: Arc<WzStr>,
+}
 
 impl BinRead for WzOffsetStr {
     type Args<'a> = WzContext<'a>;
@@ -26,8 +30,10 @@ impl BinRead for WzOffsetStr {
         // Read the offset
         let off = u32::read_options(reader, endian, ())?;
 
-        let str = args.read_offset_str(reader, off).unwrap(); // TODO err
-        Ok(Self(str))
+        let str = args
+            .read_offset_str(reader, off)
+            .map_err(WzError::into_binrw)?;
+        Ok(Self { offset: off, str })
     }
 }
 
@@ -36,11 +42,12 @@ impl BinWrite for WzOffsetStr {
 
     fn write_options<W: std::io::Write + std::io::Seek>(
         &self,
-        _writer: &mut W,
-        _endian: binrw::Endian,
+        writer: &mut W,
+        endian: binrw::Endian,
         _args: Self::Args<'_>,
     ) -> binrw::BinResult<()> {
-        todo!()
+        // The string itself must already be written at the offset
+        self.offset.write_options(writer, endian, ())
     }
 }
 
@@ -76,7 +83,7 @@ impl AsRef<WzStr> for WzUOLStr {
     fn as_ref(&self) -> &WzStr {
         match self {
             Self::Str(s) | Self::StrTypeName(s) => s,
-            Self::OffsetTypeName(s) => s.0.as_ref(),
+            Self::OffsetTypeName(s) => s.str.as_ref(),
             Self::Offset(s) => &s.value,
         }
     }
@@ -93,10 +100,27 @@ impl BinWrite for WzUOLStr {
 
     fn write_options<W: std::io::Write + std::io::Seek>(
         &self,
-        _writer: &mut W,
-        _endian: binrw::Endian,
-        _args: Self::Args<'_>,
+        writer: &mut W,
+        endian: binrw::Endian,
+        args: Self::Args<'_>,
     ) -> binrw::BinResult<()> {
-        todo!()
+        match self {
+            Self::Str(s) => {
+                0u8.write_options(writer, endian, ())?;
+                s.write_options(writer, endian, args)
+            }
+            Self::StrTypeName(s) => {
+                0x73u8.write_options(writer, endian, ())?;
+                s.write_options(writer, endian, args)
+            }
+            Self::Offset(s) => {
+                1u8.write_options(writer, endian, ())?;
+                s.ptr.write_options(writer, endian, ())
+            }
+            Self::OffsetTypeName(s) => {
+                0x1bu8.write_options(writer, endian, ())?;
+                s.write_options(writer, endian, args)
+            }
+        }
     }
 }
@@ -1,7 +1,7 @@
 use binrw::{BinRead, BinWrite};
 use derive_more::Unwrap;
 
-use crate::util::WzContext;
+use crate::{error::WzError, util::WzContext};
 
 use super::{
     canvas::WzCanvas,
@@ -28,6 +28,7 @@ impl BinRead for WzObject {
         endian: binrw::Endian,
         args: Self::Args<'_>,
     ) -> binrw::BinResult<Self> {
+        let pos = reader.stream_position()?;
         let ty_name = WzUOLStr::read_options(reader, endian, args)?;
         let ty_name = ty_name.as_ref();
 
@@ -39,15 +40,19 @@ impl BinRead for WzObject {
             b"Shape2D#Convex2D" => Self::Convex2D(WzConvex2D::read_options(reader, endian, args)?),
             b"Sound_DX8" => Self::SoundDX8(WzSound::read_options(reader, endian, args)?),
             _ => {
-                return Err(binrw::Error::Custom {
-                    pos: reader.stream_position().unwrap_or(0),
-                    err: Box::new(anyhow::format_err!("Invalid obj: {ty_name:?}")),
-                })
+                return Err(WzError::UnknownObject {
+                    pos,
+                    ty: ty_name.to_string(),
+                    path: None,
+                }
+                .into_binrw())
             }
         })
     }
 }
 
+/// Canvas and sound objects only write their header,
+/// the payload has to be appended by the caller
 impl BinWrite for WzObject {
     type Args<'a> = WzContext<'a>;
 
@@ -62,7 +67,7 @@ impl BinWrite for WzObject {
             WzObject::UOL(v) => v.write_options(writer, endian, args),
             WzObject::Vec2(v) => v.write_options(writer, endian, ()),
             WzObject::Convex2D(v) => v.write_options(writer, endian, args),
-            WzObject::Canvas(_v) => todo!(), //v.write_options(writer, endian, (args,)),
+            WzObject::Canvas(v) => v.write_options(writer, endian, args),
             WzObject::SoundDX8(v) => v.write_options(writer, endian, args),
         }
     }
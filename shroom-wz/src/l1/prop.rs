@@ -4,7 +4,7 @@ use binrw::{binread, binrw, BinRead, BinWrite, PosValue};
 use derive_more::Unwrap;
 
 use crate::{
-    ty::{WzF32, WzInt, WzLong, WzVec},
+    ty::{WzF32, WzInt, WzLong, WzStr, WzVec},
     util::WzContext,
 };
 
@@ -22,11 +22,11 @@ impl BinWrite for WzObjRef {
 
     fn write_options<W: std::io::Write + std::io::Seek>(
         &self,
-        _writer: &mut W,
-        _endian: binrw::Endian,
-        _args: Self::Args<'_>,
+        writer: &mut W,
+        endian: binrw::Endian,
+        args: Self::Args<'_>,
     ) -> binrw::BinResult<()> {
-        todo!()
+        self.len.val.write_options(writer, endian, args)
     }
 }
 
@@ -50,6 +50,7 @@ impl BinRead for WzObj {
     }
 }
 
+/// Only writes the length, the object itself is written by the `WzImgWriter`
 impl BinWrite for WzObj {
     type Args<'a> = ();
 
@@ -59,9 +60,7 @@ impl BinWrite for WzObj {
         endian: binrw::Endian,
         args: Self::Args<'_>,
     ) -> binrw::BinResult<()> {
-        self.len.val.write_options(writer, endian, args)?;
-        //TODO add data for object here
-        Ok(())
+        self.len.val.write_options(writer, endian, args)
     }
 }
 
@@ -69,35 +68,35 @@ impl BinWrite for WzObj {
 #[brw(little, import_raw(ctx: WzContext<'_>))]
 #[derive(Debug, Clone, Unwrap)]
 pub enum WzPropValue {
-    #[br(magic(0u8))]
+    #[brw(magic(0u8))]
     Null,
 
     // Short
-    #[br(magic(2u8))]
+    #[brw(magic(2u8))]
     Short1(i16),
-    #[br(magic(11u8))]
+    #[brw(magic(11u8))]
     Short2(i16),
 
     // Int
-    #[br(magic(3u8))]
+    #[brw(magic(3u8))]
     Int1(WzInt),
-    #[br(magic(19u8))]
+    #[brw(magic(19u8))]
     Int2(WzInt),
 
     // Long
-    #[br(magic(20u8))]
+    #[brw(magic(20u8))]
     Long(WzLong),
 
     // Floats
-    #[br(magic(4u8))]
+    #[brw(magic(4u8))]
     F32(WzF32),
-    #[br(magic(5u8))]
+    #[brw(magic(5u8))]
     F64(f64),
 
-    #[br(magic(8u8))]
+    #[brw(magic(8u8))]
     Str(#[brw(args_raw(ctx))] WzUOLStr),
 
-    #[br(magic(9u8))]
+    #[brw(magic(9u8))]
     Obj(WzObj),
 }
 
@@ -168,11 +167,12 @@ impl BinWrite for WzConvex2D {
         &self,
         writer: &mut W,
         _endian: binrw::Endian,
-        _args: Self::Args<'_>,
+        args: Self::Args<'_>,
     ) -> binrw::BinResult<()> {
         WzInt(self.0.len() as i32).write_le(writer)?;
         for v in self.0.iter() {
-            // TODO: write uol for vec2d here
+            WzUOLStr::StrTypeName(WzStr::new("Shape2D#Vector2D".to_string()))
+                .write_le_args(writer, args)?;
             v.write_le(writer)?;
         }
         Ok(())
@@ -1,11 +1,14 @@
 use std::{cell::RefCell, rc::Rc};
 
 use serde::{
-    ser::{SerializeMap, SerializeStruct},
+    ser::{Error, SerializeMap, SerializeStruct},
     Serialize,
 };
 
-use crate::file::{WzIO, WzImgReader};
+use crate::{
+    error::WzResult,
+    file::{WzIO, WzImgReader},
+};
 
 use super::{
     obj::WzObject,
@@ -66,7 +69,7 @@ impl<'r, R: WzIO> Serialize for WzValueSerializer<'r, R> {
             WzPropValue::Str(v) => ser.serialize_str(v.as_ref().as_str()),
             WzPropValue::Obj(obj) => {
                 let r = r.clone();
-                let object = { r.as_ref().borrow_mut().read_obj(obj).unwrap() };
+                let object = { r.as_ref().borrow_mut().read_obj(obj) }.map_err(S::Error::custom)?;
                 let obj_ser = WzObjectSerializer {
                     object: &object,
                     r,
@@ -138,7 +141,7 @@ pub struct WzImgSerializer<R> {
 }
 
 impl<R: WzIO> WzImgSerializer<R> {
-    pub fn new(mut img_reader: WzImgReader<R>, skip_canvas: bool) -> anyhow::Result<Self> {
+    pub fn new(mut img_reader: WzImgReader<R>, skip_canvas: bool) -> WzResult<Self> {
         let root = img_reader.read_root_obj()?;
         Ok(Self {
             img_reader: Rc::new(RefCell::new(img_reader)),
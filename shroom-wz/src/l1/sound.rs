@@ -3,16 +3,19 @@ use std::io::{Cursor, Seek};
 use binrw::{binrw, BinRead, BinReaderExt, BinWrite, PosValue};
 use uuid::uuid;
 
-use crate::{ty::WzInt, util::WzContext};
-
-pub fn custom_error<R: std::io::Read + std::io::Seek>(
-    r: &mut R,
-    err: anyhow::Error,
-) -> binrw::Error {
-    binrw::Error::Custom {
+use crate::{
+    error::{WzError, WzResult},
+    ty::WzInt,
+    util::WzContext,
+};
+
+fn unknown_sound<R: std::io::Seek>(r: &mut R, ty: String) -> binrw::Error {
+    WzError::UnknownObject {
         pos: r.stream_position().unwrap_or(0),
-        err: Box::new(err),
+        ty,
+        path: None,
     }
+    .into_binrw()
 }
 
 // TODO verify paddings
@@ -61,21 +64,24 @@ pub struct SoundHeader {
     pub fmt: SoundFormat,
 }
 
-impl BinRead for SoundHeader {
-    type Args<'a> = WzContext<'a>;
+impl SoundHeader {
+    /// Parses the header from the bytes written by `to_bytes`
+    pub fn from_bytes(data: &[u8]) -> WzResult<Self> {
+        Self::read_header(&mut Cursor::new(data)).map_err(|err| WzError::from_binrw(err, 0))
+    }
 
-    fn read_options<R: std::io::Read + std::io::Seek>(
-        reader: &mut R,
-        _endian: binrw::Endian,
-        _args: Self::Args<'_>,
-    ) -> binrw::BinResult<Self> {
+    pub fn to_bytes(&self) -> WzResult<Vec<u8>> {
+        let mut w = Cursor::new(Vec::new());
+        self.write_header(&mut w)
+            .map_err(|err| WzError::from_binrw(err, 0))?;
+        Ok(w.into_inner())
+    }
+
+    fn read_header<R: std::io::Read + std::io::Seek>(reader: &mut R) -> binrw::BinResult<Self> {
         let media_header: MediaHeader = reader.read_le()?;
         let major = media_header.major_type.0;
         if major != MEDIA_TYPE_STREAM {
-            return Err(custom_error(
-                reader,
-                anyhow::format_err!("Invalid sound major type: {major}"),
-            ));
+            return Err(unknown_sound(reader, format!("sound major type {major}")));
         }
 
         let mut hdr = [0u8; u8::MAX as usize];
@@ -87,28 +93,66 @@ impl BinRead for SoundHeader {
         Ok(match sub {
             MEDIA_SUBTYPE_MPEG1_PACKET => Self {
                 media_header,
-                fmt: SoundFormat::Mpeg1(hdr.try_into().unwrap()),
+                fmt: SoundFormat::Mpeg1(hdr.try_into().map_err(|_| {
+                    WzError::malformed(
+                        reader.stream_position().unwrap_or(0),
+                        format!("Invalid mpeg1 header size: {hdr_len}"),
+                    )
+                    .into_binrw()
+                })?),
             },
             MEDIA_SUBTYPE_WAVE => {
                 let mut sub = Cursor::new(hdr);
                 let wave: WaveHeader = sub.read_le()?;
-                sub.rewind().unwrap();
+                sub.rewind()?;
 
                 let fmt = match wave.format {
                     WAVE_FORMAT_PCM => SoundFormat::Pcm(wave),
                     WAVE_FORMAT_MP3 => SoundFormat::Mpeg3(sub.read_le()?),
-                    n => todo!("Invalid wave format: {n}"),
+                    n => return Err(unknown_sound(reader, format!("wave format {n}"))),
                 };
                 Self { media_header, fmt }
             }
-            _ => {
-                return Err(custom_error(
-                    reader,
-                    anyhow::format_err!("Unknown sound sub type: {major}"),
-                ))
-            }
+            _ => return Err(unknown_sound(reader, format!("sound sub type {sub}"))),
         })
     }
+
+    fn write_header<W: std::io::Write + std::io::Seek>(
+        &self,
+        writer: &mut W,
+    ) -> binrw::BinResult<()> {
+        self.media_header.write_le(writer)?;
+
+        // Header is prefixed with its length
+        let mut hdr = Cursor::new(Vec::with_capacity(u8::MAX as usize));
+        match &self.fmt {
+            SoundFormat::Mpeg1(data) => data.write_le(&mut hdr)?,
+            SoundFormat::Mpeg3(mp3) => mp3.write_le(&mut hdr)?,
+            SoundFormat::Pcm(wave) => wave.write_le(&mut hdr)?,
+        }
+        let hdr = hdr.into_inner();
+        let hdr_len = u8::try_from(hdr.len()).map_err(|_| {
+            WzError::malformed(
+                writer.stream_position().unwrap_or(0),
+                "Sound header too large",
+            )
+            .into_binrw()
+        })?;
+        hdr_len.write_le(writer)?;
+        hdr.write_le(writer)
+    }
+}
+
+impl BinRead for SoundHeader {
+    type Args<'a> = WzContext<'a>;
+
+    fn read_options<R: std::io::Read + std::io::Seek>(
+        reader: &mut R,
+        _endian: binrw::Endian,
+        _args: Self::Args<'_>,
+    ) -> binrw::BinResult<Self> {
+        Self::read_header(reader)
+    }
 }
 
 impl BinWrite for SoundHeader {
@@ -116,11 +160,11 @@ impl BinWrite for SoundHeader {
 
     fn write_options<W: std::io::Write + std::io::Seek>(
         &self,
-        _writer: &mut W,
+        writer: &mut W,
         _endian: binrw::Endian,
         _args: Self::Args<'_>,
     ) -> binrw::BinResult<()> {
-        todo!()
+        self.write_header(writer)
     }
 }
 
@@ -136,8 +180,6 @@ pub struct WaveHeader {
     pub avg_bytes_per_sec: u32,
     pub block_align: u16,
     pub bits_per_sample: u16,
-    // Align tail, struct is not packed
-    #[bw(pad_size_to = 4)]
     pub extra_size: u16,
 }
 
@@ -145,6 +187,32 @@ impl WaveHeader {
     pub fn is_valid_header_size(&self, header_size: usize) -> bool {
         WAVE_HEADER_SIZE + (self.extra_size as usize) == header_size
     }
+
+    /// RIFF header of a `.wav` file with `data_len` bytes of samples
+    pub fn riff_header(&self, data_len: u32) -> [u8; PCM_HEADER_SIZE] {
+        let mut hdr = [0; PCM_HEADER_SIZE];
+        let fields: [&[u8]; 13] = [
+            b"RIFF",
+            &(PCM_HEADER_SIZE as u32 - 8 + data_len).to_le_bytes(),
+            b"WAVE",
+            b"fmt ",
+            &16u32.to_le_bytes(),
+            &self.format.to_le_bytes(),
+            &self.channels.to_le_bytes(),
+            &self.samples_per_sec.to_le_bytes(),
+            &self.avg_bytes_per_sec.to_le_bytes(),
+            &self.block_align.to_le_bytes(),
+            &self.bits_per_sample.to_le_bytes(),
+            b"data",
+            &data_len.to_le_bytes(),
+        ];
+        let mut ix = 0;
+        for field in fields {
+            hdr[ix..ix + field.len()].copy_from_slice(field);
+            ix += field.len();
+        }
+        hdr
+    }
 }
 
 // see MPEGLAYER3WAVEFORMAT
@@ -154,12 +222,10 @@ impl WaveHeader {
 #[derive(Debug, Clone)]
 pub struct Mpeg3WaveHeader {
     pub wav: WaveHeader,
-    #[bw(pad_size_to = 4)]
     pub id: u16,
     pub flags: u32,
     pub block_size: u16,
     pub frames_per_block: u16,
-    #[bw(pad_size_to = 4)]
     pub codec_delay: u16,
 }
 
@@ -254,6 +320,7 @@ impl BinWrite for SoundFormat {
     }
 }*/
 
+/// Sound header, when written the caller has to append the sound data
 #[binrw]
 #[brw(little, import_raw(ctx: WzContext<'_>))]
 #[derive(Debug, Clone)]
@@ -268,6 +335,12 @@ pub struct WzSound {
 }
 
 impl WzSound {
+    /// Size of the sound data in the image
+    pub fn raw_size(&self) -> usize {
+        self.size.0 as usize
+    }
+
+    /// Size of the sound as a file, PCM sounds get a RIFF header
     pub fn data_size(&self) -> usize {
         let extra = match self.header.fmt {
             SoundFormat::Mpeg3(_) => 0,
@@ -276,4 +349,96 @@ impl WzSound {
         };
         (self.size.0 as usize) + extra
     }
+
+    pub fn is_pcm(&self) -> bool {
+        matches!(self.header.fmt, SoundFormat::Pcm(_))
+    }
+
+    /// File extension without the dot
+    pub fn file_ext(&self) -> &'static str {
+        if self.is_pcm() {
+            "wav"
+        } else {
+            "mp3"
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        if self.is_pcm() {
+            "audio/wav"
+        } else {
+            "audio/mpeg"
+        }
+    }
+
+    /// Turns the raw sound data into a playable file, PCM samples get a RIFF header,
+    /// MPEG streams are cut to start at the first frame
+    pub fn to_file_bytes(&self, data: &[u8]) -> Vec<u8> {
+        match &self.header.fmt {
+            SoundFormat::Pcm(wave) => {
+                let mut file = Vec::with_capacity(PCM_HEADER_SIZE + data.len());
+                file.extend_from_slice(&wave.riff_header(data.len() as u32));
+                file.extend_from_slice(data);
+                file
+            }
+            SoundFormat::Mpeg1(_) | SoundFormat::Mpeg3(_) => {
+                let start = data
+                    .windows(2)
+                    .position(|w| w[0] == 0xFF && w[1] & 0xE0 == 0xE0)
+                    .unwrap_or(0);
+                data[start..].to_vec()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{
+        MediaHeader, SoundFormat, SoundHeader, WaveHeader, GUID, MEDIA_SUBTYPE_WAVE,
+        MEDIA_TYPE_STREAM,
+    };
+
+    /// Header of a mono 8 bit PCM sound
+    pub(crate) fn pcm_header() -> SoundHeader {
+        SoundHeader {
+            media_header: MediaHeader {
+                unknown1: 0,
+                major_type: GUID(MEDIA_TYPE_STREAM),
+                sub_type: GUID(MEDIA_SUBTYPE_WAVE),
+                sample_size: 0,
+                format_type: GUID(uuid::Uuid::nil()),
+            },
+            fmt: SoundFormat::Pcm(WaveHeader {
+                format: 1,
+                channels: 1,
+                samples_per_sec: 8000,
+                avg_bytes_per_sec: 8000,
+                block_align: 1,
+                bits_per_sample: 8,
+                extra_size: 0,
+            }),
+        }
+    }
+
+    #[test]
+    fn riff_header() {
+        let wave = WaveHeader {
+            format: 1,
+            channels: 2,
+            samples_per_sec: 44100,
+            avg_bytes_per_sec: 44100 * 4,
+            block_align: 4,
+            bits_per_sample: 16,
+            extra_size: 0,
+        };
+        let hdr = wave.riff_header(8);
+        assert_eq!(&hdr[..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(hdr[4..8].try_into().unwrap()), 44);
+        assert_eq!(&hdr[8..16], b"WAVEfmt ");
+        assert_eq!(u16::from_le_bytes([hdr[22], hdr[23]]), 2);
+        assert_eq!(u32::from_le_bytes(hdr[24..28].try_into().unwrap()), 44100);
+        assert_eq!(&hdr[36..40], b"data");
+        assert_eq!(u32::from_le_bytes(hdr[40..44].try_into().unwrap()), 8);
+    }
 }
@@ -1,8 +1,12 @@
 use std::collections::VecDeque;
 
-use id_tree::Tree;
+use id_tree::{NodeId, Tree};
 
-use crate::{l0::WzImgHeader, val::WzValue};
+use crate::{
+    error::{WzError, WzResult},
+    l0::WzImgHeader,
+    val::WzValue,
+};
 
 pub struct WzValueNode<'a> {
     pub name: &'a str,
@@ -62,4 +66,27 @@ impl WzValueTree {
             tree
         })
     }
+
+    /// Path of the node from the root like `stand/0`
+    pub fn node_path(&self, node: &NodeId) -> Option<String> {
+        let tree = self.borrow_tree();
+        let mut parts = vec![tree.get(node).ok()?.data().name];
+        for ancestor in tree.ancestors(node).ok()? {
+            parts.push(ancestor.data().name);
+        }
+        // Skip the root node
+        parts.pop();
+        parts.reverse();
+        Some(parts.join("/"))
+    }
+
+    /// Value of the node, if it's a link the target is returned
+    pub fn resolve(&self, node: &NodeId) -> WzResult<&WzValue> {
+        let path = self.node_path(node).ok_or_else(|| WzError::NotFound {
+            path: format!("{node:?}"),
+        })?;
+        self.borrow_root()
+            .get_path_resolved(&path)?
+            .ok_or(WzError::NotFound { path })
+    }
 }
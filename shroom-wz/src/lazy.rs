@@ -0,0 +1,330 @@
+use std::cell::{OnceCell, RefCell};
+
+use indexmap::IndexMap;
+
+use crate::{
+    canvas::Canvas,
+    error::{WzError, WzResult},
+    file::{WzIO, WzImgReader},
+    l1::{
+        canvas::WzCanvas,
+        obj::WzObject,
+        prop::{WzObj, WzPropValue, WzProperty},
+    },
+    val::{CanvasVal, ObjectVal, SoundVal, Vec2Val, Vex2Val, WzValue},
+};
+
+/// Image which reads the properties on demand,
+/// only the objects along an accessed path are read and then cached
+pub struct WzLazyImg<R> {
+    r: RefCell<WzImgReader<R>>,
+}
+
+impl<R: WzIO> WzLazyImg<R> {
+    pub fn new(r: WzImgReader<R>) -> Self {
+        Self { r: RefCell::new(r) }
+    }
+
+    pub fn into_inner(self) -> WzImgReader<R> {
+        self.r.into_inner()
+    }
+
+    /// Reads the root object, without any of the children
+    pub fn root(&self) -> WzResult<LazyValue<'_, R>> {
+        let obj = self.r.borrow().root_obj();
+        LazyValue::read_obj(&self.r, &obj)
+    }
+}
+
+pub struct LazyEntry<'r, R> {
+    val: WzPropValue,
+    cached: OnceCell<LazyValue<'r, R>>,
+}
+
+pub struct LazyObject<'r, R> {
+    r: &'r RefCell<WzImgReader<R>>,
+    entries: IndexMap<String, LazyEntry<'r, R>>,
+}
+
+impl<'r, R: WzIO> LazyObject<'r, R> {
+    fn new(r: &'r RefCell<WzImgReader<R>>, prop: &WzProperty) -> Self {
+        let entries = prop
+            .entries
+            .0
+            .iter()
+            .map(|entry| {
+                (
+                    entry.name.as_ref().to_string(),
+                    LazyEntry {
+                        val: entry.val.clone(),
+                        cached: OnceCell::new(),
+                    },
+                )
+            })
+            .collect();
+        Self { r, entries }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn keys(&self) -> indexmap::map::Keys<'_, String, LazyEntry<'r, R>> {
+        self.entries.keys()
+    }
+
+    /// Gets the child, reading it on the first access
+    pub fn get(&self, index: &str) -> WzResult<Option<&LazyValue<'r, R>>> {
+        let Some(entry) = self.entries.get(index) else {
+            return Ok(None);
+        };
+        if let Some(v) = entry.cached.get() {
+            return Ok(Some(v));
+        }
+
+        let v = LazyValue::read_val(self.r, &entry.val).map_err(|err| err.in_parent(index))?;
+        Ok(Some(entry.cached.get_or_init(|| v)))
+    }
+
+    pub fn must_get(&self, index: &str) -> WzResult<&LazyValue<'r, R>> {
+        self.get(index)?.ok_or_else(|| WzError::NotFound {
+            path: index.to_string(),
+        })
+    }
+
+    /// Iterates over all children, which reads all of them
+    pub fn iter(&self) -> impl Iterator<Item = WzResult<(&str, &LazyValue<'r, R>)>> {
+        self.keys().map(|k| Ok((k.as_str(), self.must_get(k)?)))
+    }
+
+    /// Reads the whole object
+    pub fn to_value(&self) -> WzResult<ObjectVal> {
+        let mut map = IndexMap::new();
+        for kv in self.iter() {
+            let (k, v) = kv?;
+            map.insert(k.to_string(), v.to_value().map_err(|err| err.in_parent(k))?);
+        }
+        Ok(ObjectVal(map))
+    }
+}
+
+pub struct LazyCanvas<'r, R> {
+    r: &'r RefCell<WzImgReader<R>>,
+    pub canvas: WzCanvas,
+    pub sub: Option<LazyObject<'r, R>>,
+}
+
+impl<'r, R: WzIO> LazyCanvas<'r, R> {
+    pub fn read_canvas(&self) -> WzResult<Canvas> {
+        self.r.borrow_mut().read_canvas(&self.canvas)
+    }
+}
+
+/// Lazy counterpart of `WzValue`, objects and canvas properties are
+/// resolved when they are accessed, everything else is read directly
+pub enum LazyValue<'r, R> {
+    Object(LazyObject<'r, R>),
+    Canvas(LazyCanvas<'r, R>),
+    Value(WzValue),
+}
+
+impl<'r, R: WzIO> LazyValue<'r, R> {
+    fn read_val(r: &'r RefCell<WzImgReader<R>>, val: &WzPropValue) -> WzResult<Self> {
+        match val {
+            WzPropValue::Obj(obj) => Self::read_obj(r, obj),
+            val => Ok(Self::Value(WzValue::read_val(&mut r.borrow_mut(), val)?)),
+        }
+    }
+
+    fn read_obj(r: &'r RefCell<WzImgReader<R>>, obj: &WzObj) -> WzResult<Self> {
+        let obj = r.borrow_mut().read_obj(obj)?;
+        Ok(match obj {
+            WzObject::Property(prop) => Self::Object(LazyObject::new(r, &prop)),
+            WzObject::Canvas(canvas) => Self::Canvas(LazyCanvas {
+                r,
+                sub: canvas
+                    .property
+                    .as_ref()
+                    .map(|prop| LazyObject::new(r, prop)),
+                canvas,
+            }),
+            WzObject::UOL(link) => Self::Value(WzValue::Link(link.entries.as_ref().to_string())),
+            WzObject::Vec2(vec2) => Self::Value(WzValue::Vec(vec2.into())),
+            WzObject::Convex2D(vex) => Self::Value(WzValue::Convex(Vex2Val::from(&vex))),
+            WzObject::SoundDX8(sound) => Self::Value(WzValue::Sound(SoundVal {
+                sound,
+                payload: None,
+            })),
+        })
+    }
+
+    /// Gets the value at the path, only reading the objects along the path
+    pub fn get_path(&self, path: &str) -> WzResult<Option<&LazyValue<'r, R>>> {
+        let mut cur = self;
+        let mut parent_len = 0;
+        for part in path.split('/') {
+            let cur_obj = match cur {
+                LazyValue::Object(v) => v,
+                // We get the next object from the canvas If there's one
+                LazyValue::Canvas(LazyCanvas { sub: Some(v), .. }) => v,
+                _ => return Ok(None),
+            };
+
+            let next = cur_obj.get(part).map_err(|err| match parent_len {
+                0 => err,
+                n => err.in_parent(&path[..n - 1]),
+            })?;
+            match next {
+                Some(v) => cur = v,
+                None => return Ok(None),
+            }
+            parent_len += part.len() + 1;
+        }
+
+        Ok(Some(cur))
+    }
+
+    /// Reads the whole value, like `WzValue::read` does
+    pub fn to_value(&self) -> WzResult<WzValue> {
+        Ok(match self {
+            LazyValue::Object(v) => WzValue::Object(v.to_value()?),
+            LazyValue::Canvas(v) => WzValue::Canvas(CanvasVal {
+                canvas: v.canvas.clone(),
+                sub: match v.sub.as_ref() {
+                    Some(sub) => Some(Box::new(WzValue::Object(sub.to_value()?))),
+                    None => None,
+                },
+                payload: None,
+            }),
+            LazyValue::Value(v) => v.clone(),
+        })
+    }
+
+    pub fn as_value(&self) -> Option<&WzValue> {
+        match self {
+            LazyValue::Value(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&LazyObject<'r, R>> {
+        match self {
+            LazyValue::Object(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_canvas(&self) -> Option<&LazyCanvas<'r, R>> {
+        match self {
+            LazyValue::Canvas(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_f32(&self) -> Option<f32> {
+        self.as_value()?.as_f32()
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        self.as_value()?.as_f64()
+    }
+
+    pub fn as_i16(&self) -> Option<i16> {
+        self.as_value()?.as_i16()
+    }
+
+    pub fn as_i32(&self) -> Option<i32> {
+        self.as_value()?.as_i32()
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        self.as_value()?.as_i64()
+    }
+
+    pub fn as_string(&self) -> Option<&str> {
+        self.as_value()?.as_string()
+    }
+
+    pub fn as_vec(&self) -> Option<&Vec2Val> {
+        self.as_value()?.as_vec()
+    }
+
+    pub fn as_convex(&self) -> Option<&Vex2Val> {
+        self.as_value()?.as_convex()
+    }
+
+    pub fn as_sound(&self) -> Option<&SoundVal> {
+        self.as_value()?.as_sound()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::{
+        crypto::WzCrypto,
+        link::tests::img,
+        util::{WzContext, WzStrTable},
+        val::WzValue,
+        version::{WzRegion, WzVersion},
+        writer::tests::int_img,
+        WzReader,
+    };
+
+    use super::{LazyValue, WzLazyImg};
+
+    #[test]
+    fn lazy_path() {
+        let region = WzRegion::GMS;
+        let crypto = WzCrypto::from_region(region, WzVersion(95), 0);
+        let str_table = WzStrTable::default();
+        let ctx = WzContext::new(&crypto, &str_table);
+
+        let data = img(
+            ctx,
+            &[
+                ("info", int_img(region, 5)),
+                (
+                    "stand",
+                    img(
+                        ctx,
+                        &[("0", int_img(region, 120)), ("1", int_img(region, 150))],
+                    ),
+                ),
+            ],
+        );
+
+        let mut r = WzReader::open_img(Cursor::new(data), region, WzVersion(95)).unwrap();
+        let expected = WzValue::read(&mut r.root_img_reader().unwrap()).unwrap();
+        let img = WzLazyImg::new(r.root_img_reader().unwrap());
+        let root = img.root().unwrap();
+
+        let level = root.get_path("stand/1/level").unwrap().unwrap();
+        assert_eq!(level.as_i32(), Some(150));
+        assert!(root.get_path("stand/2/level").unwrap().is_none());
+        assert!(root.get_path("stand/1/level/x").unwrap().is_none());
+
+        // Only the accessed path is read
+        let LazyValue::Object(obj) = &root else {
+            panic!("root must be an object");
+        };
+        assert!(obj.entries["info"].cached.get().is_none());
+        let stand = obj.entries["stand"].cached.get().unwrap();
+        assert!(stand.as_object().unwrap().entries["0"]
+            .cached
+            .get()
+            .is_none());
+
+        let val = root.to_value().unwrap();
+        assert_eq!(
+            val.get_path("info/level").unwrap().as_i32(),
+            expected.get_path("info/level").unwrap().as_i32()
+        );
+        assert_eq!(val.get_path("stand/0/level").unwrap().as_i32(), Some(120));
+    }
+}
@@ -1,17 +1,28 @@
 pub mod canvas;
 pub mod crypto;
+pub mod error;
+pub mod extract;
 pub mod file;
 pub mod keys;
 pub mod l0;
 pub mod l1;
+pub mod lazy;
+pub mod link;
+pub mod list;
+pub mod namespace;
+pub mod nx;
+pub mod sound;
 pub mod ty;
 pub mod util;
 pub mod val;
 pub mod version;
+pub mod writer;
 
+pub use error::{WzError, WzResult};
 #[cfg(feature = "mmap")]
 pub use file::mmap::WzReaderMmap;
-pub use file::WzReader;
+pub use file::{WzReader, WzReaderShared};
+pub use writer::WzWriter;
 
 #[cfg(test)]
 mod tests {
@@ -97,7 +108,7 @@ mod tests {
         let tree = WzTree::from_reader(&mut sound, None).unwrap();
         let mob = tree.get_img_by_path("BgmGL.img").unwrap();
 
-        let mut img = sound.img_reader(&mob).unwrap();
+        let mut img = sound.img_reader(mob).unwrap();
         let val = WzValue::read(&mut img).unwrap();
 
         let sound = val
@@ -106,7 +117,7 @@ mod tests {
             .as_sound()
             .unwrap();
 
-        let sound_data = sound.read_data(&mut img).unwrap();
+        let sound_data = sound.to_file_bytes(&mut img).unwrap().data;
         let dec = rodio::Decoder::new(std::io::Cursor::new(sound_data)).unwrap();
         let (_stream, stream_handle) = OutputStream::try_default().unwrap();
         stream_handle.play_raw(dec.convert_samples()).unwrap();
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+
+use crate::{
+    canvas::Canvas,
+    error::{WzError, WzResult},
+    file::WzIO,
+    WzReader,
+};
+
+/// Limit for chains of `_outlink`s across archives
+pub(crate) const MAX_OUTLINKS: usize = 16;
+
+/// Canvas read by path, either the bitmap or the `_outlink` to follow
+pub enum WzLinkedCanvas {
+    Bitmap(Canvas),
+    Outlink(String),
+}
+
+/// Reads the canvas of an `_outlink` or `source` path like `Mob/8800000.img/stand/0`
+pub trait WzOutlinkResolver {
+    fn read_outlink(&mut self, path: &str) -> WzResult<Canvas>;
+}
+
+/// Opened archives keyed by their name like `Mob`, to resolve `_outlink`s
+pub struct WzArchiveSet<R> {
+    archives: HashMap<String, WzReader<R>>,
+}
+
+impl<R> Default for WzArchiveSet<R> {
+    fn default() -> Self {
+        Self {
+            archives: HashMap::new(),
+        }
+    }
+}
+
+impl<R: WzIO> WzArchiveSet<R> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, name: impl Into<String>, r: WzReader<R>) {
+        self.archives.insert(name.into(), r);
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut WzReader<R>> {
+        self.archives.get_mut(name)
+    }
+}
+
+/// Follows the chain of `_outlink`s from `path`,
+/// `read` reads the canvas at a path which is either the bitmap or the next link
+pub(crate) fn follow_outlinks(
+    path: &str,
+    mut read: impl FnMut(&str) -> WzResult<WzLinkedCanvas>,
+) -> WzResult<Canvas> {
+    let mut path = path.to_string();
+    for _ in 0..MAX_OUTLINKS {
+        match read(&path)? {
+            WzLinkedCanvas::Bitmap(canvas) => return Ok(canvas),
+            WzLinkedCanvas::Outlink(link) => path = link,
+        }
+    }
+
+    Err(WzError::InvalidLink {
+        link: path,
+        msg: "too many links",
+        path: None,
+    })
+}
+
+impl<R: WzIO> WzOutlinkResolver for WzArchiveSet<R> {
+    fn read_outlink(&mut self, path: &str) -> WzResult<Canvas> {
+        follow_outlinks(path, |path| {
+            let (archive, rest) = path.split_once('/').ok_or_else(|| WzError::NotFound {
+                path: path.to_string(),
+            })?;
+            let r = self
+                .archives
+                .get_mut(archive)
+                .ok_or_else(|| WzError::NotFound {
+                    path: archive.to_string(),
+                })?;
+
+            r.read_canvas_path(rest)
+                .map_err(|err| err.in_parent(archive))
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::{Cursor, Write};
+
+    use binrw::{BinWrite, PosValue};
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use crate::{
+        crypto::WzCrypto,
+        l1::{
+            canvas::{WzCanvas, WzCanvasDepth, WzCanvasScaling},
+            obj::WzObject,
+            prop::{WzPropValue, WzProperty, WzPropertyEntry},
+            WzUOLStr,
+        },
+        ty::{WzInt, WzStr, WzVec},
+        util::{WzContext, WzStrTable},
+        version::{WzRegion, WzVersion},
+        writer::WzWriterDir,
+        WzReader, WzWriter,
+    };
+
+    use super::WzArchiveSet;
+
+    fn str(s: &str) -> WzUOLStr {
+        WzUOLStr::Str(WzStr::new(s.to_string()))
+    }
+
+    /// 1x1 canvas with the pixel and the string properties
+    pub(crate) fn canvas(ctx: WzContext, pixel: [u8; 4], props: &[(&str, &str)]) -> Vec<u8> {
+        let mut z = ZlibEncoder::new(Vec::new(), Compression::default());
+        z.write_all(&pixel).unwrap();
+        let data = z.finish().unwrap();
+
+        let property = (!props.is_empty()).then(|| WzProperty {
+            unknown: 0,
+            entries: WzVec(
+                props
+                    .iter()
+                    .map(|(k, v)| WzPropertyEntry {
+                        name: str(k),
+                        val: WzPropValue::Str(str(v)),
+                    })
+                    .collect(),
+            ),
+        });
+        let mut w = Cursor::new(Vec::new());
+        WzUOLStr::StrTypeName(WzStr::new("Canvas".to_string()))
+            .write_le_args(&mut w, ctx)
+            .unwrap();
+        WzObject::Canvas(WzCanvas {
+            unknown: 0,
+            has_property: property.is_some() as u8,
+            property,
+            width: WzInt(1),
+            height: WzInt(1),
+            depth: WzCanvasDepth::BGRA8888,
+            scale: WzCanvasScaling(0),
+            unknown1: 0,
+            len: PosValue {
+                val: data.len() as u32 + 1,
+                pos: 0,
+            },
+        })
+        .write_le_args(&mut w, ctx)
+        .unwrap();
+        w.write_all(&[0]).unwrap();
+        w.write_all(&data).unwrap();
+        w.into_inner()
+    }
+
+    pub(crate) fn img(ctx: WzContext, entries: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut w = Cursor::new(Vec::new());
+        WzUOLStr::StrTypeName(WzStr::new("Property".to_string()))
+            .write_le_args(&mut w, ctx)
+            .unwrap();
+        0u16.write_le(&mut w).unwrap();
+        WzInt(entries.len() as i32).write_le(&mut w).unwrap();
+        for (name, obj) in entries {
+            str(name).write_le_args(&mut w, ctx).unwrap();
+            9u8.write_le(&mut w).unwrap();
+            (obj.len() as u32).write_le(&mut w).unwrap();
+            w.write_all(obj).unwrap();
+        }
+        w.into_inner()
+    }
+
+    fn archive(img_name: &str, data: Vec<u8>) -> WzReader<Cursor<Vec<u8>>> {
+        let mut root = WzWriterDir::new("Root");
+        root.add_img(img_name, data);
+        let mut w = Cursor::new(Vec::new());
+        WzWriter::new(WzRegion::GMS, WzVersion(95))
+            .write(&mut w, &root)
+            .unwrap();
+        w.set_position(0);
+        WzReader::open(w, WzRegion::GMS, WzVersion(95)).unwrap()
+    }
+
+    #[test]
+    fn canvas_links() {
+        let crypto = WzCrypto::from_region(WzRegion::GMS, WzVersion(95), 0);
+        let str_table = WzStrTable::default();
+        let ctx = WzContext::new(&crypto, &str_table);
+        let placeholder = [0, 0, 0, 0];
+        let pixel = [1, 2, 3, 4];
+
+        let mob = img(
+            ctx,
+            &[
+                ("real", canvas(ctx, pixel, &[])),
+                ("in", canvas(ctx, placeholder, &[("_inlink", "real")])),
+                ("placeholder", canvas(ctx, placeholder, &[])),
+            ],
+        );
+        let map = img(
+            ctx,
+            &[
+                (
+                    "out",
+                    canvas(ctx, placeholder, &[("_outlink", "Mob/a.img/in")]),
+                ),
+                (
+                    "src",
+                    canvas(ctx, placeholder, &[("source", "Mob/a.img/real")]),
+                ),
+                (
+                    "broken",
+                    canvas(ctx, placeholder, &[("_inlink", "missing")]),
+                ),
+            ],
+        );
+
+        let mut archives = WzArchiveSet::new();
+        archives.insert("Mob", archive("a.img", mob));
+        let mut map = archive("b.img", map);
+
+        let mob = archives.get_mut("Mob").unwrap();
+        let hdr = mob.img_header_by_path("a.img").unwrap();
+        let mut img = mob.img_reader(&hdr).unwrap();
+        let root = img.read_root_obj().unwrap();
+        let mut read = |name| {
+            let WzObject::Canvas(canvas) = img.read_path(&root, name).unwrap() else {
+                panic!("must be a canvas");
+            };
+            img.read_canvas(&canvas).unwrap().to_rgba_image().unwrap()
+        };
+        let expected = read("real");
+        assert_ne!(expected, read("placeholder"));
+        assert_eq!(expected, read("in"));
+
+        let hdr = map.img_header_by_path("b.img").unwrap();
+        let mut img = map.img_reader(&hdr).unwrap();
+        let root = img.read_root_obj().unwrap();
+        for name in ["out", "src"] {
+            let WzObject::Canvas(canvas) = img.read_path(&root, name).unwrap() else {
+                panic!("must be a canvas");
+            };
+            // Without the other archives only the placeholder can be read
+            let data = img.read_canvas(&canvas).unwrap().to_rgba_image().unwrap();
+            assert_ne!(data, expected);
+
+            let data = img.read_canvas_with(&canvas, &mut archives).unwrap();
+            assert_eq!(data.to_rgba_image().unwrap(), expected);
+        }
+
+        let WzObject::Canvas(canvas) = img.read_path(&root, "broken").unwrap() else {
+            panic!("must be a canvas");
+        };
+        assert!(img.read_canvas(&canvas).is_err());
+    }
+}
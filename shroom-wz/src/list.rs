@@ -0,0 +1,134 @@
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{BufRead, BufReader, Write},
+    path::Path,
+};
+
+use crate::{
+    crypto::WzCrypto,
+    error::{WzError, WzResult},
+    util::{read_vec, BufReadExt},
+    version::{WzRegion, WzVersion},
+};
+
+/// `List.wz` of older clients, which lists the images with encrypted canvas data
+///
+/// Every entry is an i32 length followed by the encrypted UTF-16 path and an encrypted null
+#[derive(Debug, Default, Clone)]
+pub struct WzList {
+    entries: Vec<String>,
+    lookup: HashSet<String>,
+}
+
+impl WzList {
+    pub fn new(entries: Vec<String>) -> Self {
+        let lookup = entries.iter().map(|e| normalize_path(e)).collect();
+        Self { entries, lookup }
+    }
+
+    pub fn open_file(path: impl AsRef<Path>, region: WzRegion) -> WzResult<Self> {
+        // The version only matters for offsets
+        let crypto = WzCrypto::from_region(region, WzVersion(0), 0);
+        Self::read(&mut BufReader::new(File::open(path)?), &crypto)
+    }
+
+    pub fn read<R: BufRead>(r: &mut R, crypto: &WzCrypto) -> WzResult<Self> {
+        let mut entries = Vec::new();
+        let mut pos = 0;
+        while !r.fill_buf()?.is_empty() {
+            let len = r.read_u32().map_err(|err| WzError::io_at(err, pos))? as usize;
+            // Path plus the encrypted null
+            let mut buf = read_vec(r, (len + 1) * 2).map_err(|err| WzError::io_at(err, pos))?;
+            crypto.transform(buf.as_mut_slice().into());
+
+            let chars = buf[..len * 2]
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]));
+            entries.push(
+                String::from_utf16(&chars.collect::<Vec<_>>())
+                    .map_err(|_| WzError::InvalidString { pos, path: None })?,
+            );
+            pos += 4 + buf.len() as u64;
+        }
+
+        // The client stores the last entry with a trailing '/' instead of 'g'
+        if let Some(last) = entries.last_mut() {
+            if last.ends_with(".im/") {
+                last.pop();
+                last.push('g');
+            }
+        }
+
+        Ok(Self::new(entries))
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W, crypto: &WzCrypto) -> WzResult<()> {
+        for entry in self.entries.iter() {
+            let mut buf: Vec<u8> = entry
+                .encode_utf16()
+                .chain(Some(0))
+                .flat_map(u16::to_le_bytes)
+                .collect();
+            let len = buf.len() / 2 - 1;
+            crypto.transform(buf.as_mut_slice().into());
+
+            w.write_all(&(len as u32).to_le_bytes())?;
+            w.write_all(&buf)?;
+        }
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// Checks if the image path like `Map/Obj/acc1.img` is listed
+    pub fn contains(&self, path: &str) -> bool {
+        self.lookup.contains(&normalize_path(path))
+    }
+}
+
+fn normalize_path(path: &str) -> String {
+    path.trim_matches('/')
+        .replace('\\', "/")
+        .to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::{
+        crypto::WzCrypto,
+        version::{WzRegion, WzVersion},
+    };
+
+    use super::WzList;
+
+    #[test]
+    fn read_write() {
+        let crypto = WzCrypto::from_region(WzRegion::GMS, WzVersion(0), 0);
+        let list = WzList::new(vec![
+            "Map/Obj/acc1.img".to_string(),
+            "Mob/0100100.img".to_string(),
+        ]);
+
+        let mut w = Cursor::new(Vec::new());
+        list.write(&mut w, &crypto).unwrap();
+        w.set_position(0);
+        let read = WzList::read(&mut w, &crypto).unwrap();
+        assert_eq!(read.entries(), list.entries());
+        assert!(read.contains("/map/obj/acc1.img"));
+        assert!(read.contains("Mob\\0100100.img"));
+        assert!(!read.contains("Mob/0100101.img"));
+
+        // Broken last entry of the client
+        let list = WzList::new(vec!["Map/Obj/acc1.im/".to_string()]);
+        let mut w = Cursor::new(Vec::new());
+        list.write(&mut w, &crypto).unwrap();
+        w.set_position(0);
+        let read = WzList::read(&mut w, &crypto).unwrap();
+        assert!(read.contains("Map/Obj/acc1.img"));
+    }
+}
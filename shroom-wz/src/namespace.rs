@@ -0,0 +1,299 @@
+use std::{collections::HashMap, fs, fs::File, io::BufReader, path::Path, sync::Arc};
+
+use crate::{
+    canvas::Canvas,
+    error::{WzError, WzResult},
+    file::{WzIO, WzImgReader},
+    l0::{WzDirHeader, WzDirNode, WzImgHeader},
+    link::{follow_outlinks, WzOutlinkResolver},
+    list::WzList,
+    util::SubReader,
+    val::WzValue,
+    version::WzRegion,
+    WzReader,
+};
+
+/// Archive which holds the paths without a category
+const BASE: &str = "Base";
+
+/// Category of an archive file, split archives like `Mob2` or `Mob001` belong to `Mob`
+pub fn archive_category(name: &str) -> &str {
+    let category = name.trim_end_matches(|c: char| c.is_ascii_digit());
+    let category = category.strip_suffix('_').unwrap_or(category);
+    if category.is_empty() {
+        name
+    } else {
+        category
+    }
+}
+
+/// Orders split archives by category and the number, so `Mob10` follows `Mob2`
+fn archive_sort_key(name: &str) -> (&str, u64, &str) {
+    let digits = name.len() - name.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    let number = name[name.len() - digits..].parse().unwrap_or(0);
+    (archive_category(name), number, name)
+}
+
+/// All archives of a client as one path space like `Mob/100100.img/info/level`
+///
+/// The first part of a path is the category, which is routed to its archives.
+/// Paths without a known category are looked up in `Base.wz`.
+pub struct WzNamespace<R> {
+    archives: HashMap<String, Vec<WzReader<R>>>,
+}
+
+impl<R> Default for WzNamespace<R> {
+    fn default() -> Self {
+        Self {
+            archives: HashMap::new(),
+        }
+    }
+}
+
+impl WzNamespace<BufReader<File>> {
+    /// Opens every `*.wz` in the client directory, `List.wz` is used
+    /// to decide which canvases are encrypted
+    pub fn open_dir(dir: impl AsRef<Path>, region: WzRegion) -> WzResult<Self> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let is_wz = path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("wz"));
+            if let (true, Some(stem)) = (is_wz, path.file_stem().and_then(|s| s.to_str())) {
+                files.push((stem.to_string(), path.clone()));
+            }
+        }
+        // Keeps `Mob.wz` in front of `Mob2.wz` and `Mob2.wz` in front of `Mob10.wz`
+        files.sort_by(|(a, _), (b, _)| archive_sort_key(a).cmp(&archive_sort_key(b)));
+
+        let mut list = None;
+        let mut ns = Self::new();
+        for (stem, path) in files {
+            if stem.eq_ignore_ascii_case("List") {
+                list = Some(Arc::new(WzList::open_file(&path, region)?));
+                continue;
+            }
+            let r = WzReader::open_file_auto(&path, region).map_err(|err| err.in_parent(&stem))?;
+            ns.add(&stem, r);
+        }
+
+        if let Some(list) = list {
+            for (category, readers) in ns.archives.iter_mut() {
+                for r in readers.iter_mut() {
+                    r.set_list(list.clone(), category.clone())
+                        .map_err(|err| err.in_parent(category))?;
+                }
+            }
+        }
+
+        Ok(ns)
+    }
+}
+
+impl<R: WzIO> WzNamespace<R> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the archive with the file name `name` without extension,
+    /// split archives must be added in order
+    pub fn add(&mut self, name: &str, r: WzReader<R>) {
+        self.archives
+            .entry(archive_category(name).to_string())
+            .or_default()
+            .push(r);
+    }
+
+    pub fn categories(&self) -> impl Iterator<Item = &str> {
+        self.archives.keys().map(String::as_str)
+    }
+
+    /// Readers of the category, split archives are in the order they were added
+    pub fn readers_mut(&mut self, category: &str) -> Option<&mut [WzReader<R>]> {
+        self.archives.get_mut(category).map(Vec::as_mut_slice)
+    }
+
+    /// Splits the path into the category and the path within the archives
+    fn split_path<'p>(&self, path: &'p str) -> (&'p str, &'p str) {
+        let path = path.trim_matches('/');
+        let (category, rest) = path.split_once('/').unwrap_or((path, ""));
+        if self.archives.contains_key(category) {
+            (category, rest)
+        } else {
+            (BASE, path)
+        }
+    }
+
+    /// Finds the reader with the image at `path` like `Mob/100100.img`
+    fn find_img(&mut self, path: &str) -> WzResult<(&mut WzReader<R>, String, WzImgHeader)> {
+        let not_found = || WzError::NotFound {
+            path: path.to_string(),
+        };
+        let (category, img_path) = self.split_path(path);
+        let readers = self.archives.get_mut(category).ok_or_else(not_found)?;
+        for r in readers.iter_mut() {
+            match r.img_header_by_path(img_path) {
+                Ok(hdr) => return Ok((r, img_path.to_string(), hdr)),
+                Err(WzError::NotFound { .. }) => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Err(not_found())
+    }
+
+    /// Image reader for the image at `path` like `Mob/100100.img`
+    pub fn img_reader(&mut self, path: &str) -> WzResult<WzImgReader<SubReader<'_, R>>> {
+        let (r, img_path, hdr) = self.find_img(path)?;
+        r.img_reader_with_path(&img_path, &hdr)
+    }
+
+    /// Reads the value at `path` like `Mob/100100.img/info/level`, links are followed
+    pub fn read_value(&mut self, path: &str) -> WzResult<WzValue> {
+        let not_found = || WzError::NotFound {
+            path: path.to_string(),
+        };
+        let (img_path, prop_path) = match path.find(".img") {
+            Some(ix) => (&path[..ix + 4], path[ix + 4..].trim_start_matches('/')),
+            None => return Err(not_found()),
+        };
+
+        let root = WzValue::read(&mut self.img_reader(img_path)?)
+            .map_err(|err| err.in_parent(img_path))?;
+        if prop_path.is_empty() {
+            return Ok(root);
+        }
+        root.get_path_resolved(prop_path)?
+            .cloned()
+            .ok_or_else(not_found)
+    }
+
+    /// Entries of the directory at `path`, merged over split archives
+    pub fn read_dir(&mut self, path: &str) -> WzResult<Vec<WzDirNode>> {
+        let not_found = || WzError::NotFound {
+            path: path.to_string(),
+        };
+        let (category, dir_path) = self.split_path(path);
+        let readers = self.archives.get_mut(category).ok_or_else(not_found)?;
+
+        let mut found = false;
+        let mut entries = Vec::new();
+        for r in readers.iter_mut() {
+            let dir = if dir_path.is_empty() {
+                r.read_root_dir()?
+            } else {
+                let root = WzDirNode::Dir(WzDirHeader::root("root", 1, r.root_offset()));
+                match r.read_path(&root, dir_path) {
+                    Ok(WzDirNode::Dir(hdr)) => r.read_dir_node(&hdr)?,
+                    Ok(_) | Err(WzError::NotFound { .. }) => continue,
+                    Err(err) => return Err(err),
+                }
+            };
+            found = true;
+            entries.extend(dir.entries.0);
+        }
+
+        if !found {
+            return Err(not_found());
+        }
+        Ok(entries)
+    }
+}
+
+impl<R: WzIO> WzOutlinkResolver for WzNamespace<R> {
+    fn read_outlink(&mut self, path: &str) -> WzResult<Canvas> {
+        follow_outlinks(path, |path| {
+            let not_found = || WzError::NotFound {
+                path: path.to_string(),
+            };
+            let (category, rest) = self.split_path(path);
+            let readers = self.archives.get_mut(category).ok_or_else(not_found)?;
+
+            for r in readers.iter_mut() {
+                match r.read_canvas_path(rest) {
+                    Err(WzError::NotFound { .. }) => continue,
+                    res => return res.map_err(|err| err.in_parent(category)),
+                }
+            }
+            Err(not_found())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        l0::WzDirNode,
+        version::{WzRegion, WzVersion},
+        writer::{tests::int_img, WzWriterDir},
+        WzWriter,
+    };
+
+    use super::{archive_category, archive_sort_key, WzNamespace};
+
+    #[test]
+    fn category() {
+        assert_eq!(archive_category("Mob"), "Mob");
+        assert_eq!(archive_category("Mob2"), "Mob");
+        assert_eq!(archive_category("Mob001"), "Mob");
+        assert_eq!(archive_category("Map_000"), "Map");
+        assert_eq!(archive_category("001"), "001");
+
+        let mut names = ["Mob10", "Mob2", "Mob", "Map001", "Map"];
+        names.sort_by(|a, b| archive_sort_key(a).cmp(&archive_sort_key(b)));
+        assert_eq!(names, ["Map", "Map001", "Mob", "Mob2", "Mob10"]);
+    }
+
+    #[test]
+    fn open_dir() {
+        let region = WzRegion::GMS;
+        let dir = std::env::temp_dir().join(format!("shroom-wz-ns-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, root: &WzWriterDir| {
+            WzWriter::new(region, WzVersion(95))
+                .write_file(dir.join(name), root)
+                .unwrap();
+        };
+
+        let mut base = WzWriterDir::new("Base");
+        base.add_dir(WzWriterDir::new("Mob"));
+        base.add_img("smap.img", int_img(region, 1));
+        write("Base.wz", &base);
+
+        let mut mob = WzWriterDir::new("Mob");
+        mob.add_img("100100.img", int_img(region, 2));
+        write("Mob.wz", &mob);
+
+        let mut mob2 = WzWriterDir::new("Mob");
+        let mut sub = WzWriterDir::new("Sub");
+        sub.add_img("100101.img", int_img(region, 3));
+        mob2.add_dir(sub);
+        write("Mob2.wz", &mob2);
+
+        let mut ns = WzNamespace::open_dir(&dir, region).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut categories = ns.categories().collect::<Vec<_>>();
+        categories.sort();
+        assert_eq!(categories, ["Base", "Mob"]);
+
+        let level = |ns: &mut WzNamespace<_>, path| ns.read_value(path).unwrap().as_i32();
+        assert_eq!(level(&mut ns, "smap.img/level"), Some(1));
+        assert_eq!(level(&mut ns, "Mob/100100.img/level"), Some(2));
+        assert_eq!(level(&mut ns, "Mob/Sub/100101.img/level"), Some(3));
+        assert!(ns.read_value("Mob/100102.img/level").is_err());
+        assert!(ns.read_value("Mob/100100.img/x").is_err());
+
+        let names = ns
+            .read_dir("Mob")
+            .unwrap()
+            .iter()
+            .filter_map(|node| node.name().map(str::to_string))
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["100100.img", "Sub"]);
+        assert!(matches!(
+            ns.read_dir("Mob/Sub").unwrap()[..],
+            [WzDirNode::Img(_)]
+        ));
+    }
+}
@@ -0,0 +1,474 @@
+//! NX files, a pre-decoded alternative to WZ archives
+//!
+//! A file consists of a header and four tables: nodes, strings, LZ4 compressed
+//! BGRA bitmaps and audio. The children of a node are stored in a row
+//! and sorted by name, so lookups are binary searches without decoding anything.
+
+use std::{fs, io::Cursor, path::Path};
+
+use binrw::{binrw, BinRead};
+use image::RgbaImage;
+
+use crate::{
+    error::{WzError, WzResult},
+    util::SharedData,
+    val::Vec2Val,
+};
+
+pub mod writer;
+
+pub use writer::NxWriter;
+
+pub const NX_HEADER_SIZE: u64 = 52;
+pub const NX_NODE_SIZE: u64 = 20;
+
+#[binrw]
+#[brw(little, magic = b"PKG4")]
+#[derive(Debug, Clone, Default)]
+pub struct NxHeader {
+    pub node_count: u32,
+    pub node_offset: u64,
+    pub string_count: u32,
+    pub string_offset: u64,
+    pub bitmap_count: u32,
+    pub bitmap_offset: u64,
+    pub audio_count: u32,
+    pub audio_offset: u64,
+}
+
+/// Type of the node data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum NxType {
+    None = 0,
+    Int = 1,
+    Double = 2,
+    String = 3,
+    Vector = 4,
+    Bitmap = 5,
+    Audio = 6,
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Default)]
+pub struct NxNodeRaw {
+    pub name: u32,
+    pub first_child: u32,
+    pub child_count: u16,
+    pub ty: u16,
+    pub data: [u8; 8],
+}
+
+/// Data of a node, bitmaps and audio are ids into their tables
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NxData<'a> {
+    None,
+    Int(i64),
+    Double(f64),
+    String(&'a str),
+    Vector(Vec2Val),
+    Bitmap { id: u32, width: u16, height: u16 },
+    Audio { id: u32, len: u32 },
+}
+
+impl NxData<'_> {
+    pub fn ty(&self) -> NxType {
+        match self {
+            NxData::None => NxType::None,
+            NxData::Int(_) => NxType::Int,
+            NxData::Double(_) => NxType::Double,
+            NxData::String(_) => NxType::String,
+            NxData::Vector(_) => NxType::Vector,
+            NxData::Bitmap { .. } => NxType::Bitmap,
+            NxData::Audio { .. } => NxType::Audio,
+        }
+    }
+}
+
+fn u32_at(data: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes(data[i..i + 4].try_into().unwrap())
+}
+
+/// Opened NX file, the data is only read on access
+#[derive(Debug, Clone)]
+pub struct NxFile {
+    data: SharedData,
+    header: NxHeader,
+}
+
+impl NxFile {
+    pub fn new(data: SharedData) -> WzResult<Self> {
+        let header = NxHeader::read(&mut Cursor::new(data.as_ref()))
+            .map_err(|err| WzError::from_binrw(err, 0))?;
+        let file = Self { data, header };
+        let h = &file.header;
+        if h.node_count == 0 {
+            return Err(WzError::malformed(0, "nx file has no root node"));
+        }
+        file.bytes(h.node_offset, h.node_count as u64 * NX_NODE_SIZE)?;
+        file.bytes(h.string_offset, h.string_count as u64 * 8)?;
+        file.bytes(h.bitmap_offset, h.bitmap_count as u64 * 8)?;
+        file.bytes(h.audio_offset, h.audio_count as u64 * 8)?;
+        Ok(file)
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> WzResult<Self> {
+        Self::new(SharedData::new(fs::read(path)?))
+    }
+
+    #[cfg(feature = "mmap")]
+    pub fn open_mmap(path: impl AsRef<Path>) -> WzResult<Self> {
+        let file = fs::File::open(path)?;
+        let mmap = unsafe { memmap2::Mmap::map(&file)? };
+        Self::new(SharedData::new(mmap))
+    }
+
+    pub fn header(&self) -> &NxHeader {
+        &self.header
+    }
+
+    pub fn root(&self) -> NxNode<'_> {
+        NxNode {
+            file: self,
+            ix: 0,
+            raw: self.raw_node(0).expect("root is checked on open"),
+        }
+    }
+
+    fn bytes(&self, offset: u64, len: u64) -> WzResult<&[u8]> {
+        let data = self.data.as_ref();
+        offset
+            .checked_add(len)
+            .filter(|end| *end <= data.len() as u64)
+            .map(|end| &data[offset as usize..end as usize])
+            .ok_or(WzError::Truncated {
+                pos: offset,
+                path: None,
+            })
+    }
+
+    /// Entry of an offset table
+    fn table_offset(&self, table: u64, count: u32, id: u32) -> WzResult<u64> {
+        if id >= count {
+            return Err(WzError::malformed(table, format!("invalid id {id}")));
+        }
+        let b = self.bytes(table + id as u64 * 8, 8)?;
+        Ok(u64::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn raw_node(&self, ix: u32) -> WzResult<NxNodeRaw> {
+        if ix >= self.header.node_count {
+            return Err(WzError::malformed(
+                self.header.node_offset,
+                format!("invalid node {ix}"),
+            ));
+        }
+        let offset = self.header.node_offset + ix as u64 * NX_NODE_SIZE;
+        NxNodeRaw::read(&mut Cursor::new(self.bytes(offset, NX_NODE_SIZE)?))
+            .map_err(|err| WzError::from_binrw(err, offset))
+    }
+
+    pub fn string(&self, id: u32) -> WzResult<&str> {
+        let h = &self.header;
+        let offset = self.table_offset(h.string_offset, h.string_count, id)?;
+        let len = self.bytes(offset, 2)?;
+        let len = u16::from_le_bytes([len[0], len[1]]);
+        std::str::from_utf8(self.bytes(offset + 2, len as u64)?).map_err(|_| {
+            WzError::InvalidString {
+                pos: offset,
+                path: None,
+            }
+        })
+    }
+
+    /// Decompresses the bitmap as RGBA image
+    pub fn bitmap(&self, id: u32, width: u16, height: u16) -> WzResult<RgbaImage> {
+        let h = &self.header;
+        let offset = self.table_offset(h.bitmap_offset, h.bitmap_count, id)?;
+        let len = u32_at(self.bytes(offset, 4)?, 0);
+        let block = self.bytes(offset + 4, len as u64)?;
+        let size = width as usize * height as usize * 4;
+        let mut data = lz4_flex::block::decompress(block, size)
+            .map_err(|err| WzError::malformed(offset + 4, format!("invalid bitmap: {err}")))?;
+        if data.len() != size {
+            return Err(WzError::malformed(offset + 4, "bitmap has the wrong size"));
+        }
+        for px in data.chunks_exact_mut(4) {
+            px.swap(0, 2);
+        }
+        Ok(RgbaImage::from_raw(width as u32, height as u32, data).expect("size is checked"))
+    }
+
+    pub fn audio(&self, id: u32, len: u32) -> WzResult<&[u8]> {
+        let h = &self.header;
+        let offset = self.table_offset(h.audio_offset, h.audio_count, id)?;
+        self.bytes(offset, len as u64)
+    }
+}
+
+/// Node of a NX file, navigation mirrors `WzValue`
+#[derive(Debug, Clone)]
+pub struct NxNode<'a> {
+    file: &'a NxFile,
+    ix: u32,
+    raw: NxNodeRaw,
+}
+
+impl<'a> NxNode<'a> {
+    pub fn index(&self) -> u32 {
+        self.ix
+    }
+
+    pub fn name(&self) -> WzResult<&'a str> {
+        self.file.string(self.raw.name)
+    }
+
+    pub fn data(&self) -> WzResult<NxData<'a>> {
+        let d = &self.raw.data;
+        let i32_at = |i: usize| u32_at(d, i) as i32;
+        Ok(match self.raw.ty {
+            0 => NxData::None,
+            1 => NxData::Int(i64::from_le_bytes(*d)),
+            2 => NxData::Double(f64::from_le_bytes(*d)),
+            3 => NxData::String(self.file.string(u32_at(d, 0))?),
+            4 => NxData::Vector(Vec2Val {
+                x: i32_at(0),
+                y: i32_at(4),
+            }),
+            5 => NxData::Bitmap {
+                id: u32_at(d, 0),
+                width: u16::from_le_bytes([d[4], d[5]]),
+                height: u16::from_le_bytes([d[6], d[7]]),
+            },
+            6 => NxData::Audio {
+                id: u32_at(d, 0),
+                len: u32_at(d, 4),
+            },
+            ty => {
+                return Err(WzError::UnknownObject {
+                    pos: self.file.header.node_offset + self.ix as u64 * NX_NODE_SIZE,
+                    ty: format!("nx type {ty}"),
+                    path: None,
+                })
+            }
+        })
+    }
+
+    pub fn child_count(&self) -> usize {
+        self.raw.child_count as usize
+    }
+
+    pub fn child(&self, i: usize) -> WzResult<NxNode<'a>> {
+        let ix = self.raw.first_child + i as u32;
+        Ok(NxNode {
+            file: self.file,
+            ix,
+            raw: self.file.raw_node(ix)?,
+        })
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = WzResult<NxNode<'a>>> + '_ {
+        (0..self.child_count()).map(|i| self.child(i))
+    }
+
+    /// Child by name, found by binary search
+    pub fn get(&self, name: &str) -> Option<NxNode<'a>> {
+        let (mut lo, mut hi) = (0, self.child_count());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            let child = self.child(mid).ok()?;
+            match child.name().ok()?.as_bytes().cmp(name.as_bytes()) {
+                std::cmp::Ordering::Equal => return Some(child),
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+            }
+        }
+        None
+    }
+
+    pub fn get_path(&self, path: &str) -> Option<NxNode<'a>> {
+        let mut cur = self.clone();
+        for part in path.split('/') {
+            cur = cur.get(part)?;
+        }
+        Some(cur)
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self.data().ok()? {
+            NxData::Int(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self.data().ok()? {
+            NxData::Double(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&'a str> {
+        match self.data().ok()? {
+            NxData::String(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_vec(&self) -> Option<Vec2Val> {
+        match self.data().ok()? {
+            NxData::Vector(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Decodes the bitmap, if the node has one
+    pub fn bitmap(&self) -> WzResult<Option<RgbaImage>> {
+        match self.data()? {
+            NxData::Bitmap { id, width, height } => self.file.bitmap(id, width, height).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Audio data as it's stored in the WZ image, if the node has audio
+    pub fn audio(&self) -> WzResult<Option<&'a [u8]>> {
+        match self.data()? {
+            NxData::Audio { id, len } => self.file.audio(id, len).map(Some),
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use binrw::PosValue;
+    use image::{Rgba, RgbaImage};
+
+    use crate::{
+        canvas::Canvas,
+        crypto::WzCrypto,
+        l0::tree::WzTree,
+        l1::{
+            canvas::{WzCanvasDepth, WzCanvasScaling},
+            sound::{tests::pcm_header, WzSound},
+        },
+        ty::WzInt,
+        util::SharedData,
+        val::{
+            tests::{link, obj},
+            CanvasVal, Payload, SoundVal, Vec2Val, Vex2Val, WzValue,
+        },
+        version::{WzRegion, WzVersion},
+        writer::{NoPayload, WzImgWriter, WzWriterDir},
+        WzReader, WzWriter,
+    };
+
+    use super::{NxData, NxFile, NxWriter};
+
+    #[test]
+    fn convert() {
+        let region = WzRegion::GMS;
+        let ver = WzVersion(95);
+        let crypto = WzCrypto::from_region(region, ver, 0);
+
+        let img = RgbaImage::from_fn(3, 2, |x, y| Rgba([x as u8, y as u8, 7, 255]));
+        let (canvas, payload) =
+            Canvas::from_rgba_image(&img, WzCanvasDepth::BGRA8888, WzCanvasScaling(0))
+                .to_wz_canvas(None, None)
+                .unwrap();
+        let icon = WzValue::Canvas(CanvasVal {
+            canvas,
+            sub: Some(Box::new(obj(vec![(
+                "origin",
+                WzValue::Vec(Vec2Val { x: 1, y: -2 }),
+            )]))),
+            payload: Some(Payload::Data(payload)),
+        });
+        let sound = WzValue::Sound(SoundVal {
+            sound: WzSound {
+                unknown: 0,
+                size: WzInt(0),
+                len_ms: WzInt(1),
+                header: pcm_header(),
+                offset: PosValue { val: (), pos: 0 },
+            },
+            payload: Some(Payload::Data(vec![1, 2, 3])),
+        });
+        let val = obj(vec![
+            (
+                "info",
+                obj(vec![
+                    ("level", WzValue::Short(7)),
+                    ("exp", WzValue::Long(-3)),
+                    ("speed", WzValue::F32(0.5)),
+                    ("name", WzValue::String("Snail".to_string())),
+                ]),
+            ),
+            ("icon", icon),
+            ("alias", link("icon")),
+            ("broken", link("missing")),
+            (
+                "hit",
+                WzValue::Convex(Vex2Val(vec![Vec2Val { x: 3, y: 4 }])),
+            ),
+            ("die", sound),
+        ]);
+        let data = WzImgWriter::write(&crypto, &val, &mut NoPayload).unwrap();
+
+        let mut root = WzWriterDir::new("Root");
+        let mut mob = WzWriterDir::new("Mob");
+        mob.add_img("100100.img", data);
+        root.add_dir(mob);
+        let mut w = Cursor::new(Vec::new());
+        WzWriter::new(region, ver).write(&mut w, &root).unwrap();
+        w.set_position(0);
+        let mut r = WzReader::open(w, region, ver).unwrap();
+        let tree = WzTree::from_reader(&mut r, None).unwrap();
+
+        let data = NxWriter::new(Cursor::new(Vec::new()))
+            .unwrap()
+            .write_tree(&mut r, &tree)
+            .unwrap()
+            .into_inner();
+        let nx = NxFile::new(SharedData::new(data)).unwrap();
+        let mob = nx.root().get_path("Mob/100100.img").unwrap();
+        let names = mob
+            .children()
+            .map(|c| c.unwrap().name().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["alias", "broken", "die", "hit", "icon", "info"]);
+
+        assert_eq!(mob.get_path("info/level").unwrap().as_i64(), Some(7));
+        assert_eq!(mob.get_path("info/exp").unwrap().as_i64(), Some(-3));
+        assert_eq!(mob.get_path("info/speed").unwrap().as_f64(), Some(0.5));
+        assert_eq!(mob.get_path("info/name").unwrap().as_str(), Some("Snail"));
+        assert!(mob.get_path("info/missing").is_none());
+        assert_eq!(
+            mob.get_path("hit/0").unwrap().as_vec(),
+            Some(Vec2Val { x: 3, y: 4 })
+        );
+
+        let icon = mob.get("icon").unwrap();
+        assert_eq!(icon.bitmap().unwrap().unwrap(), img);
+        assert_eq!(
+            icon.get("origin").unwrap().as_vec(),
+            Some(Vec2Val { x: 1, y: -2 })
+        );
+        // The link is a copy which shares the bitmap
+        let alias = mob.get_path("alias").unwrap();
+        assert_eq!(alias.data().unwrap(), icon.data().unwrap());
+        assert!(alias.get("origin").is_some());
+        assert_eq!(nx.header().bitmap_count, 1);
+        // Only the broken link is kept as a string
+        assert_eq!(mob.get("broken").unwrap().as_str(), Some("missing"));
+
+        let die = mob.get("die").unwrap();
+        assert!(matches!(die.data().unwrap(), NxData::Audio { len: 3, .. }));
+        assert_eq!(die.audio().unwrap(), Some(&[1, 2, 3][..]));
+
+        assert!(NxFile::new(SharedData::new(b"PKG4".to_vec())).is_err());
+    }
+}
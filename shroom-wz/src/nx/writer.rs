@@ -0,0 +1,340 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{Seek, SeekFrom, Write},
+    mem,
+};
+
+use binrw::BinWrite;
+use id_tree::NodeId;
+use image::RgbaImage;
+
+use crate::{
+    error::{WzError, WzResult},
+    file::{WzIO, WzImgReader},
+    l0::{tree::WzTree, WzDirNode, WzImgHeader},
+    val::WzValue,
+    WzReader,
+};
+
+use super::{NxHeader, NxNodeRaw, NxType};
+
+// Header and padding up to the first bitmap
+const DATA_START: u64 = 56;
+
+/// Children of a node, which are added once the node is dequeued
+enum Children {
+    None,
+    Dir(NodeId),
+    Img(WzImgHeader),
+    Nodes(Vec<NxBuildNode>),
+}
+
+struct NxBuildNode {
+    name: String,
+    ty: NxType,
+    data: [u8; 8],
+    children: Children,
+}
+
+impl NxBuildNode {
+    fn new(name: &str, ty: NxType, data: [u8; 8]) -> Self {
+        Self {
+            name: name.to_string(),
+            ty,
+            data,
+            children: Children::None,
+        }
+    }
+}
+
+fn pair(a: u32, b: u32) -> [u8; 8] {
+    let mut data = [0; 8];
+    data[..4].copy_from_slice(&a.to_le_bytes());
+    data[4..].copy_from_slice(&b.to_le_bytes());
+    data
+}
+
+/// Converts WZ archives to NX files
+///
+/// Bitmaps and audio are written while the tree is converted,
+/// the nodes and strings are written at the end.
+pub struct NxWriter<W> {
+    w: W,
+    pos: u64,
+    nodes: Vec<NxNodeRaw>,
+    strings: Vec<String>,
+    string_ids: HashMap<String, u32>,
+    bitmaps: Vec<u64>,
+    audio: Vec<u64>,
+}
+
+impl<W: Write + Seek> NxWriter<W> {
+    pub fn new(mut w: W) -> WzResult<Self> {
+        // The header is written last
+        w.write_all(&[0; DATA_START as usize])?;
+        Ok(Self {
+            w,
+            pos: DATA_START,
+            nodes: Vec::new(),
+            strings: Vec::new(),
+            string_ids: HashMap::new(),
+            bitmaps: Vec::new(),
+            audio: Vec::new(),
+        })
+    }
+
+    fn write(&mut self, data: &[u8]) -> WzResult<()> {
+        self.w.write_all(data)?;
+        self.pos += data.len() as u64;
+        Ok(())
+    }
+
+    fn align(&mut self, n: u64) -> WzResult<()> {
+        let pad = (n - self.pos % n) % n;
+        self.write(&vec![0; pad as usize])
+    }
+
+    fn string_id(&mut self, s: &str) -> u32 {
+        if let Some(&id) = self.string_ids.get(s) {
+            return id;
+        }
+        let id = self.strings.len() as u32;
+        self.strings.push(s.to_string());
+        self.string_ids.insert(s.to_string(), id);
+        id
+    }
+
+    fn add_bitmap(&mut self, img: &RgbaImage) -> WzResult<u32> {
+        let mut data = img.as_raw().clone();
+        for px in data.chunks_exact_mut(4) {
+            px.swap(0, 2);
+        }
+        let block = lz4_flex::block::compress(&data);
+        self.align(8)?;
+        self.bitmaps.push(self.pos);
+        self.write(&(block.len() as u32).to_le_bytes())?;
+        self.write(&block)?;
+        Ok(self.bitmaps.len() as u32 - 1)
+    }
+
+    fn add_audio(&mut self, data: &[u8]) -> WzResult<u32> {
+        self.align(8)?;
+        self.audio.push(self.pos);
+        self.write(data)?;
+        Ok(self.audio.len() as u32 - 1)
+    }
+
+    /// Converts the archive, image names keep their `.img` suffix
+    ///
+    /// Links are replaced with a copy of their target,
+    /// only links which can't be resolved are kept as strings.
+    pub fn write_tree<R: WzIO>(mut self, r: &mut WzReader<R>, tree: &WzTree) -> WzResult<W> {
+        let tree = tree.get_tree();
+        let root = tree
+            .root_node_id()
+            .ok_or_else(|| WzError::malformed(0, "tree has no root"))?;
+        let name = self.string_id("");
+        self.nodes.push(NxNodeRaw {
+            name,
+            ..Default::default()
+        });
+
+        // Children of a node are stored in a row, so the nodes are added level by level
+        let mut q = VecDeque::from([(0, Children::Dir(root.clone()))]);
+        while let Some((ix, children)) = q.pop_front() {
+            let mut children = match children {
+                Children::None => continue,
+                Children::Dir(id) => tree
+                    .children_ids(&id)
+                    .expect("id is from the tree")
+                    .filter_map(
+                        |id| match tree.get(id).expect("id is from the tree").data() {
+                            WzDirNode::Dir(dir) => Some(NxBuildNode {
+                                children: Children::Dir(id.clone()),
+                                ..NxBuildNode::new(&dir.name, NxType::None, [0; 8])
+                            }),
+                            WzDirNode::Img(img) => Some(NxBuildNode {
+                                children: Children::Img(img.clone()),
+                                ..NxBuildNode::new(&img.name, NxType::None, [0; 8])
+                            }),
+                            WzDirNode::Nil(_) | WzDirNode::Link(_) => None,
+                        },
+                    )
+                    .collect(),
+                Children::Img(hdr) => self
+                    .build_img(r, &hdr)
+                    .map_err(|err| err.in_parent(&hdr.name))?,
+                Children::Nodes(nodes) => nodes,
+            };
+            children.sort_by(|a, b| a.name.cmp(&b.name));
+
+            let count = u16::try_from(children.len()).map_err(|_| {
+                WzError::malformed(0, format!("{} children are too many", children.len()))
+            })?;
+            let first_child = self.nodes.len() as u32;
+            let node = &mut self.nodes[ix];
+            node.first_child = first_child;
+            node.child_count = count;
+            for child in children {
+                let name = self.string_id(&child.name);
+                q.push_back((self.nodes.len(), child.children));
+                self.nodes.push(NxNodeRaw {
+                    name,
+                    first_child: 0,
+                    child_count: 0,
+                    ty: child.ty as u16,
+                    data: child.data,
+                });
+            }
+        }
+
+        self.finish()
+    }
+
+    fn build_img<R: WzIO>(
+        &mut self,
+        r: &mut WzReader<R>,
+        hdr: &WzImgHeader,
+    ) -> WzResult<Vec<NxBuildNode>> {
+        let mut img = r.img_reader(hdr)?;
+        let mut val = WzValue::read(&mut img)?;
+        val.resolve_valid_links();
+        let mut bitmaps = HashMap::new();
+        self.build_children(&mut img, &val, &mut bitmaps)
+    }
+
+    fn build_children<R: WzIO>(
+        &mut self,
+        r: &mut WzImgReader<R>,
+        val: &WzValue,
+        bitmaps: &mut HashMap<u64, u32>,
+    ) -> WzResult<Vec<NxBuildNode>> {
+        match val {
+            WzValue::Object(obj) => obj
+                .0
+                .iter()
+                .map(|(k, v)| self.build(r, k, v, bitmaps).map_err(|err| err.in_parent(k)))
+                .collect(),
+            WzValue::Canvas(canvas) => match canvas.sub.as_deref() {
+                Some(sub) => self.build_children(r, sub, bitmaps),
+                None => Ok(Vec::new()),
+            },
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn build<R: WzIO>(
+        &mut self,
+        r: &mut WzImgReader<R>,
+        name: &str,
+        val: &WzValue,
+        bitmaps: &mut HashMap<u64, u32>,
+    ) -> WzResult<NxBuildNode> {
+        let node = |ty, data| NxBuildNode::new(name, ty, data);
+        let mut node = match val {
+            WzValue::Object(_) | WzValue::Null => node(NxType::None, [0; 8]),
+            WzValue::Short(v) => node(NxType::Int, (*v as i64).to_le_bytes()),
+            WzValue::Int(v) => node(NxType::Int, (*v as i64).to_le_bytes()),
+            WzValue::Long(v) => node(NxType::Int, v.to_le_bytes()),
+            WzValue::F32(v) => node(NxType::Double, (*v as f64).to_le_bytes()),
+            WzValue::F64(v) => node(NxType::Double, v.to_le_bytes()),
+            WzValue::String(v) | WzValue::Link(v) => {
+                node(NxType::String, pair(self.string_id(v), 0))
+            }
+            WzValue::Vec(v) => node(NxType::Vector, pair(v.x as u32, v.y as u32)),
+            WzValue::Convex(vex) => NxBuildNode {
+                children: Children::Nodes(
+                    vex.0
+                        .iter()
+                        .enumerate()
+                        .map(|(i, v)| {
+                            NxBuildNode::new(
+                                &i.to_string(),
+                                NxType::Vector,
+                                pair(v.x as u32, v.y as u32),
+                            )
+                        })
+                        .collect(),
+                ),
+                ..node(NxType::None, [0; 8])
+            },
+            WzValue::Canvas(canvas) => {
+                let (w, h) = (canvas.canvas.width(), canvas.canvas.height());
+                let (Ok(w), Ok(h)) = (u16::try_from(w), u16::try_from(h)) else {
+                    return Err(WzError::malformed(
+                        canvas.canvas.len.pos,
+                        format!("canvas is too large: {w}x{h}"),
+                    ));
+                };
+                // Copies of linked canvases share the bitmap
+                let id = match bitmaps.get(&canvas.canvas.len.pos) {
+                    Some(&id) => id,
+                    None => {
+                        let img = canvas.read_canvas(r)?.to_rgba_image()?;
+                        let id = self.add_bitmap(&img)?;
+                        bitmaps.insert(canvas.canvas.len.pos, id);
+                        id
+                    }
+                };
+                node(NxType::Bitmap, pair(id, w as u32 | ((h as u32) << 16)))
+            }
+            WzValue::Sound(sound) => {
+                let data = sound.read_data(r)?;
+                let id = self.add_audio(&data)?;
+                node(NxType::Audio, pair(id, data.len() as u32))
+            }
+        };
+        if matches!(val, WzValue::Object(_) | WzValue::Canvas(_)) {
+            node.children = Children::Nodes(self.build_children(r, val, bitmaps)?);
+        }
+        Ok(node)
+    }
+
+    fn write_table(&mut self, offsets: &[u64]) -> WzResult<u64> {
+        self.align(8)?;
+        let pos = self.pos;
+        for offset in offsets {
+            self.write(&offset.to_le_bytes())?;
+        }
+        Ok(pos)
+    }
+
+    /// Writes the nodes, strings, offset tables and the header
+    fn finish(mut self) -> WzResult<W> {
+        self.align(8)?;
+        let node_offset = self.pos;
+        let nodes = mem::take(&mut self.nodes);
+        for node in nodes.iter() {
+            node.write(&mut self.w)?;
+            self.pos += super::NX_NODE_SIZE;
+        }
+
+        let strings = mem::take(&mut self.strings);
+        let mut string_offsets = Vec::with_capacity(strings.len());
+        for s in strings.iter() {
+            let len = u16::try_from(s.len())
+                .map_err(|_| WzError::malformed(self.pos, "string is too long"))?;
+            self.align(2)?;
+            string_offsets.push(self.pos);
+            self.write(&len.to_le_bytes())?;
+            self.write(s.as_bytes())?;
+        }
+
+        let bitmaps = mem::take(&mut self.bitmaps);
+        let audio = mem::take(&mut self.audio);
+        let header = NxHeader {
+            node_count: nodes.len() as u32,
+            node_offset,
+            string_count: strings.len() as u32,
+            string_offset: self.write_table(&string_offsets)?,
+            bitmap_count: bitmaps.len() as u32,
+            bitmap_offset: self.write_table(&bitmaps)?,
+            audio_count: audio.len() as u32,
+            audio_offset: self.write_table(&audio)?,
+        };
+        self.w.seek(SeekFrom::Start(0))?;
+        header.write(&mut self.w)?;
+        self.w.seek(SeekFrom::End(0))?;
+        Ok(self.w)
+    }
+}
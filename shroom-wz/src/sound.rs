@@ -0,0 +1,197 @@
+use std::{io::Cursor, time::Duration};
+
+use symphonia::core::{
+    audio::SampleBuffer, codecs::DecoderOptions, errors::Error as DecodeError,
+    formats::FormatOptions, io::MediaSourceStream, meta::MetadataOptions, probe::Hint,
+};
+
+use crate::{
+    error::{WzError, WzResult},
+    l1::sound::{SoundFormat, WaveHeader},
+};
+
+/// Decoded sound with interleaved 16 bit samples
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedSound {
+    pub samples: Vec<i16>,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl DecodedSound {
+    /// Decodes the raw sound data as read by `WzImgReader::read_sound`
+    pub fn decode(fmt: &SoundFormat, data: &[u8]) -> WzResult<Self> {
+        match fmt {
+            SoundFormat::Pcm(wave) => Self::decode_pcm(wave, data),
+            SoundFormat::Mpeg1(_) | SoundFormat::Mpeg3(_) => Self::decode_mpeg(data),
+        }
+    }
+
+    fn decode_pcm(wave: &WaveHeader, data: &[u8]) -> WzResult<Self> {
+        let samples = match wave.bits_per_sample {
+            // 8 bit samples are unsigned
+            8 => data.iter().map(|&s| ((s as i16) - 0x80) << 8).collect(),
+            // Only the upper 16 bits are kept
+            16 | 24 | 32 => {
+                let n = wave.bits_per_sample as usize / 8;
+                data.chunks_exact(n)
+                    .map(|s| i16::from_le_bytes([s[n - 2], s[n - 1]]))
+                    .collect()
+            }
+            bits => {
+                return Err(WzError::malformed(
+                    0,
+                    format!("unsupported bits per sample: {bits}"),
+                ))
+            }
+        };
+
+        Ok(Self {
+            samples,
+            sample_rate: wave.samples_per_sec,
+            channels: wave.channels,
+        })
+    }
+
+    fn decode_mpeg(data: &[u8]) -> WzResult<Self> {
+        let err = |err: DecodeError| WzError::malformed(0, err.to_string());
+        let src = MediaSourceStream::new(Box::new(Cursor::new(data.to_vec())), Default::default());
+        let mut hint = Hint::new();
+        hint.with_extension("mp3");
+        let mut format = symphonia::default::get_probe()
+            .format(
+                &hint,
+                src,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )
+            .map_err(err)?
+            .format;
+        let track = format
+            .default_track()
+            .ok_or_else(|| WzError::malformed(0, "no audio track"))?;
+        let track_id = track.id;
+        let mut decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())
+            .map_err(err)?;
+
+        let mut sound = Self {
+            samples: Vec::new(),
+            sample_rate: track.codec_params.sample_rate.unwrap_or(0),
+            channels: track
+                .codec_params
+                .channels
+                .map_or(0, |ch| ch.count() as u16),
+        };
+        let mut buf: Option<SampleBuffer<i16>> = None;
+        loop {
+            let packet = match format.next_packet() {
+                Ok(packet) => packet,
+                Err(DecodeError::IoError(_)) => break,
+                Err(e) => return Err(err(e)),
+            };
+            if packet.track_id() != track_id {
+                continue;
+            }
+
+            let decoded = match decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                // Broken frames are skipped like players do
+                Err(DecodeError::DecodeError(_)) => continue,
+                Err(e) => return Err(err(e)),
+            };
+            let spec = *decoded.spec();
+            sound.sample_rate = spec.rate;
+            sound.channels = spec.channels.count() as u16;
+            let buf = match buf.as_mut() {
+                Some(buf) if buf.capacity() >= decoded.capacity() * spec.channels.count() => buf,
+                _ => buf.insert(SampleBuffer::new(decoded.capacity() as u64, spec)),
+            };
+            buf.copy_interleaved_ref(decoded);
+            sound.samples.extend_from_slice(buf.samples());
+        }
+
+        Ok(sound)
+    }
+
+    /// Number of samples per channel
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels.max(1) as usize
+    }
+
+    pub fn duration(&self) -> Duration {
+        if self.sample_rate == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(self.frames() as f64 / self.sample_rate as f64)
+    }
+
+    /// Checks that the decoded duration is within `tolerance` of `expected`
+    pub fn check_duration(&self, expected: Duration, tolerance: Duration) -> WzResult<()> {
+        let duration = self.duration();
+        if duration.abs_diff(expected) > tolerance {
+            return Err(WzError::malformed(
+                0,
+                format!("sound is {duration:?} long, expected: {expected:?}"),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::l1::sound::{SoundFormat, WaveHeader};
+
+    use super::DecodedSound;
+
+    fn wave(channels: u16, bits: u16) -> SoundFormat {
+        let block_align = channels * bits / 8;
+        SoundFormat::Pcm(WaveHeader {
+            format: 1,
+            channels,
+            samples_per_sec: 8000,
+            avg_bytes_per_sec: 8000 * block_align as u32,
+            block_align,
+            bits_per_sample: bits,
+            extra_size: 0,
+        })
+    }
+
+    #[test]
+    fn decode_pcm() {
+        let sound = DecodedSound::decode(&wave(1, 8), &[0x80, 0xFF, 0x00]).unwrap();
+        assert_eq!(sound.samples, [0, 0x7F00, -0x8000]);
+
+        let data = [1i16, -1, 2, -2]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect::<Vec<_>>();
+        let sound = DecodedSound::decode(&wave(2, 16), &data).unwrap();
+        assert_eq!(sound.samples, [1, -1, 2, -2]);
+        assert_eq!(sound.frames(), 2);
+        assert_eq!(sound.duration(), Duration::from_micros(250));
+
+        let sound = DecodedSound::decode(&wave(1, 16), &vec![0; 8000 * 2]).unwrap();
+        let ms = Duration::from_millis;
+        assert!(sound.check_duration(ms(1000), ms(10)).is_ok());
+        assert!(sound.check_duration(ms(1500), ms(10)).is_err());
+
+        assert!(DecodedSound::decode(&wave(1, 12), &[]).is_err());
+    }
+
+    #[test]
+    fn decode_mpeg() {
+        // Silent MPEG-1 layer 3 frames with 128kbps at 44.1kHz
+        let mut frame = vec![0; 417];
+        frame[..4].copy_from_slice(&[0xFF, 0xFB, 0x90, 0x64]);
+        let data = frame.repeat(10);
+
+        let sound = DecodedSound::decode(&SoundFormat::Mpeg1([0; 73]), &data).unwrap();
+        assert_eq!((sound.sample_rate, sound.channels), (44100, 2));
+        assert!(sound.frames() > 0);
+        assert!(sound.samples.iter().all(|&s| s == 0));
+    }
+}
@@ -1,12 +1,16 @@
 use std::{
     io::{Read, Seek},
-    num::Wrapping,
+    iter,
     ops::{Deref, DerefMut, Neg},
 };
 
 use binrw::{binrw, BinRead, BinWrite, VecArgs};
 
-use crate::{crypto::WzCrypto, util::WzContext};
+use crate::{
+    crypto::WzCrypto,
+    error::WzError,
+    util::{read_vec, WzContext},
+};
 
 pub type RefWzCrypto<'a> = (&'a WzCrypto,);
 
@@ -134,18 +138,16 @@ impl BinWrite for WzF32 {
 // String mask helper
 
 fn xor_mask_ascii(data: &mut [u8]) {
-    let mut mask = Wrapping(0xAAu8);
-    for b in data.iter_mut() {
-        *b ^= mask.0;
-        mask += 1;
+    let masks = iter::successors(Some(0xAAu8), |m| Some(m.wrapping_add(1)));
+    for (b, mask) in data.iter_mut().zip(masks) {
+        *b ^= mask;
     }
 }
 
 fn xor_mask_unicode(data: &mut [u16]) {
-    let mut mask = Wrapping(0xAAAA);
-    for b in data.iter_mut() {
-        *b ^= mask.0;
-        mask += 1;
+    let masks = iter::successors(Some(0xAAAAu16), |m| Some(m.wrapping_add(1)));
+    for (b, mask) in data.iter_mut().zip(masks) {
+        *b ^= mask;
     }
 }
 
@@ -191,6 +193,11 @@ impl DerefMut for WzStr {
     }
 }
 
+fn str_len(pos: u64, len: i32) -> binrw::BinResult<usize> {
+    usize::try_from(len)
+        .map_err(|_| WzError::malformed(pos, format!("Invalid string length: {len}")).into_binrw())
+}
+
 impl BinRead for WzStr {
     type Args<'a> = WzContext<'a>;
 
@@ -199,33 +206,36 @@ impl BinRead for WzStr {
         endian: binrw::Endian,
         args: Self::Args<'_>,
     ) -> binrw::BinResult<Self> {
+        let pos = reader.stream_position()?;
+        let invalid_str = || WzError::InvalidString { pos, path: None }.into_binrw();
         let flag = i8::read_options(reader, endian, ())?;
         Ok(if flag <= 0 {
             let ln = if flag == -128 {
-                i32::read_options(reader, endian, ())? as usize
+                str_len(pos, i32::read_options(reader, endian, ())?)?
             } else {
-                -flag as usize
+                -(flag as i32) as usize
             };
 
-            let mut data = vec![0; ln];
-            reader.read_exact(&mut data)?;
+            let mut data = read_vec(reader, ln)?;
             xor_mask_ascii(&mut data);
             args.crypto.transform(data.as_mut_slice().into());
-            WzStr::new(String::from_utf8(data).unwrap()) // TODO propagate error
+            WzStr::new(String::from_utf8(data).map_err(|_| invalid_str())?)
         } else {
             let ln = if flag == 127 {
-                i32::read_options(reader, endian, ())? as usize
+                str_len(pos, i32::read_options(reader, endian, ())?)?
             } else {
                 flag as usize
             };
 
-            let mut data = vec![0u16; ln];
-            reader.read_exact(bytemuck::cast_slice_mut(data.as_mut_slice()))?;
+            let mut data: Vec<u16> = read_vec(reader, ln * 2)?
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .collect();
             xor_mask_unicode(&mut data);
             args.crypto
                 .transform(bytemuck::cast_slice_mut(data.as_mut_slice()).into());
 
-            WzStr::new(String::from_utf16(&data).unwrap()) // TODO propagate error
+            WzStr::new(String::from_utf16(&data).map_err(|_| invalid_str())?)
         })
     }
 }
@@ -245,7 +255,7 @@ impl BinWrite for WzStr {
             let n = data.len();
             if n >= 128 {
                 i8::MIN.write_options(writer, endian, ())?;
-                (n as i32).neg().write_options(writer, endian, ())?;
+                (n as i32).write_options(writer, endian, ())?;
             } else {
                 (n as i8).neg().write_options(writer, endian, ())?;
             }
@@ -289,14 +299,15 @@ where
         endian: binrw::Endian,
         args: Self::Args<'_>,
     ) -> binrw::BinResult<Self> {
+        let pos = reader.stream_position()?;
         let n = WzInt::read_options(reader, endian, ())?;
+        let count = usize::try_from(n.0).map_err(|_| {
+            WzError::malformed(pos, format!("Invalid element count: {}", n.0)).into_binrw()
+        })?;
         Ok(Self(Vec::read_options(
             reader,
             endian,
-            VecArgs {
-                count: n.0 as usize,
-                inner: args,
-            },
+            VecArgs { count, inner: args },
         )?))
     }
 }
@@ -1,16 +1,81 @@
-use std::time::Duration;
+use std::{io, io::Write, time::Duration};
+
+use image::{imageops, RgbaImage};
 
 use crate::{
     canvas::Canvas,
+    error::{WzError, WzResult},
     file::{WzIO, WzImgReader},
     l1::canvas::WzCanvas,
     val::{ObjectVal, Vec2Val, WzValue},
 };
 
+/// Delay of frames without a `delay`
+pub const DEFAULT_FRAME_DELAY: Duration = Duration::from_millis(100);
+
+/// Length of the frames a fading frame is split into for the exports
+pub const TWEEN_STEP: Duration = Duration::from_millis(50);
+
 pub struct AnimationFrame {
     pub offset: Option<Vec2Val>,
     pub delay: Option<Duration>,
     pub canvas: WzCanvas,
+    /// Alpha at the start of the frame
+    pub a0: u8,
+    /// Alpha at the end of the frame, the alpha fades linearly from `a0`
+    pub a1: u8,
+    /// Layer hint
+    pub z: Option<i32>,
+}
+
+impl AnimationFrame {
+    pub fn delay_or_default(&self) -> Duration {
+        self.delay.unwrap_or(DEFAULT_FRAME_DELAY)
+    }
+
+    /// Alpha after `elapsed` of the frame's delay
+    pub fn alpha_at(&self, elapsed: Duration) -> u8 {
+        tween_alpha(self.a0, self.a1, elapsed, self.delay_or_default())
+    }
+}
+
+fn tween_alpha(a0: u8, a1: u8, elapsed: Duration, delay: Duration) -> u8 {
+    if delay.is_zero() {
+        return a1;
+    }
+    let p = (elapsed.as_secs_f32() / delay.as_secs_f32()).min(1.0);
+    (a0 as f32 + (a1 as f32 - a0 as f32) * p).round() as u8
+}
+
+/// Finds the frame shown at `t` of the looping animation,
+/// returns the index and the time elapsed within the frame
+fn find_frame(
+    delays: impl Iterator<Item = Duration> + Clone,
+    t: Duration,
+) -> Option<(usize, Duration)> {
+    let total = delays.clone().sum::<Duration>().as_nanos();
+    if total == 0 {
+        return None;
+    }
+    let mut t = Duration::from_nanos((t.as_nanos() % total) as u64);
+    for (i, delay) in delays.enumerate() {
+        if t < delay {
+            return Some((i, t));
+        }
+        t -= delay;
+    }
+    None
+}
+
+/// Multiplies the alpha of every pixel with `alpha`
+fn apply_alpha(img: &RgbaImage, alpha: u8) -> RgbaImage {
+    let mut img = img.clone();
+    if alpha != u8::MAX {
+        for px in img.pixels_mut() {
+            px[3] = (px[3] as u16 * alpha as u16 / 255) as u8;
+        }
+    }
+    img
 }
 
 pub struct Animation {
@@ -32,7 +97,40 @@ impl Animation {
         }
     }
 
-    pub fn from_obj_value(obj_val: &ObjectVal) -> anyhow::Result<Self> {
+    /// Links are relative to the image root, so frames which are links fail
+    #[deprecated(note = "use `Animation::from_path`, which resolves linked frames")]
+    pub fn from_obj_value(obj_val: &ObjectVal) -> WzResult<Self> {
+        Self::from_obj_with(obj_val, |_, frame| Ok(frame))
+    }
+
+    /// Reads the animation at `path` from the image root,
+    /// frames which are links get resolved
+    pub fn from_path(root: &WzValue, path: &str) -> WzResult<Self> {
+        let obj = root
+            .get_path_resolved(path)?
+            .ok_or_else(|| WzError::NotFound {
+                path: path.to_string(),
+            })?
+            .as_object()
+            .ok_or_else(|| WzError::UnexpectedValue {
+                expected: "Object",
+                path: Some(path.to_string()),
+            })?;
+
+        Self::from_obj_with(obj, |key, frame| match frame {
+            WzValue::Link(_) => {
+                let path = format!("{path}/{key}");
+                root.get_path_resolved(&path)?
+                    .ok_or(WzError::NotFound { path })
+            }
+            frame => Ok(frame),
+        })
+    }
+
+    fn from_obj_with<'a>(
+        obj_val: &'a ObjectVal,
+        resolve: impl Fn(&str, &'a WzValue) -> WzResult<&'a WzValue>,
+    ) -> WzResult<Self> {
         let mut dim_h = 0;
         let mut dim_w = 0;
         let mut frames = Vec::new();
@@ -42,20 +140,36 @@ impl Animation {
                 continue;
             }
 
-            let frame = frame.as_canvas().ok_or_else(|| {
-                anyhow::anyhow!("Expected canvas for animation frame, got {:?}", frame)
-            })?;
+            let frame =
+                resolve(key, frame)?
+                    .as_canvas()
+                    .ok_or_else(|| WzError::UnexpectedValue {
+                        expected: "Canvas",
+                        path: Some(key.clone()),
+                    })?;
 
             let mut delay = None;
             let mut origin = None;
+            let mut a0 = u8::MAX;
+            let mut a1 = None;
+            let mut z = None;
 
             if let Some(WzValue::Object(obj)) = frame.sub.as_deref() {
+                let alpha = |k| {
+                    obj.0
+                        .get(k)
+                        .and_then(WzValue::as_i32)
+                        .map(|v| v.clamp(0, 255) as u8)
+                };
                 delay = obj
                     .0
                     .get("delay")
                     .and_then(|v| v.as_i32())
                     .map(|v| Duration::from_millis(v as u64));
                 origin = obj.0.get("origin").and_then(|v| v.as_vec().cloned());
+                a0 = alpha("a0").unwrap_or(a0);
+                a1 = alpha("a1");
+                z = obj.0.get("z").and_then(WzValue::as_i32);
             }
 
             dim_h = dim_h.max(frame.canvas.height());
@@ -64,11 +178,17 @@ impl Animation {
                 offset: origin,
                 delay,
                 canvas: frame.canvas.clone(),
+                a0,
+                // Without an end alpha the frame doesn't fade
+                a1: a1.unwrap_or(a0),
+                z,
             });
         }
 
         if frames.is_empty() {
-            anyhow::bail!("No frames found in animation");
+            return Err(WzError::NotFound {
+                path: "0".to_string(),
+            });
         }
 
         Ok(Self {
@@ -77,7 +197,7 @@ impl Animation {
         })
     }
 
-    pub fn load_all_frames<R: WzIO>(&self, r: &mut WzImgReader<R>) -> anyhow::Result<Vec<Canvas>> {
+    pub fn load_all_frames<R: WzIO>(&self, r: &mut WzImgReader<R>) -> WzResult<Vec<Canvas>> {
         let mut v = vec![];
         for frame in self.frames.iter() {
             v.push(r.read_canvas(&frame.canvas)?);
@@ -85,6 +205,72 @@ impl Animation {
         Ok(v)
     }
 
+    /// Composites the decoded frames onto a shared canvas, so the `origin`s
+    /// of all frames are at the same position, there must be a canvas per frame
+    pub fn compose(&self, canvases: &[Canvas]) -> WzResult<ComposedAnimation> {
+        if canvases.len() != self.frames.len() {
+            return Err(WzError::malformed(
+                0,
+                format!(
+                    "{} canvases for {} frames",
+                    canvases.len(),
+                    self.frames.len()
+                ),
+            ));
+        }
+        let imgs = canvases
+            .iter()
+            .map(Canvas::to_rgba_image)
+            .collect::<WzResult<Vec<_>>>()?;
+        let origin = |i: usize| {
+            self.frames[i]
+                .offset
+                .as_ref()
+                .map_or((0, 0), |v| (v.x as i64, v.y as i64))
+        };
+
+        // Bounds of all frames relative to the origin
+        let (mut left, mut top, mut right, mut bottom) = (0, 0, 0, 0);
+        for (i, img) in imgs.iter().enumerate() {
+            let (x, y) = origin(i);
+            left = left.min(-x);
+            top = top.min(-y);
+            right = right.max(img.width() as i64 - x);
+            bottom = bottom.max(img.height() as i64 - y);
+        }
+        let (width, height) = (right - left, bottom - top);
+        if width > u16::MAX as i64 || height > u16::MAX as i64 {
+            return Err(WzError::malformed(0, "animation too large"));
+        }
+
+        let frames = imgs
+            .into_iter()
+            .enumerate()
+            .zip(self.frames.iter())
+            .map(|((i, img), frame)| {
+                let (x, y) = origin(i);
+                let mut composed = RgbaImage::new(width as u32, height as u32);
+                imageops::replace(&mut composed, &img, -x - left, -y - top);
+                ComposedFrame {
+                    img: composed,
+                    delay: frame.delay_or_default(),
+                    a0: frame.a0,
+                    a1: frame.a1,
+                }
+            })
+            .collect();
+
+        Ok(ComposedAnimation {
+            frames,
+            origin: ((-left) as u32, (-top) as u32),
+        })
+    }
+
+    /// Reads all frames and composites them
+    pub fn read_composed<R: WzIO>(&self, r: &mut WzImgReader<R>) -> WzResult<ComposedAnimation> {
+        self.compose(&self.load_all_frames(r)?)
+    }
+
     pub fn len(&self) -> usize {
         self.frames.len()
     }
@@ -104,4 +290,294 @@ impl Animation {
     pub fn dim(&self) -> (u32, u32) {
         self.dim
     }
+
+    /// Frame shown at `t` of the looping animation and its alpha
+    pub fn frame_at(&self, t: Duration) -> Option<(usize, u8)> {
+        let (i, elapsed) = find_frame(self.frames.iter().map(|f| f.delay_or_default()), t)?;
+        Some((i, self.frames[i].alpha_at(elapsed)))
+    }
+}
+
+pub struct ComposedFrame {
+    pub img: RgbaImage,
+    pub delay: Duration,
+    pub a0: u8,
+    pub a1: u8,
+}
+
+/// Frames of an animation with the same size and aligned origins
+pub struct ComposedAnimation {
+    pub frames: Vec<ComposedFrame>,
+    /// Position of the shared origin in the frames
+    pub origin: (u32, u32),
+}
+
+impl ComposedAnimation {
+    pub fn dim(&self) -> (u32, u32) {
+        self.frames.first().map_or((0, 0), |f| f.img.dimensions())
+    }
+
+    /// Renders the frame shown at `t` of the looping animation with its alpha
+    pub fn render_at(&self, t: Duration) -> Option<RgbaImage> {
+        let (i, elapsed) = find_frame(self.frames.iter().map(|f| f.delay), t)?;
+        let f = &self.frames[i];
+        Some(apply_alpha(
+            &f.img,
+            tween_alpha(f.a0, f.a1, elapsed, f.delay),
+        ))
+    }
+
+    /// Applies the alpha to the frames, fading frames are split
+    /// into frames of `step` length
+    pub fn tweened(&self, step: Duration) -> Self {
+        let mut frames = Vec::new();
+        for f in self.frames.iter() {
+            let mut elapsed = Duration::ZERO;
+            loop {
+                let delay = if f.a0 == f.a1 || step.is_zero() {
+                    f.delay
+                } else {
+                    step.min(f.delay - elapsed)
+                };
+                let alpha = tween_alpha(f.a0, f.a1, elapsed, f.delay);
+                frames.push(ComposedFrame {
+                    img: apply_alpha(&f.img, alpha),
+                    delay,
+                    a0: u8::MAX,
+                    a1: u8::MAX,
+                });
+                elapsed += delay;
+                if elapsed >= f.delay {
+                    break;
+                }
+            }
+        }
+        Self {
+            frames,
+            origin: self.origin,
+        }
+    }
+
+    /// Writes an endlessly looping GIF, pixels with an alpha
+    /// below 50% become transparent
+    pub fn write_gif<W: Write>(&self, w: W) -> WzResult<()> {
+        let (width, height) = self.dim();
+        let mut enc =
+            gif::Encoder::new(w, width as u16, height as u16, &[]).map_err(io::Error::other)?;
+        enc.set_repeat(gif::Repeat::Infinite)
+            .map_err(io::Error::other)?;
+
+        for ComposedFrame { img, delay, .. } in self.tweened(TWEEN_STEP).frames {
+            let mut pixels = img.into_raw();
+            for px in pixels.chunks_exact_mut(4) {
+                px[3] = if px[3] < 0x80 { 0 } else { 0xFF };
+            }
+            let mut frame =
+                gif::Frame::from_rgba_speed(width as u16, height as u16, &mut pixels, 10);
+            // GIF delays are in 10ms units
+            frame.delay = (delay.as_millis() / 10).min(u16::MAX as u128) as u16;
+            frame.dispose = gif::DisposalMethod::Background;
+            enc.write_frame(&frame).map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+
+    /// Writes an endlessly looping APNG
+    pub fn write_apng<W: Write>(&self, w: W) -> WzResult<()> {
+        let (width, height) = self.dim();
+        let mut enc = png::Encoder::new(w, width, height);
+        enc.set_color(png::ColorType::Rgba);
+        enc.set_depth(png::BitDepth::Eight);
+        let tweened = self.tweened(TWEEN_STEP);
+        enc.set_animated(tweened.frames.len() as u32, 0)
+            .map_err(io::Error::other)?;
+        let mut w = enc.write_header().map_err(io::Error::other)?;
+
+        for ComposedFrame { img, delay, .. } in tweened.frames.iter() {
+            let delay = delay.as_millis().min(u16::MAX as u128) as u16;
+            w.set_frame_delay(delay, 1000).map_err(io::Error::other)?;
+            w.set_dispose_op(png::DisposeOp::Background)
+                .map_err(io::Error::other)?;
+            w.write_image_data(img.as_raw()).map_err(io::Error::other)?;
+        }
+        w.finish().map_err(io::Error::other)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, time::Duration};
+
+    use image::{
+        codecs::{gif::GifDecoder, png::PngDecoder},
+        AnimationDecoder, Rgba, RgbaImage,
+    };
+
+    use crate::{
+        canvas::Canvas,
+        l1::canvas::{WzCanvasDepth, WzCanvasScaling},
+        val::{
+            tests::{canvas, link, obj},
+            Vec2Val, WzValue,
+        },
+    };
+
+    use super::{Animation, AnimationFrame, DEFAULT_FRAME_DELAY};
+
+    #[test]
+    fn linked_frames() {
+        let root = obj(vec![
+            (
+                "stand",
+                obj(vec![
+                    ("0", canvas(2, obj(vec![("delay", WzValue::Int(100))]))),
+                    ("1", canvas(3, obj(vec![]))),
+                ]),
+            ),
+            (
+                "alert",
+                obj(vec![("0", link("../stand/1")), ("1", link("../stand/0"))]),
+            ),
+            ("move", link("alert")),
+        ]);
+
+        for path in ["alert", "move"] {
+            let anim = Animation::from_path(&root, path).unwrap();
+            assert_eq!(anim.len(), 2);
+            assert_eq!(anim.dim(), (3, 1));
+            assert_eq!(anim.frames()[0].canvas.width(), 3);
+            assert!(anim.frames()[1].delay.is_some());
+        }
+    }
+
+    #[test]
+    fn export() {
+        let red = Rgba([0xFF, 0, 0, 0xFF]);
+        let blue = Rgba([0, 0, 0xFF, 0xFF]);
+        let canvases = [
+            Canvas::from_rgba_image(
+                &RgbaImage::from_pixel(2, 2, red),
+                WzCanvasDepth::BGRA8888,
+                WzCanvasScaling(0),
+            ),
+            Canvas::from_rgba_image(
+                &RgbaImage::from_pixel(1, 1, blue),
+                WzCanvasDepth::BGRA8888,
+                WzCanvasScaling(0),
+            ),
+        ];
+        let frame = |origin: Option<Vec2Val>, delay| {
+            let (hdr, _) = Canvas::from_rgba_image(
+                &RgbaImage::new(1, 1),
+                WzCanvasDepth::BGRA8888,
+                WzCanvasScaling(0),
+            )
+            .to_wz_canvas(None, None)
+            .unwrap();
+            AnimationFrame {
+                offset: origin,
+                delay,
+                canvas: hdr,
+                a0: 255,
+                a1: 255,
+                z: None,
+            }
+        };
+        let anim = Animation::from_frames(vec![
+            frame(None, Some(Duration::from_millis(200))),
+            frame(Some(Vec2Val { x: 1, y: 1 }), None),
+        ]);
+
+        assert!(anim.compose(&canvases[..1]).is_err());
+        // More canvases than frames
+        assert!(Animation::from_frames(vec![frame(None, None)])
+            .compose(&canvases)
+            .is_err());
+        let composed = anim.compose(&canvases).unwrap();
+        assert_eq!(composed.dim(), (3, 3));
+        assert_eq!(composed.origin, (1, 1));
+        let (first, delay) = (&composed.frames[0].img, composed.frames[0].delay);
+        assert_eq!(delay, Duration::from_millis(200));
+        assert_eq!(*first.get_pixel(1, 1), red);
+        assert_eq!(first.get_pixel(0, 0)[3], 0);
+        let (second, delay) = (&composed.frames[1].img, composed.frames[1].delay);
+        assert_eq!(delay, DEFAULT_FRAME_DELAY);
+        assert_eq!(*second.get_pixel(0, 0), blue);
+        assert_eq!(second.get_pixel(1, 1)[3], 0);
+
+        let mut gif = Vec::new();
+        composed.write_gif(&mut gif).unwrap();
+        let frames = GifDecoder::new(Cursor::new(gif))
+            .unwrap()
+            .into_frames()
+            .collect_frames()
+            .unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].buffer(), first);
+        assert_eq!(
+            Duration::from(frames[0].delay()),
+            Duration::from_millis(200)
+        );
+
+        let mut apng = Vec::new();
+        composed.write_apng(&mut apng).unwrap();
+        let frames = PngDecoder::new(Cursor::new(apng))
+            .unwrap()
+            .apng()
+            .into_frames()
+            .collect_frames()
+            .unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1].buffer(), second);
+        assert_eq!(Duration::from(frames[1].delay()), DEFAULT_FRAME_DELAY);
+    }
+
+    #[test]
+    fn alpha_tween() {
+        let frame = |sub| canvas(1, obj(sub));
+        let anim = obj(vec![
+            (
+                "0",
+                frame(vec![
+                    ("delay", WzValue::Int(100)),
+                    ("a0", WzValue::Int(255)),
+                    ("a1", WzValue::Int(0)),
+                    ("z", WzValue::Int(-1)),
+                ]),
+            ),
+            ("1", frame(vec![("a0", WzValue::Int(128))])),
+        ]);
+        let anim = Animation::from_path(&anim, "").unwrap();
+        let frames = anim.frames();
+        assert_eq!(
+            (frames[0].a0, frames[0].a1, frames[0].z),
+            (255, 0, Some(-1))
+        );
+        assert_eq!((frames[1].a0, frames[1].a1, frames[1].z), (128, 128, None));
+
+        let ms = Duration::from_millis;
+        assert_eq!(anim.frame_at(ms(0)), Some((0, 255)));
+        assert_eq!(anim.frame_at(ms(50)), Some((0, 128)));
+        assert_eq!(anim.frame_at(ms(150)), Some((1, 128)));
+        // Loops
+        assert_eq!(anim.frame_at(ms(275)), Some((0, 64)));
+
+        let img = RgbaImage::from_pixel(1, 1, Rgba([0xFF, 0, 0, 0xFF]));
+        let composed = anim
+            .compose(&[
+                Canvas::from_rgba_image(&img, WzCanvasDepth::BGRA8888, WzCanvasScaling(0)),
+                Canvas::from_rgba_image(&img, WzCanvasDepth::BGRA8888, WzCanvasScaling(0)),
+            ])
+            .unwrap();
+        assert_eq!(composed.render_at(ms(50)).unwrap().get_pixel(0, 0)[3], 128);
+
+        let tweened = composed.tweened(ms(40));
+        let alphas = tweened
+            .frames
+            .iter()
+            .map(|f| (f.delay.as_millis(), f.img.get_pixel(0, 0)[3]))
+            .collect::<Vec<_>>();
+        assert_eq!(alphas, [(40, 255), (40, 153), (20, 51), (100, 128)]);
+    }
 }
@@ -0,0 +1,480 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use image::{imageops, RgbaImage};
+use serde::Serialize;
+
+use crate::{
+    error::WzResult,
+    file::{WzIO, WzImgReader},
+    util::animation::{Animation, DEFAULT_FRAME_DELAY},
+    val::WzValue,
+};
+
+/// Default width and height of an atlas page
+pub const DEFAULT_ATLAS_SIZE: u32 = 2048;
+
+struct Sprite {
+    name: String,
+    tag: Option<String>,
+    img: RgbaImage,
+    origin: (i32, i32),
+    delay: Duration,
+}
+
+/// Sprite placed on an atlas page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AtlasFrame {
+    /// Path of the canvas like `stand/0`
+    pub name: String,
+    /// Path of the animation the frame belongs to
+    pub tag: Option<String>,
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+    pub origin: (i32, i32),
+    pub delay: Duration,
+}
+
+/// One packed atlas image with its frames in the order they were added
+pub struct AtlasPage {
+    pub img: RgbaImage,
+    pub frames: Vec<AtlasFrame>,
+}
+
+/// Packs the canvases of an image into atlas pages
+///
+/// Every animation, an object with the frames `0`, `1`, ..., becomes a tag
+/// named by its path, so the pages can be loaded as Aseprite sprite sheets.
+pub struct AtlasBuilder {
+    size: u32,
+    padding: u32,
+    sprites: Vec<Sprite>,
+}
+
+impl Default for AtlasBuilder {
+    fn default() -> Self {
+        Self::new(DEFAULT_ATLAS_SIZE)
+    }
+}
+
+impl AtlasBuilder {
+    /// Builder for pages of `size`x`size`, larger sprites get a page of their own
+    pub fn new(size: u32) -> Self {
+        Self {
+            size,
+            padding: 1,
+            sprites: Vec::new(),
+        }
+    }
+
+    /// Transparent pixels between the sprites, 1 by default
+    pub fn padding(mut self, padding: u32) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.sprites.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sprites.is_empty()
+    }
+
+    /// Adds a single image
+    pub fn add_image(&mut self, name: &str, img: RgbaImage, origin: (i32, i32)) {
+        self.sprites.push(Sprite {
+            name: name.to_string(),
+            tag: None,
+            img,
+            origin,
+            delay: DEFAULT_FRAME_DELAY,
+        });
+    }
+
+    /// Adds the frames of the animation as `<tag>/<index>`
+    pub fn add_animation<R: WzIO>(
+        &mut self,
+        r: &mut WzImgReader<R>,
+        tag: &str,
+        anim: &Animation,
+    ) -> WzResult<()> {
+        let canvases = anim.load_all_frames(r)?;
+        for (i, (frame, canvas)) in anim.frames().iter().zip(canvases).enumerate() {
+            self.sprites.push(Sprite {
+                name: format!("{tag}/{i}"),
+                tag: Some(tag.to_string()),
+                img: canvas.to_rgba_image()?,
+                origin: frame.offset.as_ref().map_or((0, 0), |v| (v.x, v.y)),
+                delay: frame.delay_or_default(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Adds every animation and canvas below `path` of the image root
+    pub fn add_value<R: WzIO>(
+        &mut self,
+        r: &mut WzImgReader<R>,
+        root: &WzValue,
+        path: &str,
+    ) -> WzResult<()> {
+        let Some(val) = root.get_path_resolved(path)? else {
+            return Ok(());
+        };
+        let obj = match val {
+            WzValue::Canvas(canvas) => {
+                let origin = val
+                    .get("origin")
+                    .and_then(WzValue::as_vec)
+                    .map_or((0, 0), |v| (v.x, v.y));
+                let img = canvas.read_canvas(r)?.to_rgba_image()?;
+                self.add_image(path, img, origin);
+                return Ok(());
+            }
+            WzValue::Object(obj) => obj,
+            _ => return Ok(()),
+        };
+
+        let child_path = |k: &str| match path {
+            "" => k.to_string(),
+            path => format!("{path}/{k}"),
+        };
+        let is_anim = root
+            .get_path_resolved(&child_path("0"))?
+            .is_some_and(|v| v.as_canvas().is_some());
+        if is_anim {
+            let anim = Animation::from_path(root, path)?;
+            return self
+                .add_animation(r, path, &anim)
+                .map_err(|err| err.in_parent(path));
+        }
+
+        for k in obj.0.keys() {
+            // Links are added at their target
+            if !matches!(obj.0[k], WzValue::Link(_)) {
+                self.add_value(r, root, &child_path(k))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Packs the sprites with a shelf packer, the highest sprites first
+    pub fn pack(&self) -> Vec<AtlasPage> {
+        let mut order = (0..self.sprites.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| std::cmp::Reverse(self.sprites[i].img.height()));
+
+        // Placement and page of every sprite
+        let mut placed = vec![(0, 0, 0); self.sprites.len()];
+        let mut pages = 0;
+        // Page which is currently filled
+        let mut cur = None;
+        let (mut x, mut y, mut shelf_h) = (0, 0, 0);
+        for i in order {
+            let (w, h) = self.sprites[i].img.dimensions();
+            if w > self.size || h > self.size {
+                placed[i] = (pages, 0, 0);
+                pages += 1;
+                continue;
+            }
+            if x + w > self.size {
+                (x, y, shelf_h) = (0, y + shelf_h + self.padding, 0);
+            }
+            let page = match cur {
+                Some(page) if y + h <= self.size => page,
+                _ => {
+                    (x, y, shelf_h) = (0, 0, 0);
+                    pages += 1;
+                    cur = Some(pages - 1);
+                    pages - 1
+                }
+            };
+            placed[i] = (page, x, y);
+            x += w + self.padding;
+            shelf_h = shelf_h.max(h);
+        }
+
+        let mut out = (0..pages)
+            .map(|_| AtlasPage {
+                img: RgbaImage::new(0, 0),
+                frames: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+        for (sprite, &(page, x, y)) in self.sprites.iter().zip(placed.iter()) {
+            let (w, h) = sprite.img.dimensions();
+            out[page].frames.push(AtlasFrame {
+                name: sprite.name.clone(),
+                tag: sprite.tag.clone(),
+                x,
+                y,
+                w,
+                h,
+                origin: sprite.origin,
+                delay: sprite.delay,
+            });
+        }
+
+        for (page, out) in out.iter_mut().enumerate() {
+            let (w, h) = out
+                .frames
+                .iter()
+                .fold((0, 0), |(w, h), f| (w.max(f.x + f.w), h.max(f.y + f.h)));
+            out.img = RgbaImage::new(w, h);
+            for (sprite, &(p, x, y)) in self.sprites.iter().zip(placed.iter()) {
+                if p == page {
+                    imageops::replace(&mut out.img, &sprite.img, x as i64, y as i64);
+                }
+            }
+        }
+        out
+    }
+
+    /// Packs the sprites and writes `<name>-<page>.png` and `<name>-<page>.json`
+    /// in the Aseprite array format, returns the written JSON files
+    pub fn write(&self, out_dir: impl AsRef<Path>, name: &str) -> WzResult<Vec<PathBuf>> {
+        let out_dir = out_dir.as_ref();
+        fs::create_dir_all(out_dir)?;
+
+        let mut files = Vec::new();
+        for (i, page) in self.pack().iter().enumerate() {
+            let png = format!("{name}-{i}.png");
+            page.img
+                .save(out_dir.join(&png))
+                .map_err(io::Error::other)?;
+
+            let json = out_dir.join(format!("{name}-{i}.json"));
+            let mut w = BufWriter::new(File::create(&json)?);
+            serde_json::to_writer_pretty(&mut w, &page.sheet(&png)).map_err(io::Error::from)?;
+            w.flush()?;
+            files.push(json);
+        }
+        Ok(files)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SheetRect {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SheetSize {
+    pub w: u32,
+    pub h: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SheetPoint {
+    pub x: f32,
+    pub y: f32,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SheetFrame {
+    pub filename: String,
+    pub frame: SheetRect,
+    pub rotated: bool,
+    pub trimmed: bool,
+    pub sprite_source_size: SheetRect,
+    pub source_size: SheetSize,
+    /// Origin relative to the frame size
+    pub pivot: SheetPoint,
+    /// Delay in milliseconds
+    pub duration: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SheetTag {
+    pub name: String,
+    pub from: usize,
+    pub to: usize,
+    pub direction: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SheetMeta {
+    pub app: &'static str,
+    pub image: String,
+    pub format: &'static str,
+    pub size: SheetSize,
+    pub scale: &'static str,
+    pub frame_tags: Vec<SheetTag>,
+}
+
+/// Sprite sheet in the array format of Aseprite and TexturePacker
+#[derive(Debug, Serialize)]
+pub struct SpriteSheet {
+    pub frames: Vec<SheetFrame>,
+    pub meta: SheetMeta,
+}
+
+impl AtlasPage {
+    /// Sprite sheet metadata, `image` is the file name of the page image
+    pub fn sheet(&self, image: &str) -> SpriteSheet {
+        let frames = self
+            .frames
+            .iter()
+            .map(|f| {
+                let pivot = |o: i32, n: u32| if n == 0 { 0. } else { o as f32 / n as f32 };
+                SheetFrame {
+                    filename: f.name.clone(),
+                    frame: SheetRect {
+                        x: f.x,
+                        y: f.y,
+                        w: f.w,
+                        h: f.h,
+                    },
+                    rotated: false,
+                    trimmed: false,
+                    sprite_source_size: SheetRect {
+                        x: 0,
+                        y: 0,
+                        w: f.w,
+                        h: f.h,
+                    },
+                    source_size: SheetSize { w: f.w, h: f.h },
+                    pivot: SheetPoint {
+                        x: pivot(f.origin.0, f.w),
+                        y: pivot(f.origin.1, f.h),
+                    },
+                    duration: f.delay.as_millis() as u64,
+                }
+            })
+            .collect();
+
+        // Tags refer to the frame indices of this page, frames of a tag are consecutive
+        let mut frame_tags: Vec<SheetTag> = Vec::new();
+        for (i, f) in self.frames.iter().enumerate() {
+            let Some(tag) = f.tag.as_ref() else {
+                continue;
+            };
+            match frame_tags.last_mut() {
+                Some(last) if last.name == *tag && last.to + 1 == i => last.to = i,
+                _ => frame_tags.push(SheetTag {
+                    name: tag.clone(),
+                    from: i,
+                    to: i,
+                    direction: "forward",
+                }),
+            }
+        }
+
+        SpriteSheet {
+            frames,
+            meta: SheetMeta {
+                app: "shroom-wz",
+                image: image.to_string(),
+                format: "RGBA8888",
+                size: SheetSize {
+                    w: self.img.width(),
+                    h: self.img.height(),
+                },
+                scale: "1",
+                frame_tags,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::{
+        crypto::WzCrypto,
+        link::tests::{canvas, img},
+        util::{WzContext, WzStrTable},
+        val::WzValue,
+        version::{WzRegion, WzVersion},
+        WzReader,
+    };
+
+    use super::AtlasBuilder;
+
+    #[test]
+    fn pack() {
+        let crypto = WzCrypto::from_region(WzRegion::GMS, WzVersion(95), 0);
+        let str_table = WzStrTable::default();
+        let ctx = WzContext::new(&crypto, &str_table);
+        let data = img(
+            ctx,
+            &[
+                (
+                    "stand",
+                    img(
+                        ctx,
+                        &[
+                            ("0", canvas(ctx, [0, 0, 0xFF, 0xFF], &[])),
+                            ("1", canvas(ctx, [0, 0xFF, 0, 0xFF], &[])),
+                        ],
+                    ),
+                ),
+                ("icon", canvas(ctx, [0xFF, 0, 0, 0xFF], &[])),
+            ],
+        );
+
+        let mut r = WzReader::open_img(Cursor::new(data), WzRegion::GMS, WzVersion(95)).unwrap();
+        let mut img = r.root_img_reader().unwrap();
+        let root = WzValue::read(&mut img).unwrap();
+
+        let mut atlas = AtlasBuilder::new(3);
+        atlas.add_value(&mut img, &root, "").unwrap();
+        assert_eq!(atlas.len(), 3);
+
+        let pages = atlas.pack();
+        assert_eq!(pages.len(), 1);
+        let page = &pages[0];
+        assert_eq!(page.img.dimensions(), (3, 3));
+        let names = page
+            .frames
+            .iter()
+            .map(|f| f.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["stand/0", "stand/1", "icon"]);
+        let icon = &page.frames[2];
+        // BGRA in the canvas
+        assert_eq!(page.img.get_pixel(icon.x, icon.y).0, [0, 0, 0xFF, 0xFF]);
+
+        let sheet = page.sheet("mob-0.png");
+        assert_eq!(sheet.meta.frame_tags.len(), 1);
+        assert_eq!(sheet.meta.frame_tags[0].name, "stand");
+        assert_eq!(
+            (sheet.meta.frame_tags[0].from, sheet.meta.frame_tags[0].to),
+            (0, 1)
+        );
+
+        // A sprite per page, the tag is split
+        let mut small = AtlasBuilder::new(2).padding(1);
+        small.add_value(&mut img, &root, "").unwrap();
+        let pages = small.pack();
+        assert_eq!(pages.len(), 3);
+        assert!(pages.iter().all(|page| page.img.dimensions() == (1, 1)));
+        let tags = pages
+            .iter()
+            .flat_map(|page| page.sheet("").meta.frame_tags)
+            .map(|tag| tag.name)
+            .collect::<Vec<_>>();
+        assert_eq!(tags, ["stand", "stand"]);
+
+        let out_dir = std::env::temp_dir().join(format!("shroom-wz-atlas-{}", std::process::id()));
+        let files = atlas.write(&out_dir, "mob").unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&files[0]).unwrap()).unwrap();
+        assert!(out_dir.join("mob-0.png").exists());
+        std::fs::remove_dir_all(&out_dir).unwrap();
+        assert_eq!(json["frames"][1]["filename"], "stand/1");
+        assert_eq!(json["frames"][1]["duration"], 100);
+        assert_eq!(json["meta"]["frameTags"][0]["to"], 1);
+    }
+}
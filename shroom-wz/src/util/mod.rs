@@ -1,15 +1,63 @@
 use std::{
-    cell::RefCell,
     collections::HashMap,
+    ffi::OsString,
     io::{self, BufRead, Read, Seek, SeekFrom, Write},
-    rc::Rc,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, PoisonError, RwLock},
 };
 
 pub mod animation;
+pub mod atlas;
+pub mod xml;
 
 use binrw::BinRead;
 
-use crate::{crypto::WzCrypto, ty::WzStr};
+use crate::{
+    crypto::WzCrypto,
+    error::{WzError, WzResult},
+    ty::WzStr,
+};
+
+/// Reads exactly `n` bytes, without allocating `n` bytes upfront for untrusted lengths
+pub fn read_vec<R: Read>(r: &mut R, n: usize) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    r.take(n as u64).read_to_end(&mut buf)?;
+    if buf.len() != n {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(buf)
+}
+
+/// Checks that a name from an archive is a single file name,
+/// so empty names, `.`, `..`, absolute paths and separators are rejected
+pub fn file_name(name: &str) -> WzResult<&str> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) if !name.contains(['/', '\\']) => Ok(name),
+        _ => Err(WzError::InvalidName {
+            name: name.to_string(),
+            path: None,
+        }),
+    }
+}
+
+/// Joins the `/` separated path onto `dir`, every component is checked with [`file_name`]
+pub fn join_path(dir: &Path, path: &str) -> WzResult<PathBuf> {
+    let mut out = dir.to_path_buf();
+    for name in path.split('/') {
+        out.push(file_name(name)?);
+    }
+    Ok(out)
+}
+
+/// Appends `.ext` to the file name, unlike `with_extension` which replaces
+/// everything after the last dot
+pub fn append_ext(path: &Path, ext: &str) -> PathBuf {
+    let mut s = OsString::from(path);
+    s.push(".");
+    s.push(ext);
+    s.into()
+}
 
 pub trait BufReadExt: BufRead {
     fn read_n<const N: usize>(&mut self) -> io::Result<[u8; N]> {
@@ -45,10 +93,9 @@ pub trait BufReadExt: BufRead {
             i += 4;
 
             if chunk_size > chunked_len {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("Bad chunk size {chunk_size}, max: {chunked_len}"),
-                ));
+                return Err(io::Error::other(format!(
+                    "Bad chunk size {chunk_size}, max: {chunked_len}"
+                )));
             }
             let n = buf.len();
             buf.resize(n + chunk_size, 0);
@@ -66,9 +113,16 @@ pub trait BufReadExt: BufRead {
         flate2::bufread::ZlibDecoder::new(self).read_to_end(buf)
     }
 
+    /// Decompresses exactly `size` bytes, the buffer grows with the decompressed data,
+    /// so a bogus `size` can't force a large allocation
     fn decompress_flate_size(&mut self, buf: &mut Vec<u8>, size: usize) -> io::Result<usize> {
-        buf.resize(size, 0);
-        flate2::bufread::ZlibDecoder::new(self).read_exact(buf)?;
+        let start = buf.len();
+        flate2::bufread::ZlibDecoder::new(self)
+            .take(size as u64)
+            .read_to_end(buf)?;
+        if buf.len() - start < size {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
         Ok(size)
     }
 }
@@ -84,7 +138,7 @@ pub trait WriteExt: Write {
         &mut self,
         crypto: &WzCrypto,
         chunks: impl Iterator<Item = &'a mut [u8]>,
-    ) -> anyhow::Result<usize> {
+    ) -> io::Result<usize> {
         let mut written = 0;
 
         for chunk in chunks {
@@ -107,7 +161,60 @@ pub trait WriteExt: Write {
 
 impl<T: Write> WriteExt for T {}
 
-pub type WzStrTable = Rc<RefCell<HashMap<u32, Rc<WzStr>>>>;
+/// Cache for the offset strings of images, which can be shared between threads
+///
+/// Offsets are relative to the image, so every image reader gets a view
+/// with the base offset of its image
+#[derive(Debug, Clone, Default)]
+pub struct WzStrTable {
+    strings: Arc<RwLock<HashMap<u64, Arc<WzStr>>>>,
+    base: u64,
+}
+
+impl WzStrTable {
+    /// View of the table for the image at `base`
+    pub fn with_base(&self, base: u64) -> Self {
+        Self {
+            strings: self.strings.clone(),
+            base,
+        }
+    }
+
+    pub fn get(&self, offset: u32) -> Option<Arc<WzStr>> {
+        let strings = self.strings.read().unwrap_or_else(PoisonError::into_inner);
+        strings.get(&(self.base + offset as u64)).cloned()
+    }
+
+    pub fn insert(&self, offset: u32, s: Arc<WzStr>) {
+        let mut strings = self.strings.write().unwrap_or_else(PoisonError::into_inner);
+        strings.insert(self.base + offset as u64, s);
+    }
+}
+
+/// Bytes which can be cloned cheaply and shared between threads,
+/// so every thread can read an archive with its own cursor
+#[derive(Clone)]
+pub struct SharedData(Arc<dyn AsRef<[u8]> + Send + Sync>);
+
+impl SharedData {
+    pub fn new(data: impl AsRef<[u8]> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(data))
+    }
+}
+
+impl AsRef<[u8]> for SharedData {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_ref().as_ref()
+    }
+}
+
+impl std::fmt::Debug for SharedData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("SharedData")
+            .field(&self.as_ref().len())
+            .finish()
+    }
+}
 
 #[derive(Debug, Clone, Copy)]
 pub struct WzContext<'a> {
@@ -120,77 +227,119 @@ impl<'a> WzContext<'a> {
         Self { crypto, str_table }
     }
 
-    pub fn read_offset_str<R: Read + Seek>(
-        &self,
-        r: &mut R,
-        offset: u32,
-    ) -> anyhow::Result<Rc<WzStr>> {
-        if let Some(s) = self.str_table.borrow().get(&offset) {
-            return Ok(s.clone());
+    pub fn read_offset_str<R: Read + Seek>(&self, r: &mut R, offset: u32) -> WzResult<Arc<WzStr>> {
+        if let Some(s) = self.str_table.get(offset) {
+            return Ok(s);
         }
 
         let pos = r.stream_position()?;
+        // Strings are only referenced after they were written
+        if offset as u64 >= pos {
+            return Err(WzError::BadOffset {
+                pos,
+                offset: offset as u64,
+                path: None,
+            });
+        }
         r.seek(SeekFrom::Start(offset as u64))?;
-        let str = Rc::new(WzStr::read_le_args(r, *self)?);
+        let str = Arc::new(
+            WzStr::read_le_args(r, *self).map_err(|err| WzError::from_binrw(err, offset as u64))?,
+        );
         r.seek(SeekFrom::Start(pos))?;
-        self.str_table.borrow_mut().insert(offset, str.clone());
+        self.str_table.insert(offset, str.clone());
         Ok(str)
     }
 }
 
-pub struct SubReader<'a, R> {
-    inner: &'a mut R,
+/// Reader for the range `[offset, offset+size)` of the inner reader,
+/// reads end at the end of the range and seeks outside of It fail
+#[derive(Debug)]
+pub struct BoundedReader<R> {
+    inner: R,
     offset: u64,
     size: u64,
+    /// Position relative to `offset`
+    pos: u64,
+}
+
+/// Bounded reader which borrows the reader of the archive
+pub type SubReader<'a, R> = BoundedReader<&'a mut R>;
+
+impl<R> BoundedReader<R> {
+    fn remaining(&self) -> usize {
+        (self.size - self.pos).try_into().unwrap_or(usize::MAX)
+    }
 }
 
-impl<'a, R> Read for SubReader<'a, R>
+impl<R> Read for BoundedReader<R>
 where
     R: Read,
 {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.inner.read(buf)
+        let n = buf.len().min(self.remaining());
+        let n = self.inner.read(&mut buf[..n])?;
+        self.pos += n as u64;
+        Ok(n)
     }
 }
 
-impl<'a, R> BufRead for SubReader<'a, R>
+impl<R> BufRead for BoundedReader<R>
 where
     R: BufRead,
 {
     fn fill_buf(&mut self) -> io::Result<&[u8]> {
-        self.inner.fill_buf()
+        let n = self.remaining();
+        let buf = self.inner.fill_buf()?;
+        Ok(&buf[..buf.len().min(n)])
     }
 
     fn consume(&mut self, amt: usize) {
-        self.inner.consume(amt)
+        let amt = amt.min(self.remaining());
+        self.inner.consume(amt);
+        self.pos += amt as u64;
     }
 }
 
-// TODO this MUST be tested
-impl<'a, R> Seek for SubReader<'a, R>
+impl<R> Seek for BoundedReader<R>
 where
     R: Seek,
 {
     fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
-        let pos = match pos {
-            SeekFrom::Current(p) => SeekFrom::Current(p),
-            SeekFrom::End(p) => SeekFrom::End((self.offset + self.size) as i64 + p),
-            SeekFrom::Start(p) => SeekFrom::Start(p + self.offset),
+        let new_pos = match pos {
+            SeekFrom::Current(p) => self.pos.checked_add_signed(p),
+            SeekFrom::End(p) => self.size.checked_add_signed(p),
+            SeekFrom::Start(p) => Some(p),
         };
-        self.inner.seek(pos).map(|p| p - self.offset)
+        let new_pos = new_pos.filter(|p| *p <= self.size).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Seek {pos:?} out of bounds, size: {}", self.size),
+            )
+        })?;
+
+        self.inner.seek(SeekFrom::Start(self.offset + new_pos))?;
+        self.pos = new_pos;
+        Ok(new_pos)
+    }
+
+    fn stream_position(&mut self) -> io::Result<u64> {
+        Ok(self.pos)
     }
 }
 
-impl<'a, R> SubReader<'a, R>
+impl<R> BoundedReader<R>
 where
     R: Read + Seek,
 {
-    pub fn new(r: &'a mut R, offset: u64, size: u64) -> Self {
-        Self {
+    /// Creates the reader and seeks the inner reader to `offset`
+    pub fn new(mut r: R, offset: u64, size: u64) -> io::Result<Self> {
+        r.seek(SeekFrom::Start(offset))?;
+        Ok(Self {
             inner: r,
             offset,
             size,
-        }
+            pos: 0,
+        })
     }
 }
 
@@ -202,6 +351,30 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn paths() {
+        let dir = Path::new("out");
+        assert_eq!(
+            join_path(dir, "Mob/a.1.img").unwrap(),
+            Path::new("out/Mob/a.1.img")
+        );
+        for path in [
+            "",
+            "..",
+            "Mob/../..",
+            "Mob//a",
+            "/etc/passwd",
+            "a\\..\\b",
+            ".",
+        ] {
+            assert!(
+                matches!(join_path(dir, path), Err(WzError::InvalidName { .. })),
+                "{path}"
+            );
+        }
+        assert_eq!(append_ext(Path::new("a.1"), "png"), Path::new("a.1.png"));
+    }
+
     #[test]
     fn read_peek() {
         let data = [0x1, 0x2, 0x3, 0x4];
@@ -216,6 +389,32 @@ mod tests {
         assert!(r.peek_n::<1>().is_err());
     }
 
+    #[test]
+    fn sub_reader() {
+        let mut inner = BufReader::new(Cursor::new([0, 1, 2, 3, 4, 5, 6, 7]));
+        let mut r = SubReader::new(&mut inner, 2, 4).unwrap();
+
+        assert_eq!(r.fill_buf().unwrap(), [2, 3, 4, 5]);
+        assert_eq!(r.read_n().unwrap(), [2, 3]);
+        assert_eq!(r.stream_position().unwrap(), 2);
+
+        let mut buf = Vec::new();
+        r.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, [4, 5]);
+        assert!(r.read_n::<1>().is_err());
+
+        assert_eq!(r.seek(SeekFrom::End(-1)).unwrap(), 3);
+        assert_eq!(r.read_n().unwrap(), [5]);
+        assert_eq!(r.seek(SeekFrom::Current(-3)).unwrap(), 1);
+        assert_eq!(r.read_n().unwrap(), [3]);
+
+        assert!(r.seek(SeekFrom::Start(5)).is_err());
+        assert!(r.seek(SeekFrom::Current(-3)).is_err());
+        assert!(r.seek(SeekFrom::End(1)).is_err());
+        // Failed seeks keep the position
+        assert_eq!(r.read_n().unwrap(), [4]);
+    }
+
     #[test]
     fn chunked() {
         let mut rw = Cursor::new(Vec::new());
@@ -0,0 +1,821 @@
+//! XML in the format of the classic WZ dumpers
+//!
+//! Every value is an element with a `name` attribute, like
+//! `<imgdir name="info"><int name="level" value="1"/></imgdir>`.
+//! Canvases only keep their dimensions and properties,
+//! the bitmaps can be exported as PNG files next to the XML.
+//! Bitmaps embedded as base64 PNG `basedata` are read as well.
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use image::RgbaImage;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use binrw::PosValue;
+
+use crate::{
+    canvas::Canvas,
+    error::{WzError, WzResult},
+    file::{WzIO, WzImgReader},
+    l1::{
+        canvas::{WzCanvas, WzCanvasDepth, WzCanvasScaling},
+        sound::{SoundHeader, WzSound},
+    },
+    ty::WzInt,
+    util::{append_ext, join_path},
+    val::{CanvasVal, Map, ObjectVal, Payload, SoundVal, Vec2Val, Vex2Val, WzValue},
+};
+
+const XML_HEADER: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#;
+
+fn escape(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            '\'' => res.push_str("&apos;"),
+            c if c.is_control() => res.push_str(&format!("&#{};", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+fn png_path(dir: &Path, path: &str) -> WzResult<PathBuf> {
+    Ok(append_ext(&join_path(dir, path)?, "png"))
+}
+
+fn child_path(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{path}/{name}")
+    }
+}
+
+/// Writes images as XML
+pub struct XmlWriter<W> {
+    w: W,
+    png_dir: Option<PathBuf>,
+}
+
+impl<W: Write> XmlWriter<W> {
+    pub fn new(w: W) -> Self {
+        Self { w, png_dir: None }
+    }
+
+    /// Exports the canvases of images as `<dir>/<path>.png`
+    pub fn png_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.png_dir = Some(dir.into());
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+
+    /// Writes the root value of an image as `<imgdir name="...">`
+    pub fn write_value(&mut self, name: &str, val: &WzValue) -> WzResult<()> {
+        writeln!(self.w, "{XML_HEADER}")?;
+        self.write_node(name, val, 0)
+    }
+
+    /// Writes the image and exports its canvases, if there's a PNG directory
+    pub fn write_img<R: WzIO>(&mut self, name: &str, r: &mut WzImgReader<R>) -> WzResult<()> {
+        let val = WzValue::read(r)?;
+        self.write_value(name, &val)?;
+        if let Some(dir) = self.png_dir.clone() {
+            Self::export_pngs(r, &dir, &val, "")?;
+        }
+        Ok(())
+    }
+
+    fn export_pngs<R: WzIO>(
+        r: &mut WzImgReader<R>,
+        dir: &Path,
+        val: &WzValue,
+        path: &str,
+    ) -> WzResult<()> {
+        let children = match val {
+            WzValue::Object(obj) => obj,
+            WzValue::Canvas(canvas) => {
+                let file = png_path(dir, path)?;
+                if let Some(parent) = file.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                canvas
+                    .read_canvas(r)?
+                    .to_rgba_image()?
+                    .save(file)
+                    .map_err(io::Error::other)?;
+                match canvas.sub.as_deref() {
+                    Some(WzValue::Object(obj)) => obj,
+                    _ => return Ok(()),
+                }
+            }
+            _ => return Ok(()),
+        };
+        for (k, v) in children.0.iter() {
+            Self::export_pngs(r, dir, v, &child_path(path, k)).map_err(|err| err.in_parent(k))?;
+        }
+        Ok(())
+    }
+
+    fn write_children(
+        &mut self,
+        tag: &str,
+        head: &str,
+        obj: &ObjectVal,
+        depth: usize,
+    ) -> WzResult<()> {
+        let indent = "  ".repeat(depth);
+        if obj.0.is_empty() {
+            writeln!(self.w, "{indent}<{head}/>")?;
+            return Ok(());
+        }
+        writeln!(self.w, "{indent}<{head}>")?;
+        for (k, v) in obj.0.iter() {
+            self.write_node(k, v, depth + 1)?;
+        }
+        writeln!(self.w, "{indent}</{tag}>")?;
+        Ok(())
+    }
+
+    fn write_node(&mut self, name: &str, val: &WzValue, depth: usize) -> WzResult<()> {
+        let indent = "  ".repeat(depth);
+        let name = escape(name);
+        let leaf = |tag: &str, value: &dyn std::fmt::Display| {
+            format!("{indent}<{tag} name=\"{name}\" value=\"{value}\"/>")
+        };
+        let line = match val {
+            WzValue::Object(obj) => {
+                return self.write_children(
+                    "imgdir",
+                    &format!("imgdir name=\"{name}\""),
+                    obj,
+                    depth,
+                )
+            }
+            WzValue::Canvas(canvas) => {
+                let head = format!(
+                    "canvas name=\"{name}\" width=\"{}\" height=\"{}\"",
+                    canvas.canvas.width.0, canvas.canvas.height.0
+                );
+                let empty = ObjectVal(Map::new());
+                let sub = canvas.sub.as_deref().and_then(WzValue::as_object);
+                return self.write_children("canvas", &head, sub.unwrap_or(&empty), depth);
+            }
+            WzValue::Convex(vex) => {
+                let obj = ObjectVal(
+                    vex.0
+                        .iter()
+                        .enumerate()
+                        .map(|(i, v)| (i.to_string(), WzValue::Vec(*v)))
+                        .collect(),
+                );
+                return self.write_children(
+                    "extended",
+                    &format!("extended name=\"{name}\""),
+                    &obj,
+                    depth,
+                );
+            }
+            WzValue::Null => format!("{indent}<null name=\"{name}\"/>"),
+            WzValue::Short(v) => leaf("short", v),
+            WzValue::Int(v) => leaf("int", v),
+            WzValue::Long(v) => leaf("long", v),
+            WzValue::F32(v) => leaf("float", v),
+            WzValue::F64(v) => leaf("double", v),
+            WzValue::String(v) => leaf("string", &escape(v)),
+            WzValue::Link(v) => leaf("uol", &escape(v)),
+            WzValue::Vec(v) => format!(
+                "{indent}<vector name=\"{name}\" x=\"{}\" y=\"{}\"/>",
+                v.x, v.y
+            ),
+            WzValue::Sound(sound) => {
+                let head = STANDARD.encode(sound.sound.header.to_bytes()?);
+                let data = match sound.payload.as_ref().and_then(Payload::data) {
+                    Some(data) => format!(" basedata=\"{}\"", STANDARD.encode(data)),
+                    None => String::new(),
+                };
+                format!(
+                    "{indent}<sound name=\"{name}\" length=\"{}\" basehead=\"{head}\"{data}/>",
+                    sound.sound.len_ms.0
+                )
+            }
+        };
+        writeln!(self.w, "{line}")?;
+        Ok(())
+    }
+}
+
+/// Element of an XML document, text is ignored
+#[derive(Debug)]
+struct XmlNode {
+    tag: String,
+    attrs: Vec<(String, String)>,
+    children: Vec<XmlNode>,
+    pos: usize,
+}
+
+impl XmlNode {
+    fn attr(&self, name: &str) -> WzResult<&str> {
+        self.attrs
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+            .ok_or_else(|| {
+                WzError::malformed(
+                    self.pos as u64,
+                    format!("<{}> has no attribute {name}", self.tag),
+                )
+            })
+    }
+
+    fn parse_attr<T: std::str::FromStr>(&self, name: &str) -> WzResult<T> {
+        let v = self.attr(name)?;
+        v.trim().parse().map_err(|_| {
+            WzError::malformed(
+                self.pos as u64,
+                format!("invalid {name} of <{}>: {v}", self.tag),
+            )
+        })
+    }
+
+    fn base64_attr(&self, name: &str) -> WzResult<Vec<u8>> {
+        STANDARD
+            .decode(self.attr(name)?)
+            .map_err(|err| WzError::malformed(self.pos as u64, format!("invalid {name}: {err}")))
+    }
+}
+
+/// Nesting limit of the elements, so deep documents can't overflow the stack
+const MAX_XML_DEPTH: usize = 256;
+
+/// Parser for the subset of XML which is used by the dumps
+struct XmlParser<'a> {
+    s: &'a str,
+    pos: usize,
+    // Depth of the current element
+    depth: usize,
+}
+
+impl<'a> XmlParser<'a> {
+    fn err(&self, msg: impl Into<String>) -> WzError {
+        WzError::malformed(self.pos as u64, msg)
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.s[self.pos..]
+    }
+
+    fn skip_ws(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn skip_past(&mut self, end: &str) -> WzResult<()> {
+        match self.rest().find(end) {
+            Some(i) => {
+                self.pos += i + end.len();
+                Ok(())
+            }
+            None => Err(self.err(format!("missing {end}"))),
+        }
+    }
+
+    fn expect(&mut self, s: &str) -> WzResult<()> {
+        if !self.rest().starts_with(s) {
+            return Err(self.err(format!("expected {s}")));
+        }
+        self.pos += s.len();
+        Ok(())
+    }
+
+    /// Skips text, declarations and comments up to the next element or end tag
+    fn skip_misc(&mut self) -> WzResult<()> {
+        loop {
+            match self.rest().find('<') {
+                Some(i) => self.pos += i,
+                None => {
+                    self.pos = self.s.len();
+                    return Ok(());
+                }
+            }
+            let rest = self.rest();
+            if rest.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if rest.starts_with("<!") {
+                self.skip_past(">")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn name(&mut self) -> WzResult<&'a str> {
+        let rest = self.rest();
+        let n = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '/' | '>' | '='))
+            .unwrap_or(rest.len());
+        if n == 0 {
+            return Err(self.err("expected a name"));
+        }
+        self.pos += n;
+        Ok(&rest[..n])
+    }
+
+    fn unescape(&self, s: &str) -> WzResult<String> {
+        let mut res = String::with_capacity(s.len());
+        let mut rest = s;
+        while let Some(i) = rest.find('&') {
+            res.push_str(&rest[..i]);
+            rest = &rest[i..];
+            let end = rest
+                .find(';')
+                .ok_or_else(|| self.err("unterminated entity"))?;
+            let entity = &rest[1..end];
+            let c = match entity {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                _ => entity
+                    .strip_prefix("#x")
+                    .map(|hex| u32::from_str_radix(hex, 16))
+                    .or_else(|| entity.strip_prefix('#').map(str::parse))
+                    .and_then(Result::ok)
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| self.err(format!("unknown entity &{entity};")))?,
+            };
+            res.push(c);
+            rest = &rest[end + 1..];
+        }
+        res.push_str(rest);
+        Ok(res)
+    }
+
+    fn node(&mut self) -> WzResult<XmlNode> {
+        if self.depth >= MAX_XML_DEPTH {
+            return Err(self.err(format!("elements nested deeper than {MAX_XML_DEPTH}")));
+        }
+        let pos = self.pos;
+        self.expect("<")?;
+        let tag = self.name()?.to_string();
+        let mut node = XmlNode {
+            tag,
+            attrs: Vec::new(),
+            children: Vec::new(),
+            pos,
+        };
+
+        loop {
+            self.skip_ws();
+            if self.rest().starts_with("/>") {
+                self.pos += 2;
+                return Ok(node);
+            }
+            if self.rest().starts_with('>') {
+                self.pos += 1;
+                break;
+            }
+            let k = self.name()?.to_string();
+            self.skip_ws();
+            self.expect("=")?;
+            self.skip_ws();
+            let quote = match self.rest().chars().next() {
+                Some(q @ ('"' | '\'')) => q,
+                _ => return Err(self.err("expected a quoted value")),
+            };
+            self.pos += 1;
+            let end = self
+                .rest()
+                .find(quote)
+                .ok_or_else(|| self.err("unterminated value"))?;
+            let v = self.unescape(&self.rest()[..end])?;
+            self.pos += end + 1;
+            node.attrs.push((k, v));
+        }
+
+        loop {
+            self.skip_misc()?;
+            if self.rest().starts_with("</") {
+                self.pos += 2;
+                if self.name()? != node.tag {
+                    return Err(self.err(format!("expected </{}>", node.tag)));
+                }
+                self.skip_ws();
+                self.expect(">")?;
+                return Ok(node);
+            }
+            if self.rest().is_empty() {
+                return Err(self.err(format!("missing </{}>", node.tag)));
+            }
+            self.depth += 1;
+            let child = self.node();
+            self.depth -= 1;
+            node.children.push(child?);
+        }
+    }
+}
+
+/// Reads images from XML
+#[derive(Debug, Default)]
+pub struct XmlReader {
+    png_dir: Option<PathBuf>,
+}
+
+impl XmlReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the canvases from `<dir>/<path>.png`, canvases without `basedata`
+    /// or a PNG only have a header and writing them fails
+    pub fn png_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.png_dir = Some(dir.into());
+        self
+    }
+
+    /// Reads the document and returns the name of the root `<imgdir>` and its value
+    pub fn read(&self, xml: &str) -> WzResult<(String, WzValue)> {
+        let mut p = XmlParser {
+            s: xml,
+            pos: 0,
+            depth: 0,
+        };
+        p.skip_misc()?;
+        let root = p.node()?;
+        if root.tag != "imgdir" {
+            return Err(WzError::malformed(
+                root.pos as u64,
+                "root must be an <imgdir>",
+            ));
+        }
+        let name = root.attr("name")?.to_string();
+        Ok((name, self.read_node(&root, "")?))
+    }
+
+    fn read_children(&self, node: &XmlNode, path: &str) -> WzResult<ObjectVal> {
+        let mut map = Map::new();
+        for child in node.children.iter() {
+            let name = child.attr("name")?;
+            let v = self
+                .read_node(child, &child_path(path, name))
+                .map_err(|err| err.in_parent(name))?;
+            map.insert(name.to_string(), v);
+        }
+        Ok(ObjectVal(map))
+    }
+
+    fn read_node(&self, node: &XmlNode, path: &str) -> WzResult<WzValue> {
+        Ok(match node.tag.as_str() {
+            "imgdir" => WzValue::Object(self.read_children(node, path)?),
+            "null" => WzValue::Null,
+            "short" => WzValue::Short(node.parse_attr("value")?),
+            "int" => WzValue::Int(node.parse_attr("value")?),
+            "long" => WzValue::Long(node.parse_attr("value")?),
+            "float" => WzValue::F32(node.parse_attr("value")?),
+            "double" => WzValue::F64(node.parse_attr("value")?),
+            "string" => WzValue::String(node.attr("value")?.to_string()),
+            "uol" => WzValue::Link(node.attr("value")?.to_string()),
+            "vector" => WzValue::Vec(Vec2Val {
+                x: node.parse_attr("x")?,
+                y: node.parse_attr("y")?,
+            }),
+            "extended" => WzValue::Convex(Vex2Val(
+                node.children
+                    .iter()
+                    .map(|v| {
+                        Ok(Vec2Val {
+                            x: v.parse_attr("x")?,
+                            y: v.parse_attr("y")?,
+                        })
+                    })
+                    .collect::<WzResult<_>>()?,
+            )),
+            "canvas" => {
+                let sub = if node.children.is_empty() {
+                    None
+                } else {
+                    Some(Box::new(WzValue::Object(self.read_children(node, path)?)))
+                };
+                WzValue::Canvas(self.read_canvas(node, path, sub)?)
+            }
+            "sound" => {
+                let header = SoundHeader::from_bytes(&node.base64_attr("basehead")?)?;
+                let data = node
+                    .attr("basedata")
+                    .is_ok()
+                    .then(|| node.base64_attr("basedata"))
+                    .transpose()?;
+                WzValue::Sound(SoundVal {
+                    sound: WzSound {
+                        unknown: 0,
+                        size: WzInt(data.as_ref().map_or(0, Vec::len) as i32),
+                        len_ms: WzInt(node.parse_attr("length")?),
+                        header,
+                        offset: PosValue { val: (), pos: 0 },
+                    },
+                    payload: Some(data.map_or(Payload::Missing, Payload::Data)),
+                })
+            }
+            tag => {
+                return Err(WzError::UnknownObject {
+                    pos: node.pos as u64,
+                    ty: tag.to_string(),
+                    path: None,
+                })
+            }
+        })
+    }
+
+    fn read_canvas(
+        &self,
+        node: &XmlNode,
+        path: &str,
+        sub: Option<Box<WzValue>>,
+    ) -> WzResult<CanvasVal> {
+        let (canvas, payload) = match self.read_bitmap(node, path)? {
+            Some(img) => {
+                let (canvas, payload) =
+                    Canvas::from_rgba_image(&img, WzCanvasDepth::BGRA8888, WzCanvasScaling(0))
+                        .to_wz_canvas(None, None)?;
+                (canvas, Some(Payload::Data(payload)))
+            }
+            None => (
+                WzCanvas {
+                    unknown: 0,
+                    has_property: 0,
+                    property: None,
+                    width: WzInt(node.parse_attr("width")?),
+                    height: WzInt(node.parse_attr("height")?),
+                    depth: WzCanvasDepth::BGRA8888,
+                    scale: WzCanvasScaling(0),
+                    unknown1: 0,
+                    len: PosValue { val: 0, pos: 0 },
+                },
+                Some(Payload::Missing),
+            ),
+        };
+        Ok(CanvasVal {
+            canvas: WzCanvas {
+                has_property: sub.is_some() as u8,
+                ..canvas
+            },
+            sub,
+            payload,
+        })
+    }
+
+    /// Embedded `basedata` takes precedence over the PNG files
+    fn read_bitmap(&self, node: &XmlNode, path: &str) -> WzResult<Option<RgbaImage>> {
+        if node.attr("basedata").is_ok() {
+            let img = image::load_from_memory(&node.base64_attr("basedata")?).map_err(|err| {
+                WzError::malformed(node.pos as u64, format!("invalid basedata: {err}"))
+            })?;
+            return Ok(Some(img.to_rgba8()));
+        }
+        let Some(dir) = self.png_dir.as_ref() else {
+            return Ok(None);
+        };
+        let file = png_path(dir, path)?;
+        if !file.exists() {
+            return Ok(None);
+        }
+        Ok(Some(
+            image::open(file).map_err(io::Error::other)?.to_rgba8(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use binrw::PosValue;
+    use image::{ImageFormat, RgbaImage};
+
+    use crate::{
+        crypto::WzCrypto,
+        error::WzError,
+        l1::sound::{tests::pcm_header, WzSound},
+        link::tests::{canvas as canvas_obj, img},
+        ty::WzInt,
+        util::{WzContext, WzStrTable},
+        val::{
+            tests::{canvas, link, obj},
+            Payload, SoundVal, Vec2Val, Vex2Val, WzValue,
+        },
+        version::{WzRegion, WzVersion},
+        writer::{NoPayload, WzImgWriter},
+        WzReader,
+    };
+
+    use super::{XmlReader, XmlWriter};
+
+    fn to_xml(name: &str, val: &WzValue) -> String {
+        let mut w = XmlWriter::new(Vec::new());
+        w.write_value(name, val).unwrap();
+        String::from_utf8(w.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let sound = WzValue::Sound(SoundVal {
+            sound: WzSound {
+                unknown: 0,
+                size: WzInt(2),
+                len_ms: WzInt(1),
+                header: pcm_header(),
+                offset: PosValue { val: (), pos: 0 },
+            },
+            payload: Some(Payload::Data(vec![0x80, 0x80])),
+        });
+        let val = obj(vec![
+            (
+                "info",
+                obj(vec![
+                    ("a&b", WzValue::Short(-1)),
+                    ("hp", WzValue::Int(100)),
+                    ("exp", WzValue::Long(1 << 40)),
+                    ("rate", WzValue::F32(0.25)),
+                    ("speed", WzValue::F64(1.5)),
+                    ("name", WzValue::String("<\"Snail\">".to_string())),
+                    ("empty", obj(vec![])),
+                    ("nil", WzValue::Null),
+                ]),
+            ),
+            (
+                "stand",
+                canvas(
+                    3,
+                    obj(vec![("origin", WzValue::Vec(Vec2Val { x: 1, y: -2 }))]),
+                ),
+            ),
+            ("alias", link("../stand")),
+            (
+                "hit",
+                WzValue::Convex(Vex2Val(vec![
+                    Vec2Val { x: 1, y: 2 },
+                    Vec2Val { x: 3, y: 4 },
+                ])),
+            ),
+            ("sound", sound),
+        ]);
+        let xml = to_xml("0100100.img", &val);
+        assert!(xml.starts_with("<?xml"));
+        assert!(xml.contains(r#"<imgdir name="0100100.img">"#));
+        assert!(xml.contains(r#"    <short name="a&amp;b" value="-1"/>"#));
+        assert!(xml.contains(r#"<string name="name" value="&lt;&quot;Snail&quot;&gt;"/>"#));
+        assert!(xml.contains(r#"<canvas name="stand" width="3" height="1">"#));
+        assert!(xml.contains(r#"<vector name="origin" x="1" y="-2"/>"#));
+        assert!(xml.contains(r#"<uol name="alias" value="../stand"/>"#));
+        assert!(xml.contains(r#"<extended name="hit">"#));
+        assert!(xml.contains(r#"basedata="gIA="/>"#));
+
+        let (name, read) = XmlReader::new().read(&xml).unwrap();
+        assert_eq!(name, "0100100.img");
+        assert_eq!(read.get_path("info/a&b").unwrap().as_i16(), Some(-1));
+        assert_eq!(to_xml(&name, &read), xml);
+
+        let err = XmlReader::new()
+            .read(
+                r#"<imgdir name="x"><imgdir name="a"><int name="b" value="c"/></imgdir></imgdir>"#,
+            )
+            .unwrap_err();
+        assert_eq!(err.path(), Some("a/b"));
+        assert!(XmlReader::new()
+            .read(r#"<imgdir name="x"><int name="a"/>"#)
+            .is_err());
+    }
+
+    #[test]
+    fn pngs() {
+        let region = WzRegion::GMS;
+        let ver = WzVersion(95);
+        let crypto = WzCrypto::from_region(region, ver, 0);
+        let str_table = WzStrTable::default();
+        let ctx = WzContext::new(&crypto, &str_table);
+        let data = img(
+            ctx,
+            &[("icon", canvas_obj(ctx, [1, 2, 3, 4], &[("z", "front")]))],
+        );
+        let mut r = WzReader::open_img(Cursor::new(data), region, ver).unwrap();
+        let mut src = r.root_img_reader().unwrap();
+
+        let dir = std::env::temp_dir().join(format!("shroom-wz-{}", uuid::Uuid::new_v4()));
+        let mut w = XmlWriter::new(Vec::new()).png_dir(&dir);
+        w.write_img("item.img", &mut src).unwrap();
+        let xml = String::from_utf8(w.into_inner()).unwrap();
+        assert!(dir.join("icon.png").exists());
+
+        let (_, val) = XmlReader::new().png_dir(&dir).read(&xml).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let data = WzImgWriter::write(&crypto, &val, &mut NoPayload).unwrap();
+        let mut r = WzReader::open_img(Cursor::new(data), region, ver).unwrap();
+        let mut img = r.root_img_reader().unwrap();
+        let val = WzValue::read(&mut img).unwrap();
+        assert_eq!(val.get_path("icon/z").unwrap().as_string(), Some("front"));
+        let canvas = val.get_path("icon").unwrap().as_canvas().unwrap();
+        let pixels = canvas
+            .read_canvas(&mut img)
+            .unwrap()
+            .to_rgba_image()
+            .unwrap();
+        assert_eq!(pixels.into_raw(), [3, 2, 1, 4]);
+    }
+
+    #[test]
+    fn basedata() {
+        let region = WzRegion::GMS;
+        let ver = WzVersion(95);
+        let crypto = WzCrypto::from_region(region, ver, 0);
+        let png = RgbaImage::from_raw(2, 1, vec![1, 2, 3, 255, 4, 5, 6, 128]).unwrap();
+        let mut data = Cursor::new(Vec::new());
+        png.write_to(&mut data, ImageFormat::Png).unwrap();
+        let xml = format!(
+            r#"<imgdir name="item.img"><canvas name="icon" width="2" height="1" basedata="{}"><vector name="origin" x="1" y="2"/></canvas></imgdir>"#,
+            STANDARD.encode(data.into_inner())
+        );
+
+        let (_, val) = XmlReader::new().read(&xml).unwrap();
+        let data = WzImgWriter::write(&crypto, &val, &mut NoPayload).unwrap();
+        let mut r = WzReader::open_img(Cursor::new(data), region, ver).unwrap();
+        let mut img = r.root_img_reader().unwrap();
+        let val = WzValue::read(&mut img).unwrap();
+        assert_eq!(
+            val.get_path("icon/origin").unwrap().as_vec(),
+            Some(&Vec2Val { x: 1, y: 2 })
+        );
+        let canvas = val.get_path("icon").unwrap().as_canvas().unwrap();
+        let pixels = canvas
+            .read_canvas(&mut img)
+            .unwrap()
+            .to_rgba_image()
+            .unwrap();
+        assert_eq!(pixels, png);
+
+        let err = XmlReader::new()
+            .read(r#"<imgdir name="x"><canvas name="a" width="1" height="1" basedata="AAAA"/></imgdir>"#)
+            .unwrap_err();
+        assert_eq!(err.path(), Some("a"));
+
+        // Without basedata or a PNG there is nothing to write, even with a source image
+        let (_, val) = XmlReader::new()
+            .read(r#"<imgdir name="x"><canvas name="icon" width="2" height="1"/></imgdir>"#)
+            .unwrap();
+        let err = WzImgWriter::write(&crypto, &val, &mut img).unwrap_err();
+        assert!(matches!(err, WzError::Malformed { .. }), "{err:?}");
+    }
+
+    #[test]
+    fn png_names() {
+        let dir = std::env::temp_dir().join(format!("shroom-wz-{}", uuid::Uuid::new_v4()));
+        let err = XmlReader::new()
+            .png_dir(&dir)
+            .read(r#"<imgdir name="x"><imgdir name=".."><canvas name="a" width="1" height="1"/></imgdir></imgdir>"#)
+            .unwrap_err();
+        assert!(matches!(err, WzError::InvalidName { .. }));
+
+        let region = WzRegion::GMS;
+        let ver = WzVersion(95);
+        let crypto = WzCrypto::from_region(region, ver, 0);
+        let str_table = WzStrTable::default();
+        let ctx = WzContext::new(&crypto, &str_table);
+        let data = img(ctx, &[("..", canvas_obj(ctx, [1, 2, 3, 4], &[]))]);
+        let mut r = WzReader::open_img(Cursor::new(data), region, ver).unwrap();
+        let mut src = r.root_img_reader().unwrap();
+        let err = XmlWriter::new(Vec::new())
+            .png_dir(&dir)
+            .write_img("item.img", &mut src)
+            .unwrap_err();
+        assert!(matches!(err, WzError::InvalidName { .. }));
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn depth() {
+        let nested = |depth: usize| {
+            format!(
+                "{}{}",
+                r#"<imgdir name="a">"#.repeat(depth),
+                "</imgdir>".repeat(depth)
+            )
+        };
+        assert!(XmlReader::new().read(&nested(100)).is_ok());
+        let err = XmlReader::new().read(&nested(100_000)).unwrap_err();
+        assert!(matches!(err, WzError::Malformed { .. }), "{err:?}");
+    }
+}
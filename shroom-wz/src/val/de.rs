@@ -0,0 +1,577 @@
+use serde::{
+    de::{
+        value::{MapDeserializer, SeqDeserializer},
+        DeserializeSeed, Error as _, IntoDeserializer, MapAccess, SeqAccess, Unexpected, Visitor,
+    },
+    forward_to_deserialize_any, Deserialize, Deserializer,
+};
+
+use crate::error::{WzError, WzResult};
+
+use super::{ObjectVal, Vec2Val, WzValue};
+
+/// Deserializes `T` from the value, links are not followed
+pub fn from_value<'de, T: Deserialize<'de>>(val: &'de WzValue) -> WzResult<T> {
+    T::deserialize(val)
+}
+
+/// Deserializes `T` from the object, links are not followed
+pub fn from_object<'de, T: Deserialize<'de>>(obj: &'de ObjectVal) -> WzResult<T> {
+    T::deserialize(obj)
+}
+
+fn unexpected(val: &WzValue) -> Unexpected<'_> {
+    match val {
+        WzValue::Object(_) => Unexpected::Map,
+        WzValue::Null => Unexpected::Unit,
+        WzValue::F32(v) => Unexpected::Float(*v as f64),
+        WzValue::F64(v) => Unexpected::Float(*v),
+        WzValue::Short(v) => Unexpected::Signed(*v as i64),
+        WzValue::Int(v) => Unexpected::Signed(*v as i64),
+        WzValue::Long(v) => Unexpected::Signed(*v),
+        WzValue::String(v) => Unexpected::Str(v),
+        WzValue::Vec(_) => Unexpected::Other("vector"),
+        WzValue::Convex(_) => Unexpected::Other("convex"),
+        WzValue::Sound(_) => Unexpected::Other("sound"),
+        WzValue::Canvas(_) => Unexpected::Other("canvas"),
+        WzValue::Link(_) => Unexpected::Other("link"),
+    }
+}
+
+/// Entries of an object, errors of a value get the key as parent
+struct Entries<'de, I> {
+    iter: I,
+    value: Option<(&'de str, &'de WzValue)>,
+}
+
+impl<'de, I: Iterator<Item = (&'de String, &'de WzValue)>> Entries<'de, I> {
+    fn new(iter: I) -> Self {
+        Self { iter, value: None }
+    }
+}
+
+impl<'de, I: Iterator<Item = (&'de String, &'de WzValue)>> MapAccess<'de> for Entries<'de, I> {
+    type Error = WzError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> WzResult<Option<K::Value>> {
+        let Some((k, v)) = self.iter.next() else {
+            return Ok(None);
+        };
+        self.value = Some((k, v));
+        seed.deserialize(Key(k))
+            .map(Some)
+            .map_err(|err| err.in_parent(k))
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> WzResult<V::Value> {
+        let (k, v) = self
+            .value
+            .take()
+            .ok_or_else(|| WzError::custom("value is missing"))?;
+        seed.deserialize(v).map_err(|err| err.in_parent(k))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.iter.size_hint().1
+    }
+}
+
+/// Elements of a sequence, errors get the key or index as parent
+struct Seq<I> {
+    iter: I,
+    ix: usize,
+}
+
+impl<'de, I, D> SeqAccess<'de> for Seq<I>
+where
+    I: Iterator<Item = (Option<&'de str>, D)>,
+    D: Deserializer<'de, Error = WzError>,
+{
+    type Error = WzError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> WzResult<Option<T::Value>> {
+        let Some((k, v)) = self.iter.next() else {
+            return Ok(None);
+        };
+        let ix = self.ix;
+        self.ix += 1;
+        seed.deserialize(v).map(Some).map_err(|err| match k {
+            Some(k) => err.in_parent(k),
+            None => err.in_parent(&ix.to_string()),
+        })
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.iter.size_hint().1
+    }
+}
+
+// Integers can be stored as any integer type or as a string
+macro_rules! deserialize_int {
+    ($method:ident, $visit:ident, $ty:ty) => {
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> WzResult<V::Value> {
+            let v = match self {
+                WzValue::Short(v) => <$ty>::try_from(*v).ok(),
+                WzValue::Int(v) => <$ty>::try_from(*v).ok(),
+                WzValue::Long(v) => <$ty>::try_from(*v).ok(),
+                WzValue::String(v) => v.trim().parse::<$ty>().ok(),
+                _ => return Err(WzError::invalid_type(unexpected(self), &visitor)),
+            };
+            match v {
+                Some(v) => visitor.$visit(v),
+                None => Err(WzError::invalid_value(unexpected(self), &visitor)),
+            }
+        }
+    };
+}
+
+macro_rules! deserialize_float {
+    ($method:ident, $visit:ident, $ty:ty) => {
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> WzResult<V::Value> {
+            let v = match self {
+                WzValue::F32(v) => *v as $ty,
+                WzValue::F64(v) => *v as $ty,
+                WzValue::Short(v) => *v as $ty,
+                WzValue::Int(v) => *v as $ty,
+                WzValue::Long(v) => *v as $ty,
+                WzValue::String(v) => v
+                    .trim()
+                    .parse::<$ty>()
+                    .map_err(|_| WzError::invalid_value(unexpected(self), &visitor))?,
+                _ => return Err(WzError::invalid_type(unexpected(self), &visitor)),
+            };
+            visitor.$visit(v)
+        }
+    };
+}
+
+impl<'de> Deserializer<'de> for &'de WzValue {
+    type Error = WzError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> WzResult<V::Value> {
+        match self {
+            WzValue::Object(obj) => obj.deserialize_any(visitor),
+            WzValue::Null | WzValue::Sound(_) => visitor.visit_unit(),
+            WzValue::F32(v) => visitor.visit_f32(*v),
+            WzValue::F64(v) => visitor.visit_f64(*v),
+            WzValue::Short(v) => visitor.visit_i16(*v),
+            WzValue::Int(v) => visitor.visit_i32(*v),
+            WzValue::Long(v) => visitor.visit_i64(*v),
+            WzValue::String(v) | WzValue::Link(v) => visitor.visit_borrowed_str(v),
+            WzValue::Vec(v) => v.deserialize_any(visitor),
+            WzValue::Convex(_) => self.deserialize_seq(visitor),
+            // A canvas is treated as its property
+            WzValue::Canvas(canvas) => match canvas.sub.as_deref() {
+                Some(sub) => sub.deserialize_any(visitor),
+                None => visitor.visit_unit(),
+            },
+        }
+    }
+
+    /// Integers are `true` if they are not 0
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> WzResult<V::Value> {
+        match self {
+            WzValue::Short(v) => visitor.visit_bool(*v != 0),
+            WzValue::Int(v) => visitor.visit_bool(*v != 0),
+            WzValue::Long(v) => visitor.visit_bool(*v != 0),
+            WzValue::String(v) => Key(v).deserialize_bool(visitor),
+            _ => Err(WzError::invalid_type(unexpected(self), &visitor)),
+        }
+    }
+
+    deserialize_int!(deserialize_i8, visit_i8, i8);
+    deserialize_int!(deserialize_i16, visit_i16, i16);
+    deserialize_int!(deserialize_i32, visit_i32, i32);
+    deserialize_int!(deserialize_i64, visit_i64, i64);
+    deserialize_int!(deserialize_u8, visit_u8, u8);
+    deserialize_int!(deserialize_u16, visit_u16, u16);
+    deserialize_int!(deserialize_u32, visit_u32, u32);
+    deserialize_int!(deserialize_u64, visit_u64, u64);
+    deserialize_float!(deserialize_f32, visit_f32, f32);
+    deserialize_float!(deserialize_f64, visit_f64, f64);
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> WzResult<V::Value> {
+        self.deserialize_str(visitor)
+    }
+
+    /// Numbers are formatted as strings
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> WzResult<V::Value> {
+        match self {
+            WzValue::String(v) | WzValue::Link(v) => visitor.visit_borrowed_str(v),
+            WzValue::Short(v) => visitor.visit_string(v.to_string()),
+            WzValue::Int(v) => visitor.visit_string(v.to_string()),
+            WzValue::Long(v) => visitor.visit_string(v.to_string()),
+            WzValue::F32(v) => visitor.visit_string(v.to_string()),
+            WzValue::F64(v) => visitor.visit_string(v.to_string()),
+            _ => Err(WzError::invalid_type(unexpected(self), &visitor)),
+        }
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> WzResult<V::Value> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> WzResult<V::Value> {
+        match self {
+            WzValue::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> WzResult<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// Objects are read as sequence of their entries with numeric keys
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> WzResult<V::Value> {
+        match self {
+            WzValue::Object(obj) => obj.deserialize_seq(visitor),
+            WzValue::Canvas(canvas) => match canvas.sub.as_deref() {
+                Some(sub) => sub.deserialize_seq(visitor),
+                None => visitor.visit_seq(SeqDeserializer::new(std::iter::empty::<i32>())),
+            },
+            WzValue::Vec(v) => v.deserialize_seq(visitor),
+            WzValue::Convex(vex) => visitor.visit_seq(Seq {
+                iter: vex.0.iter().map(|v| (None, v)),
+                ix: 0,
+            }),
+            _ => Err(WzError::invalid_type(unexpected(self), &visitor)),
+        }
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> WzResult<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> WzResult<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> WzResult<V::Value> {
+        match self {
+            WzValue::Object(obj) => obj.deserialize_map(visitor),
+            WzValue::Canvas(canvas) => match canvas.sub.as_deref() {
+                Some(sub) => sub.deserialize_map(visitor),
+                None => visitor.visit_map(Entries::new(std::iter::empty())),
+            },
+            WzValue::Vec(v) => v.deserialize_map(visitor),
+            _ => Err(WzError::invalid_type(unexpected(self), &visitor)),
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> WzResult<V::Value> {
+        self.deserialize_map(visitor)
+    }
+
+    /// Unit variants are stored as name or as index
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> WzResult<V::Value> {
+        match self {
+            WzValue::String(v) => visitor.visit_enum(v.as_str().into_deserializer()),
+            WzValue::Short(v) => visitor.visit_enum((*v as u32).into_deserializer()),
+            WzValue::Int(v) => visitor.visit_enum((*v as u32).into_deserializer()),
+            _ => Err(WzError::invalid_type(unexpected(self), &visitor)),
+        }
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> WzResult<V::Value> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> WzResult<V::Value> {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        bytes byte_buf unit unit_struct
+    }
+}
+
+impl<'de> Deserializer<'de> for &'de ObjectVal {
+    type Error = WzError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> WzResult<V::Value> {
+        visitor.visit_map(Entries::new(self.0.iter()))
+    }
+
+    /// Entries with numeric keys ordered by their key, other entries are skipped
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> WzResult<V::Value> {
+        let mut entries = self
+            .0
+            .iter()
+            .filter_map(|(k, v)| Some((k.parse::<u64>().ok()?, k.as_str(), v)))
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|(ix, _, _)| *ix);
+        visitor.visit_seq(Seq {
+            iter: entries.into_iter().map(|(_, k, v)| (Some(k), v)),
+            ix: 0,
+        })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> WzResult<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> WzResult<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> WzResult<V::Value> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> WzResult<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> WzResult<V::Value> {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf
+        unit unit_struct map struct enum identifier
+    }
+}
+
+/// A vector is a struct with `x` and `y` or a `(x, y)` tuple
+impl<'de> Deserializer<'de> for &'de Vec2Val {
+    type Error = WzError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> WzResult<V::Value> {
+        visitor.visit_map(MapDeserializer::new(
+            [("x", self.x), ("y", self.y)].into_iter(),
+        ))
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> WzResult<V::Value> {
+        visitor.visit_seq(SeqDeserializer::new([self.x, self.y].into_iter()))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> WzResult<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> WzResult<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> WzResult<V::Value> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> WzResult<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf
+        unit unit_struct map struct enum identifier ignored_any
+    }
+}
+
+/// Key of an object, which is parsed for numbers
+struct Key<'de>(&'de str);
+
+macro_rules! deserialize_parse {
+    ($method:ident, $visit:ident, $ty:ty) => {
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> WzResult<V::Value> {
+            match self.0.trim().parse::<$ty>() {
+                Ok(v) => visitor.$visit(v),
+                Err(_) => Err(WzError::invalid_value(Unexpected::Str(self.0), &visitor)),
+            }
+        }
+    };
+}
+
+impl<'de> Deserializer<'de> for Key<'de> {
+    type Error = WzError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> WzResult<V::Value> {
+        visitor.visit_borrowed_str(self.0)
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> WzResult<V::Value> {
+        match self.0.trim() {
+            "1" | "true" => visitor.visit_bool(true),
+            "0" | "false" => visitor.visit_bool(false),
+            _ => Err(WzError::invalid_value(Unexpected::Str(self.0), &visitor)),
+        }
+    }
+
+    deserialize_parse!(deserialize_i8, visit_i8, i8);
+    deserialize_parse!(deserialize_i16, visit_i16, i16);
+    deserialize_parse!(deserialize_i32, visit_i32, i32);
+    deserialize_parse!(deserialize_i64, visit_i64, i64);
+    deserialize_parse!(deserialize_u8, visit_u8, u8);
+    deserialize_parse!(deserialize_u16, visit_u16, u16);
+    deserialize_parse!(deserialize_u32, visit_u32, u32);
+    deserialize_parse!(deserialize_u64, visit_u64, u64);
+    deserialize_parse!(deserialize_f32, visit_f32, f32);
+    deserialize_parse!(deserialize_f64, visit_f64, f64);
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> WzResult<V::Value> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> WzResult<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> WzResult<V::Value> {
+        visitor.visit_enum(self.0.into_deserializer())
+    }
+
+    forward_to_deserialize_any! {
+        char str string bytes byte_buf unit unit_struct seq tuple tuple_struct
+        map struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde::Deserialize;
+
+    use crate::val::{tests::obj, Vec2Val, Vex2Val, WzValue};
+
+    use super::from_value;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    enum Kind {
+        Normal,
+        Boss,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Info {
+        level: i16,
+        #[serde(rename = "maxHP")]
+        max_hp: u32,
+        boss: bool,
+        hp_gauge_hide: Option<bool>,
+        speed: f32,
+        name: String,
+        kind: Kind,
+        origin: Point,
+        lt: (i32, i32),
+        hit: Vec<Vec2Val>,
+        frames: Vec<i32>,
+        drops: BTreeMap<u32, String>,
+    }
+
+    #[test]
+    fn deserialize() {
+        let s = |s: &str| WzValue::String(s.to_string());
+        let val = obj(vec![
+            ("level", WzValue::Short(3)),
+            ("maxHP", s("150")),
+            ("boss", WzValue::Int(1)),
+            ("speed", WzValue::Int(-20)),
+            ("name", s("Snail")),
+            ("kind", s("boss")),
+            ("origin", WzValue::Vec(Vec2Val { x: 1, y: -2 })),
+            ("lt", WzValue::Vec(Vec2Val { x: -5, y: 5 })),
+            (
+                "hit",
+                WzValue::Convex(Vex2Val(vec![Vec2Val { x: 3, y: 4 }])),
+            ),
+            (
+                "frames",
+                obj(vec![
+                    ("10", WzValue::Int(3)),
+                    ("info", WzValue::Null),
+                    ("2", WzValue::Int(2)),
+                    ("0", WzValue::Long(1)),
+                ]),
+            ),
+            ("drops", obj(vec![("4000000", s("a")), ("2", s("b"))])),
+        ]);
+        let info: Info = from_value(&val).unwrap();
+        assert_eq!(
+            info,
+            Info {
+                level: 3,
+                max_hp: 150,
+                boss: true,
+                hp_gauge_hide: None,
+                speed: -20.,
+                name: "Snail".to_string(),
+                kind: Kind::Boss,
+                origin: Point { x: 1, y: -2 },
+                lt: (-5, 5),
+                hit: vec![Vec2Val { x: 3, y: 4 }],
+                frames: vec![1, 2, 3],
+                drops: BTreeMap::from([(2, "b".to_string()), (4000000, "a".to_string())]),
+            }
+        );
+
+        #[derive(Debug, Deserialize)]
+        #[allow(dead_code)]
+        struct Wrapper {
+            info: Info,
+        }
+        let val = obj(vec![("info", obj(vec![("level", s("high"))]))]);
+        let err = from_value::<Wrapper>(&val).unwrap_err();
+        assert_eq!(err.path(), Some("info/level"));
+        let val = obj(vec![("info", obj(vec![("level", WzValue::Int(40000))]))]);
+        assert!(from_value::<Wrapper>(&val).is_err());
+    }
+}
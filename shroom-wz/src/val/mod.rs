@@ -0,0 +1,910 @@
+use std::{
+    fmt::Display,
+    fs,
+    ops::{Index, IndexMut},
+    path::Path,
+    time::Duration,
+};
+
+use derive_more::IsVariant;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+mod de;
+mod ser;
+
+pub use de::{from_object, from_value};
+
+use crate::{
+    canvas::Canvas,
+    error::{WzError, WzResult},
+    file::{WzIO, WzImgReader},
+    l1::{
+        canvas::WzCanvas,
+        obj::WzObject,
+        prop::{WzConvex2D, WzObj, WzPropValue, WzProperty, WzVector2D},
+        sound::WzSound,
+    },
+    link::WzOutlinkResolver,
+    sound::DecodedSound,
+    ty::WzInt,
+    util::{append_ext, join_path},
+};
+
+pub type Map = IndexMap<String, WzValue>;
+
+/// Raw canvas or sound data, which is kept so the value can be written again
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    /// Serialized as base64
+    Data(#[serde(with = "ser::base64_data")] Vec<u8>),
+    /// Side file relative to the payload directory
+    File(String),
+    /// Neither embedded nor in a source image, like for deserialized values
+    /// without payloads, writing the value fails
+    #[serde(skip)]
+    Missing,
+}
+
+impl Payload {
+    pub fn data(&self) -> Option<&[u8]> {
+        match self {
+            Payload::Data(data) => Some(data),
+            Payload::File(_) | Payload::Missing => None,
+        }
+    }
+}
+
+/// Canvas header, the payload is only set by `WzValue::read_payloads`
+#[derive(Debug, Clone)]
+pub struct CanvasVal {
+    pub canvas: WzCanvas,
+    pub sub: Option<Box<WzValue>>,
+    pub payload: Option<Payload>,
+}
+
+impl CanvasVal {
+    pub fn read_canvas<R: WzIO>(&self, r: &mut WzImgReader<R>) -> WzResult<Canvas> {
+        r.read_canvas(&self.canvas)
+    }
+
+    /// Reads the canvas and uses the `resolver` for `_outlink`s
+    pub fn read_canvas_with<R: WzIO>(
+        &self,
+        r: &mut WzImgReader<R>,
+        resolver: &mut impl WzOutlinkResolver,
+    ) -> WzResult<Canvas> {
+        r.read_canvas_with(&self.canvas, resolver)
+    }
+}
+
+/// Sound header, the payload is only set by `WzValue::read_payloads`
+#[derive(Debug, Clone)]
+pub struct SoundVal {
+    pub sound: WzSound,
+    pub payload: Option<Payload>,
+}
+
+impl SoundVal {
+    pub fn read_data<R: WzIO>(&self, r: &mut WzImgReader<R>) -> WzResult<Vec<u8>> {
+        r.read_sound(&self.sound)
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(self.sound.len_ms.0 as u64)
+    }
+
+    /// Reads and decodes the sound to samples
+    pub fn decode<R: WzIO>(&self, r: &mut WzImgReader<R>) -> WzResult<DecodedSound> {
+        DecodedSound::decode(&self.sound.header.fmt, &self.read_data(r)?)
+    }
+
+    /// Checks that `len_ms` matches the decoded duration within `tolerance`
+    pub fn check_duration(&self, decoded: &DecodedSound, tolerance: Duration) -> WzResult<()> {
+        decoded.check_duration(self.duration(), tolerance)
+    }
+
+    /// Reads the sound as a playable `.wav` or `.mp3` file
+    pub fn to_file_bytes<R: WzIO>(&self, r: &mut WzImgReader<R>) -> WzResult<SoundFile> {
+        let data = self.read_data(r)?;
+        Ok(SoundFile {
+            data: self.sound.to_file_bytes(&data),
+            ext: self.sound.file_ext(),
+            mime: self.sound.mime_type(),
+        })
+    }
+}
+
+/// Sound as a file
+#[derive(Debug, Clone)]
+pub struct SoundFile {
+    pub data: Vec<u8>,
+    /// Extension without the dot like `wav`
+    pub ext: &'static str,
+    pub mime: &'static str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Vec2Val {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl From<WzVector2D> for Vec2Val {
+    fn from(value: WzVector2D) -> Self {
+        Self {
+            x: value.x.0,
+            y: value.y.0,
+        }
+    }
+}
+
+impl Display for Vec2Val {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "x={},y={}", self.x, self.y)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Vex2Val(pub Vec<Vec2Val>);
+
+impl From<&WzConvex2D> for Vex2Val {
+    fn from(value: &WzConvex2D) -> Self {
+        Self(value.0.iter().map(|v| Vec2Val::from(*v)).collect())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ObjectVal(pub Map);
+
+impl ObjectVal {
+    pub fn get(&self, index: &str) -> Option<&WzValue> {
+        self.0.get(index)
+    }
+
+    pub fn must_get(&self, index: &str) -> WzResult<&WzValue> {
+        self.0.get(index).ok_or_else(|| WzError::NotFound {
+            path: index.to_string(),
+        })
+    }
+
+    pub fn get_into<'a, T: TryFrom<&'a WzValue>>(
+        &'a self,
+        index: &str,
+    ) -> Result<Option<T>, T::Error> {
+        self.0.get(index).map(|v| v.try_into()).transpose()
+    }
+
+    pub fn must_get_into<'a, T: TryFrom<&'a WzValue>>(&'a self, index: &str) -> WzResult<T> {
+        self.must_get(index)?
+            .try_into()
+            .map_err(|_| WzError::UnexpectedValue {
+                expected: std::any::type_name::<T>(),
+                path: Some(index.to_string()),
+            })
+    }
+}
+
+impl Index<&str> for ObjectVal {
+    type Output = WzValue;
+
+    fn index(&self, index: &str) -> &Self::Output {
+        &self.0[index]
+    }
+}
+
+impl IndexMut<&str> for ObjectVal {
+    fn index_mut(&mut self, index: &str) -> &mut Self::Output {
+        self.0.get_mut(index).unwrap()
+    }
+}
+
+/// Serialized with a type tag, so every variant survives a round-trip
+#[derive(Debug, Clone, IsVariant, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum WzValue {
+    Object(ObjectVal),
+    Null,
+    F32(f32),
+    F64(f64),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    String(String),
+    Vec(Vec2Val),
+    Convex(Vex2Val),
+    Sound(SoundVal),
+    Canvas(CanvasVal),
+    Link(String),
+}
+
+impl WzValue {
+    /// Child of an object or the property of a canvas
+    pub fn get(&self, name: &str) -> Option<&WzValue> {
+        match self {
+            WzValue::Object(v) => v.get(name),
+            // We get the next object from the canvas If there's one
+            WzValue::Canvas(v) => v.sub.as_deref()?.as_object()?.get(name),
+            _ => None,
+        }
+    }
+
+    pub fn get_path(&self, path: &str) -> Option<&WzValue> {
+        let mut cur = self;
+        for part in path.split('/') {
+            cur = cur.get(part)?;
+        }
+
+        Some(cur)
+    }
+
+    /// Like `get_path`, but links on the way and the link at the end are followed,
+    /// `self` has to be the root of the image, because links are relative to it
+    pub fn get_path_resolved(&self, path: &str) -> WzResult<Option<&WzValue>> {
+        let path = path
+            .split('/')
+            .filter(|part| !part.is_empty())
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        Ok(self
+            .lookup_resolved(&path, &mut Vec::new())?
+            .map(|(v, _)| v))
+    }
+
+    /// Replaces every link with a copy of the value it points to,
+    /// `self` has to be the root of the image
+    pub fn resolve_links(&mut self) -> WzResult<()> {
+        *self = self.resolved_clone(self, &mut Vec::new(), &mut Vec::new(), false)?;
+        Ok(())
+    }
+
+    /// Like `resolve_links`, but links which can't be resolved are kept as they are
+    pub fn resolve_valid_links(&mut self) {
+        // Only link errors can occur, which are kept
+        if let Ok(resolved) = self.resolved_clone(self, &mut Vec::new(), &mut Vec::new(), true) {
+            *self = resolved;
+        }
+    }
+
+    /// Looks up the path and returns the value with the path it's really stored at
+    fn lookup_resolved<'a>(
+        &'a self,
+        path: &[String],
+        resolving: &mut Vec<Vec<String>>,
+    ) -> WzResult<Option<(&'a WzValue, Vec<String>)>> {
+        let mut cur = self;
+        let mut cur_path = Vec::new();
+        for part in path {
+            let Some(next) = cur.get(part) else {
+                return Ok(None);
+            };
+            cur_path.push(part.clone());
+            (cur, cur_path) = self.follow_links(next, cur_path, resolving)?;
+        }
+        Ok(Some((cur, cur_path)))
+    }
+
+    /// Follows the chain of links starting at `val` which is stored at `path`
+    fn follow_links<'a>(
+        &'a self,
+        mut val: &'a WzValue,
+        mut path: Vec<String>,
+        resolving: &mut Vec<Vec<String>>,
+    ) -> WzResult<(&'a WzValue, Vec<String>)> {
+        let n = resolving.len();
+        let res = loop {
+            let WzValue::Link(link) = val else {
+                break Ok((val, path));
+            };
+            let err = |msg| WzError::InvalidLink {
+                link: link.clone(),
+                msg,
+                path: Some(path.join("/")),
+            };
+
+            if resolving.contains(&path) {
+                break Err(err("cyclic link"));
+            }
+            let Some(target) = link_target(&path, link) else {
+                break Err(err("target is outside of the image"));
+            };
+            resolving.push(path.clone());
+            match self.lookup_resolved(&target, resolving) {
+                Ok(Some((next, next_path))) => {
+                    val = next;
+                    path = next_path;
+                }
+                Ok(None) => break Err(err("target not found")),
+                Err(e) => break Err(e),
+            }
+        };
+        resolving.truncate(n);
+        res
+    }
+
+    /// Copies `val` stored at `path` with all links replaced,
+    /// if `keep_invalid` is set links which can't be resolved are copied as they are
+    fn resolved_clone(
+        &self,
+        val: &WzValue,
+        path: &mut Vec<String>,
+        expanding: &mut Vec<Vec<String>>,
+        keep_invalid: bool,
+    ) -> WzResult<WzValue> {
+        Ok(match val {
+            WzValue::Link(link) => {
+                match self.resolved_link_clone(val, link, path, expanding, keep_invalid) {
+                    Err(_) if keep_invalid => val.clone(),
+                    res => res?,
+                }
+            }
+            WzValue::Object(obj) => {
+                let mut map = Map::new();
+                for (k, v) in obj.0.iter() {
+                    path.push(k.clone());
+                    let v = self.resolved_clone(v, path, expanding, keep_invalid);
+                    path.pop();
+                    map.insert(k.clone(), v?);
+                }
+                WzValue::Object(ObjectVal(map))
+            }
+            WzValue::Canvas(canvas) => WzValue::Canvas(CanvasVal {
+                canvas: canvas.canvas.clone(),
+                sub: match canvas.sub.as_deref() {
+                    Some(sub) => Some(Box::new(self.resolved_clone(
+                        sub,
+                        path,
+                        expanding,
+                        keep_invalid,
+                    )?)),
+                    None => None,
+                },
+                payload: canvas.payload.clone(),
+            }),
+            v => v.clone(),
+        })
+    }
+
+    /// Copies the target of the link `val` stored at `path`
+    fn resolved_link_clone(
+        &self,
+        val: &WzValue,
+        link: &str,
+        path: &mut Vec<String>,
+        expanding: &mut Vec<Vec<String>>,
+        keep_invalid: bool,
+    ) -> WzResult<WzValue> {
+        let (target, mut target_path) = self.follow_links(val, path.clone(), &mut Vec::new())?;
+        // Copying a parent of a link which is being copied never ends
+        if expanding
+            .iter()
+            .chain(Some(&*path))
+            .any(|p| p.starts_with(&target_path))
+        {
+            return Err(WzError::InvalidLink {
+                link: link.to_string(),
+                msg: "link to a parent",
+                path: Some(path.join("/")),
+            });
+        }
+
+        expanding.push(path.clone());
+        let v = self.resolved_clone(target, &mut target_path, expanding, keep_invalid);
+        expanding.pop();
+        v
+    }
+
+    pub fn as_object(&self) -> Option<&ObjectVal> {
+        match self {
+            WzValue::Object(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_f32(&self) -> Option<f32> {
+        match self {
+            WzValue::F32(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            WzValue::F64(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_i16(&self) -> Option<i16> {
+        match self {
+            WzValue::Short(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_i32(&self) -> Option<i32> {
+        match self {
+            WzValue::Int(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            WzValue::Long(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_string(&self) -> Option<&str> {
+        match self {
+            WzValue::String(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_vec(&self) -> Option<&Vec2Val> {
+        match self {
+            WzValue::Vec(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_convex(&self) -> Option<&Vex2Val> {
+        match self {
+            WzValue::Convex(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_sound(&self) -> Option<&SoundVal> {
+        match self {
+            WzValue::Sound(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_canvas(&self) -> Option<&CanvasVal> {
+        match self {
+            WzValue::Canvas(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+/// Path of the link target, `..` is relative to the parent of the link
+fn link_target(link_path: &[String], link: &str) -> Option<Vec<String>> {
+    let mut target = link_path[..link_path.len().saturating_sub(1)].to_vec();
+    for part in link.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                target.pop()?;
+            }
+            part => target.push(part.to_string()),
+        }
+    }
+    Some(target)
+}
+
+macro_rules! try_into_val {
+    ($ty:ty, $into_fn:ident) => {
+        impl TryFrom<&WzValue> for $ty {
+            type Error = WzError;
+
+            fn try_from(v: &WzValue) -> Result<$ty, Self::Error> {
+                v.$into_fn().ok_or(WzError::UnexpectedValue {
+                    expected: stringify!($ty),
+                    path: None,
+                })
+            }
+        }
+    };
+    (ref, $ty:ty, $into_fn:ident) => {
+        impl<'a> TryFrom<&'a WzValue> for &'a $ty {
+            type Error = WzError;
+
+            fn try_from(v: &'a WzValue) -> Result<&'a $ty, Self::Error> {
+                v.$into_fn().ok_or(WzError::UnexpectedValue {
+                    expected: stringify!($ty),
+                    path: None,
+                })
+            }
+        }
+    };
+}
+
+try_into_val!(f32, as_f32);
+try_into_val!(f64, as_f64);
+try_into_val!(i16, as_i16);
+try_into_val!(i32, as_i32);
+try_into_val!(i64, as_i64);
+
+try_into_val!(ref, Vec2Val, as_vec);
+try_into_val!(ref, str, as_string);
+try_into_val!(ref, ObjectVal, as_object);
+try_into_val!(ref, SoundVal, as_sound);
+try_into_val!(ref, Vex2Val, as_convex);
+
+impl WzValue {
+    pub fn read<R: WzIO>(r: &mut WzImgReader<R>) -> WzResult<WzValue> {
+        let obj = r.root_obj();
+        Self::read_obj(r, &obj)
+    }
+
+    pub(crate) fn read_val<R: WzIO>(
+        r: &mut WzImgReader<R>,
+        val: &WzPropValue,
+    ) -> WzResult<WzValue> {
+        Ok(match val {
+            WzPropValue::Null => WzValue::Null,
+            WzPropValue::Short1(v) | WzPropValue::Short2(v) => WzValue::Short(*v),
+            WzPropValue::Int1(v) | WzPropValue::Int2(v) => WzValue::Int(v.0),
+            WzPropValue::Long(v) => WzValue::Long(v.0),
+            WzPropValue::F32(v) => WzValue::F32(v.0),
+            WzPropValue::F64(v) => WzValue::F64(*v),
+            WzPropValue::Str(v) => WzValue::String(v.to_string()),
+            WzPropValue::Obj(v) => Self::read_obj(r, v)?,
+        })
+    }
+
+    fn read_prop<R: WzIO>(r: &mut WzImgReader<R>, prop: &WzProperty) -> WzResult<WzValue> {
+        let mut map = Map::new();
+        for entry in prop.entries.0.iter() {
+            let k = entry.name.as_ref().to_string();
+            let v = Self::read_val(r, &entry.val).map_err(|err| err.in_parent(&k))?;
+            map.insert(k, v);
+        }
+        Ok(WzValue::Object(ObjectVal(map)))
+    }
+
+    fn read_obj<R: WzIO>(r: &mut WzImgReader<R>, obj: &WzObj) -> WzResult<WzValue> {
+        let obj = r.read_obj(obj)?;
+        Ok(match obj {
+            WzObject::Property(prop) => Self::read_prop(r, &prop)?,
+            WzObject::Canvas(canvas) => {
+                let prop = if let Some(prop) = canvas.property.as_ref() {
+                    Some(Box::new(Self::read_prop(r, prop)?))
+                } else {
+                    None
+                };
+                WzValue::Canvas(CanvasVal {
+                    canvas,
+                    sub: prop,
+                    payload: None,
+                })
+            }
+            WzObject::UOL(link) => WzValue::Link(link.entries.as_ref().to_string()),
+            WzObject::Vec2(vec2) => WzValue::Vec(vec2.into()),
+            WzObject::Convex2D(vex) => WzValue::Convex(Vex2Val::from(&vex)),
+            WzObject::SoundDX8(sound) => WzValue::Sound(SoundVal {
+                sound: sound.clone(),
+                payload: None,
+            }),
+        })
+    }
+
+    /// Reads the canvas and sound payloads into the values,
+    /// so the tree can be serialized and written without the source image
+    pub fn read_payloads<R: WzIO>(&mut self, r: &mut WzImgReader<R>) -> WzResult<()> {
+        self.visit_mut("", &mut |_, v| {
+            match v {
+                WzValue::Canvas(canvas) => {
+                    let len = &canvas.canvas.len;
+                    let data = r.read_raw(len.pos + 4, len.val as usize)?;
+                    canvas.payload = Some(Payload::Data(data));
+                }
+                WzValue::Sound(sound) => {
+                    let data = sound.read_data(r)?;
+                    sound.payload = Some(Payload::Data(data));
+                }
+                _ => {}
+            }
+            Ok(())
+        })
+    }
+
+    /// Moves the embedded payloads into `<path>.bin` files below `dir`,
+    /// property names which would leave `dir` fail
+    pub fn store_payloads(&mut self, dir: impl AsRef<Path>) -> WzResult<()> {
+        let dir = dir.as_ref();
+        self.visit_mut("", &mut |path, v| {
+            let payload = match v {
+                WzValue::Canvas(canvas) => &mut canvas.payload,
+                WzValue::Sound(sound) => &mut sound.payload,
+                _ => return Ok(()),
+            };
+            let Some(Payload::Data(data)) = payload else {
+                return Ok(());
+            };
+            let path = if path.is_empty() { "root" } else { path };
+            let file_path = append_ext(&join_path(dir, path)?, "bin");
+            let file = format!("{path}.bin");
+            if let Some(parent) = file_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(file_path, data)?;
+            *payload = Some(Payload::File(file));
+            Ok(())
+        })
+    }
+
+    /// Loads the side files written by `store_payloads` back into the values,
+    /// file names must be relative paths below `dir`
+    pub fn load_payloads(&mut self, dir: impl AsRef<Path>) -> WzResult<()> {
+        let dir = dir.as_ref();
+        self.visit_mut("", &mut |_, v| {
+            let read = |payload: &Option<Payload>| match payload {
+                Some(Payload::File(file)) => Ok(Some(fs::read(join_path(dir, file)?)?)),
+                _ => Ok::<_, WzError>(None),
+            };
+            match v {
+                WzValue::Canvas(canvas) => {
+                    if let Some(data) = read(&canvas.payload)? {
+                        canvas.canvas.len.val = data.len() as u32;
+                        canvas.payload = Some(Payload::Data(data));
+                    }
+                }
+                WzValue::Sound(sound) => {
+                    if let Some(data) = read(&sound.payload)? {
+                        sound.sound.size = WzInt(data.len() as i32);
+                        sound.payload = Some(Payload::Data(data));
+                    }
+                }
+                _ => {}
+            }
+            Ok(())
+        })
+    }
+
+    /// Calls `f` for every value in pre-order, including canvas properties
+    fn visit_mut(
+        &mut self,
+        path: &str,
+        f: &mut impl FnMut(&str, &mut WzValue) -> WzResult<()>,
+    ) -> WzResult<()> {
+        f(path, self)?;
+        let children = match self {
+            WzValue::Object(obj) => &mut obj.0,
+            WzValue::Canvas(CanvasVal { sub: Some(sub), .. }) => match sub.as_mut() {
+                WzValue::Object(obj) => &mut obj.0,
+                _ => return Ok(()),
+            },
+            _ => return Ok(()),
+        };
+        for (k, v) in children.iter_mut() {
+            let child = if path.is_empty() {
+                k.clone()
+            } else {
+                format!("{path}/{k}")
+            };
+            v.visit_mut(&child, f).map_err(|err| err.in_parent(k))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Cursor;
+
+    use binrw::PosValue;
+
+    use crate::{
+        crypto::WzCrypto,
+        error::WzError,
+        l1::{
+            canvas::{WzCanvas, WzCanvasDepth, WzCanvasScaling},
+            sound::{tests::pcm_header, WzSound},
+        },
+        ty::WzInt,
+        version::{WzRegion, WzVersion},
+        writer::{NoPayload, WzImgWriter},
+        WzReader,
+    };
+
+    use super::{CanvasVal, Map, ObjectVal, Payload, SoundVal, Vec2Val, WzValue};
+
+    pub(crate) fn obj(entries: Vec<(&str, WzValue)>) -> WzValue {
+        WzValue::Object(ObjectVal(
+            entries
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect::<Map>(),
+        ))
+    }
+
+    pub(crate) fn link(path: &str) -> WzValue {
+        WzValue::Link(path.to_string())
+    }
+
+    pub(crate) fn canvas(width: i32, sub: WzValue) -> WzValue {
+        WzValue::Canvas(CanvasVal {
+            canvas: WzCanvas {
+                unknown: 0,
+                has_property: 1,
+                property: None,
+                width: WzInt(width),
+                height: WzInt(1),
+                depth: WzCanvasDepth::BGRA8888,
+                scale: WzCanvasScaling(0),
+                unknown1: 0,
+                len: PosValue { val: 0, pos: 0 },
+            },
+            sub: Some(Box::new(sub)),
+            payload: None,
+        })
+    }
+
+    #[test]
+    fn resolve_links() {
+        let mut root = obj(vec![
+            (
+                "stand1",
+                obj(vec![
+                    ("0", canvas(1, obj(vec![("delay", WzValue::Int(100))]))),
+                    ("1", link("0")),
+                ]),
+            ),
+            (
+                "alert",
+                obj(vec![
+                    ("0", link("../stand1/1")),
+                    ("1", link("../../stand1/0")),
+                    ("delay", link("../stand1/0/delay")),
+                ]),
+            ),
+            ("walk", link("alert")),
+        ]);
+
+        let get = |root: &WzValue, path| {
+            root.get_path_resolved(path)
+                .unwrap()
+                .map(|v| v.is_canvas() || v.is_int())
+        };
+        assert_eq!(get(&root, "alert/0"), Some(true));
+        assert_eq!(get(&root, "walk/0/delay"), Some(true));
+        assert_eq!(get(&root, "alert/delay"), Some(true));
+        assert_eq!(get(&root, "alert/2"), None);
+        assert!(matches!(
+            root.get_path_resolved("alert/1"),
+            Err(WzError::InvalidLink { .. })
+        ));
+
+        // Only the broken link is kept, also in the copy of its parent
+        let mut partial = root.clone();
+        assert!(partial.clone().resolve_links().is_err());
+        partial.resolve_valid_links();
+        assert!(partial.get_path("alert/0").unwrap().is_canvas());
+        assert!(partial.get_path("walk/0").unwrap().is_canvas());
+        for path in ["alert/1", "walk/1"] {
+            assert!(matches!(
+                partial.get_path(path),
+                Some(WzValue::Link(link)) if link == "../../stand1/0"
+            ));
+        }
+
+        // Without the broken link the whole image can be resolved
+        let WzValue::Object(alert) = root.get_path_resolved("alert").unwrap().unwrap() else {
+            panic!("alert must be an object");
+        };
+        assert_eq!(alert.0.len(), 3);
+        if let WzValue::Object(root) = &mut root {
+            if let WzValue::Object(alert) = &mut root["alert"] {
+                alert.0.shift_remove("1");
+            }
+        }
+        root.resolve_links().unwrap();
+        assert!(root.get_path("walk/0/delay").unwrap().is_int());
+        assert!(root.get_path("alert/delay").unwrap().is_int());
+        assert!(root.get_path("stand1/1").unwrap().is_canvas());
+    }
+
+    #[test]
+    fn link_cycles() {
+        let root = obj(vec![("a", link("b")), ("b", link("a/x"))]);
+        let err = root.get_path_resolved("a").unwrap_err();
+        assert!(matches!(err, WzError::InvalidLink { .. }));
+
+        let mut root = obj(vec![("a", obj(vec![("self", link("../a"))]))]);
+        assert!(root
+            .get_path_resolved("a/self/self/self")
+            .unwrap()
+            .is_some());
+        let err = root.resolve_links().unwrap_err();
+        assert_eq!(err.path(), Some("a/self"));
+    }
+
+    #[test]
+    fn json_roundtrip() {
+        let region = WzRegion::GMS;
+        let ver = WzVersion(95);
+        let crypto = WzCrypto::from_region(region, ver, 0);
+        let sound = WzValue::Sound(SoundVal {
+            sound: WzSound {
+                unknown: 0,
+                size: WzInt(0),
+                len_ms: WzInt(1),
+                header: pcm_header(),
+                offset: PosValue { val: (), pos: 0 },
+            },
+            payload: Some(Payload::Data(vec![0x80; 8])),
+        });
+        let mut bare = canvas(2, WzValue::Null);
+        if let WzValue::Canvas(canvas) = &mut bare {
+            canvas.sub = None;
+            canvas.payload = Some(Payload::Data(vec![0, 1, 2, 3]));
+        }
+        let mut icon = canvas(1, obj(vec![("z", WzValue::String("front".into()))]));
+        if let WzValue::Canvas(canvas) = &mut icon {
+            canvas.payload = Some(Payload::Data(vec![0, 4, 5]));
+        }
+        let val = obj(vec![
+            ("short", WzValue::Short(1)),
+            ("int", WzValue::Int(1)),
+            ("long", WzValue::Long(1)),
+            ("f32", WzValue::F32(0.5)),
+            ("null", WzValue::Null),
+            ("str", WzValue::String("front".into())),
+            ("origin", WzValue::Vec(Vec2Val { x: -1, y: 2 })),
+            ("icon", icon),
+            ("bare", bare),
+            ("alias", link("icon")),
+            ("sound", sound),
+        ]);
+        let data = WzImgWriter::write(&crypto, &val, &mut NoPayload).unwrap();
+
+        let mut r = WzReader::open_img(Cursor::new(data.clone()), region, ver).unwrap();
+        let mut img = r.root_img_reader().unwrap();
+        let mut read = WzValue::read(&mut img).unwrap();
+        read.read_payloads(&mut img).unwrap();
+        let json = serde_json::to_string(&read).unwrap();
+        assert!(json.contains(r#"{"type":"short","value":1}"#));
+        assert!(json.contains(r#"{"type":"link","value":"icon"}"#));
+
+        let back: WzValue = serde_json::from_str(&json).unwrap();
+        let rewritten = WzImgWriter::write(&crypto, &back, &mut NoPayload).unwrap();
+        assert_eq!(rewritten, data);
+
+        // Without read_payloads the payloads are copied from the source image,
+        // which a deserialized value doesn't have
+        let mut img = r.root_img_reader().unwrap();
+        let unread = WzValue::read(&mut img).unwrap();
+        let rewritten = WzImgWriter::write(&crypto, &unread, &mut img).unwrap();
+        assert_eq!(rewritten, data);
+        let json = serde_json::to_string(&unread).unwrap();
+        let back: WzValue = serde_json::from_str(&json).unwrap();
+        let err = WzImgWriter::write(&crypto, &back, &mut img).unwrap_err();
+        assert!(matches!(err, WzError::Malformed { .. }), "{err:?}");
+
+        let dir = std::env::temp_dir().join(format!("shroom-wz-{}", uuid::Uuid::new_v4()));
+        read.store_payloads(&dir).unwrap();
+        let json = serde_json::to_string(&read).unwrap();
+        assert!(json.contains(r#""payload":{"file":"icon.bin"}"#));
+        let mut back: WzValue = serde_json::from_str(&json).unwrap();
+        assert!(WzImgWriter::write(&crypto, &back, &mut NoPayload).is_err());
+        back.load_payloads(&dir).unwrap();
+
+        // Side files must stay below the directory
+        for file in ["/etc/passwd", "../icon.bin", "icon/../../icon.bin"] {
+            let mut evil = canvas(1, WzValue::Null);
+            if let WzValue::Canvas(canvas) = &mut evil {
+                canvas.payload = Some(Payload::File(file.to_string()));
+            }
+            let err = obj(vec![("icon", evil)]).load_payloads(&dir).unwrap_err();
+            assert!(matches!(err, WzError::InvalidName { .. }), "{file}");
+        }
+        let mut evil = canvas(1, WzValue::Null);
+        if let WzValue::Canvas(canvas) = &mut evil {
+            canvas.payload = Some(Payload::Data(vec![0]));
+        }
+        let err = obj(vec![("..", evil)]).store_payloads(&dir).unwrap_err();
+        assert!(matches!(err, WzError::InvalidName { .. }));
+        std::fs::remove_dir_all(&dir).unwrap();
+        let rewritten = WzImgWriter::write(&crypto, &back, &mut NoPayload).unwrap();
+        assert_eq!(rewritten, data);
+    }
+}
@@ -0,0 +1,143 @@
+use binrw::PosValue;
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    l1::{
+        canvas::{WzCanvas, WzCanvasDepth, WzCanvasScaling},
+        sound::{SoundHeader, WzSound},
+    },
+    ty::WzInt,
+};
+
+use super::{CanvasVal, Payload, SoundVal, WzValue};
+
+pub(crate) mod base64_data {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        STANDARD.decode(s).map_err(D::Error::custom)
+    }
+}
+
+// Length of the payload, which is only known when the payload is embedded
+fn payload_len(payload: &Option<Payload>, len: u32) -> u32 {
+    match payload {
+        Some(Payload::Data(data)) => data.len() as u32,
+        _ => len,
+    }
+}
+
+// Payloads in the source image can't be serialized, the same as missing ones
+fn serialized_payload(payload: &Option<Payload>) -> Option<Payload> {
+    payload
+        .clone()
+        .filter(|payload| *payload != Payload::Missing)
+}
+
+/// Canvas header without the positions of the source image,
+/// so a canvas without payload is read back with `Payload::Missing`
+#[derive(Serialize, Deserialize)]
+struct CanvasRepr {
+    width: i32,
+    height: i32,
+    depth: i32,
+    scale: u8,
+    unknown: u8,
+    unknown1: u32,
+    len: u32,
+    sub: Option<Box<WzValue>>,
+    payload: Option<Payload>,
+}
+
+impl Serialize for CanvasVal {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let canvas = &self.canvas;
+        CanvasRepr {
+            width: canvas.width.0,
+            height: canvas.height.0,
+            depth: WzInt::from(canvas.depth).0,
+            scale: canvas.scale.0,
+            unknown: canvas.unknown,
+            unknown1: canvas.unknown1,
+            len: canvas.len.val,
+            sub: self.sub.clone(),
+            payload: serialized_payload(&self.payload),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for CanvasVal {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = CanvasRepr::deserialize(deserializer)?;
+        let depth = WzCanvasDepth::try_from(WzInt(repr.depth)).map_err(D::Error::custom)?;
+        let scale = WzCanvasScaling::try_from(repr.scale).map_err(D::Error::custom)?;
+        Ok(CanvasVal {
+            canvas: WzCanvas {
+                unknown: repr.unknown,
+                has_property: repr.sub.is_some() as u8,
+                property: None,
+                width: WzInt(repr.width),
+                height: WzInt(repr.height),
+                depth,
+                scale,
+                unknown1: repr.unknown1,
+                len: PosValue {
+                    val: payload_len(&repr.payload, repr.len),
+                    pos: 0,
+                },
+            },
+            sub: repr.sub,
+            payload: Some(repr.payload.unwrap_or(Payload::Missing)),
+        })
+    }
+}
+
+/// Sound header, the media and format header are kept as raw bytes
+#[derive(Serialize, Deserialize)]
+struct SoundRepr {
+    unknown: u8,
+    size: i32,
+    len_ms: i32,
+    #[serde(with = "base64_data")]
+    header: Vec<u8>,
+    payload: Option<Payload>,
+}
+
+impl Serialize for SoundVal {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let sound = &self.sound;
+        SoundRepr {
+            unknown: sound.unknown,
+            size: sound.size.0,
+            len_ms: sound.len_ms.0,
+            header: sound.header.to_bytes().map_err(serde::ser::Error::custom)?,
+            payload: serialized_payload(&self.payload),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for SoundVal {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = SoundRepr::deserialize(deserializer)?;
+        let header = SoundHeader::from_bytes(&repr.header).map_err(D::Error::custom)?;
+        let size = payload_len(&repr.payload, repr.size as u32) as i32;
+        Ok(SoundVal {
+            sound: WzSound {
+                unknown: repr.unknown,
+                size: WzInt(size),
+                len_ms: WzInt(repr.len_ms),
+                header,
+                offset: PosValue { val: (), pos: 0 },
+            },
+            payload: Some(repr.payload.unwrap_or(Payload::Missing)),
+        })
+    }
+}
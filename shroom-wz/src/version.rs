@@ -1,6 +1,6 @@
 use crate::keys::{self, WzCryptoContext};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct WzVersion(pub u16);
 
 impl From<u16> for WzVersion {
@@ -29,6 +29,9 @@ fn encrypt_version(hash: u32) -> u16 {
         .fold(0xFFu32, |acc, i| acc ^ hash >> (i * 8) & 0xFF) as u16
 }
 
+/// Highest version which is tried when detecting the version
+pub const WZ_MAX_VERSION: u16 = 1024;
+
 impl WzVersion {
     pub fn hash(&self) -> u32 {
         version_hash(self.0)
@@ -37,9 +40,19 @@ impl WzVersion {
     pub fn encrypted_version(&self) -> u16 {
         encrypt_version(self.hash())
     }
+
+    /// All versions up to `WZ_MAX_VERSION`
+    pub fn all() -> impl Iterator<Item = WzVersion> {
+        (0..=WZ_MAX_VERSION).map(WzVersion)
+    }
+
+    /// All versions up to `WZ_MAX_VERSION` which match the encrypted version
+    pub fn candidates(encrypted_version: u16) -> impl Iterator<Item = WzVersion> {
+        Self::all().filter(move |v| v.encrypted_version() == encrypted_version)
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum WzRegion {
     GMS,
     SEA,
@@ -47,6 +60,8 @@ pub enum WzRegion {
 }
 
 impl WzRegion {
+    pub const ALL: [WzRegion; 3] = [WzRegion::GMS, WzRegion::SEA, WzRegion::Other];
+
     pub fn crypto_context(&self) -> &'static WzCryptoContext {
         match self {
             WzRegion::GMS => keys::GMS_CRYPTO_CTX,
@@ -67,4 +82,12 @@ mod tests {
         assert_eq!(v95.hash(), 1910);
         assert_eq!(v95.encrypted_version(), 142);
     }
+
+    #[test]
+    fn candidates() {
+        let candidates = WzVersion::candidates(142).collect::<Vec<_>>();
+        assert!(candidates.len() > 1);
+        assert!(candidates.contains(&WzVersion(95)));
+        assert!(candidates.iter().all(|v| v.encrypted_version() == 142));
+    }
 }
@@ -0,0 +1,845 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{BufWriter, Cursor, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use binrw::{BinWrite, NullString};
+
+use crate::{
+    crypto::WzCrypto,
+    error::{WzError, WzResult},
+    file::{WzIO, WzImgReader},
+    l0::{WzDir, WzDirHeader, WzDirNode, WzHeader, WzImgHeader, WzLinkData, WzLinkHeader},
+    l1::{
+        prop::{WzPropValue, WzVector2D},
+        sound::WzSound,
+        WzUOLStr,
+    },
+    ty::{WzF32, WzInt, WzLong, WzOffset, WzStr, WzVec},
+    util::{WzContext, WzStrTable},
+    val::{ObjectVal, Payload, Vec2Val, WzValue},
+    version::{WzRegion, WzVersion},
+    WzReader,
+};
+
+pub const WZ_DEFAULT_DESC: &str = "Package file v1.0 Copyright 2002 Wizet, ZMS";
+
+/// Encoded image blob
+#[derive(Debug, Clone)]
+pub struct WzWriterImg {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub enum WzWriterNode {
+    Dir(WzWriterDir),
+    Img(WzWriterImg),
+}
+
+impl WzWriterNode {
+    pub fn name(&self) -> &str {
+        match self {
+            WzWriterNode::Dir(dir) => &dir.name,
+            WzWriterNode::Img(img) => &img.name,
+        }
+    }
+}
+
+/// Directory tree which is written by the `WzWriter`
+#[derive(Debug, Clone)]
+pub struct WzWriterDir {
+    pub name: String,
+    pub entries: Vec<WzWriterNode>,
+}
+
+impl WzWriterDir {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn add_dir(&mut self, dir: WzWriterDir) {
+        self.entries.push(WzWriterNode::Dir(dir));
+    }
+
+    pub fn add_img(&mut self, name: impl Into<String>, data: Vec<u8>) {
+        self.entries.push(WzWriterNode::Img(WzWriterImg {
+            name: name.into(),
+            data,
+        }));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&WzWriterNode> {
+        self.entries.iter().find(|e| e.name() == name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut WzWriterNode> {
+        self.entries.iter_mut().find(|e| e.name() == name)
+    }
+
+    /// Replaces the data of the image at `path` like `Mob/100100.img`
+    pub fn replace_img(&mut self, path: &str, data: Vec<u8>) -> WzResult<()> {
+        let not_found = || WzError::NotFound {
+            path: path.to_string(),
+        };
+        let (dir, name) = match path.trim_matches('/').rsplit_once('/') {
+            Some((dir, name)) => (dir, name),
+            None => ("", path.trim_matches('/')),
+        };
+
+        let mut cur = self;
+        for part in dir.split('/').filter(|part| !part.is_empty()) {
+            cur = match cur.get_mut(part) {
+                Some(WzWriterNode::Dir(dir)) => dir,
+                _ => return Err(not_found()),
+            };
+        }
+        match cur.get_mut(name) {
+            Some(WzWriterNode::Img(img)) => {
+                img.data = data;
+                Ok(())
+            }
+            _ => Err(not_found()),
+        }
+    }
+
+    /// Reads the whole directory tree including the image data from an archive
+    pub fn from_reader<R: WzIO>(r: &mut WzReader<R>) -> WzResult<Self> {
+        let root = r.read_root_dir()?;
+        Self::read_dir(r, "Root", root)
+    }
+
+    fn read_dir<R: WzIO>(r: &mut WzReader<R>, name: &str, dir: WzDir) -> WzResult<Self> {
+        let mut res = Self::new(name);
+        for node in dir.entries.0.iter() {
+            match node {
+                WzDirNode::Dir(hdr) => {
+                    let sub = r.read_dir_node(hdr)?;
+                    res.add_dir(Self::read_dir(r, hdr.name.as_str(), sub)?);
+                }
+                WzDirNode::Img(hdr) => {
+                    res.add_img(hdr.name.as_str(), r.read_img_data(hdr)?);
+                }
+                WzDirNode::Link(link) => {
+                    let hdr = link.img_header();
+                    res.add_img(hdr.name.as_str(), r.read_img_data(&hdr)?);
+                }
+                WzDirNode::Nil(_) => {}
+            }
+        }
+        Ok(res)
+    }
+}
+
+/// Source of the canvas and sound payloads, which the `WzImgWriter` copies
+pub trait WzPayloadSource {
+    fn read_payload(&mut self, offset: u64, len: usize) -> WzResult<Vec<u8>>;
+}
+
+impl<R: WzIO> WzPayloadSource for WzImgReader<R> {
+    fn read_payload(&mut self, offset: u64, len: usize) -> WzResult<Vec<u8>> {
+        self.read_raw(offset, len)
+    }
+}
+
+/// Source for values without canvases and sounds
+pub struct NoPayload;
+
+impl WzPayloadSource for NoPayload {
+    fn read_payload(&mut self, offset: u64, _len: usize) -> WzResult<Vec<u8>> {
+        Err(WzError::malformed(offset, "no source for the payload"))
+    }
+}
+
+/// Writes a `WzValue` tree as image data
+///
+/// Strings which occur more than once are written once and referenced by offset.
+/// Canvas and sound payloads are taken from the values if they are embedded,
+/// otherwise they are copied unchanged from the source image, so the
+/// crypto must match the source for encrypted canvases.
+pub struct WzImgWriter<'a, S> {
+    ctx: WzContext<'a>,
+    src: &'a mut S,
+    // Offsets of the written strings
+    strs: HashMap<String, u32>,
+    w: Cursor<Vec<u8>>,
+}
+
+impl<'a, S: WzPayloadSource> WzImgWriter<'a, S> {
+    /// Writes the image with `val` as root object
+    pub fn write(crypto: &WzCrypto, val: &WzValue, src: &mut S) -> WzResult<Vec<u8>> {
+        let str_table = WzStrTable::default();
+        let mut w = WzImgWriter {
+            ctx: WzContext::new(crypto, &str_table),
+            src,
+            strs: HashMap::new(),
+            w: Cursor::new(Vec::new()),
+        };
+        w.write_obj(val)?;
+        Ok(w.w.into_inner())
+    }
+
+    fn pos(&self) -> u64 {
+        self.w.position()
+    }
+
+    fn write_str(&mut self, s: &str, type_name: bool) -> WzResult<()> {
+        if let Some(&offset) = self.strs.get(s) {
+            let magic: u8 = if type_name { 0x1b } else { 1 };
+            (magic, offset).write_le(&mut self.w)?;
+            return Ok(());
+        }
+
+        // Only strings which are longer than an offset are worth to be referenced
+        if s.len() > 4 {
+            self.strs.insert(s.to_string(), self.pos() as u32 + 1);
+        }
+        let s = WzStr::new(s.to_string());
+        let s = if type_name {
+            WzUOLStr::StrTypeName(s)
+        } else {
+            WzUOLStr::Str(s)
+        };
+        s.write_le_args(&mut self.w, self.ctx)?;
+        Ok(())
+    }
+
+    /// Embedded payload of the value or the payload from the source
+    fn payload(&mut self, payload: Option<&Payload>, offset: u64, len: u32) -> WzResult<Vec<u8>> {
+        match payload {
+            Some(Payload::Data(data)) => Ok(data.clone()),
+            Some(Payload::File(file)) => Err(WzError::malformed(
+                offset,
+                format!("payload file {file} is not loaded"),
+            )),
+            Some(Payload::Missing) => Err(WzError::malformed(
+                offset,
+                "payload is missing and there is no source for it",
+            )),
+            None => self.src.read_payload(offset, len as usize),
+        }
+    }
+
+    fn write_vec2(&mut self, v: &Vec2Val) -> WzResult<()> {
+        WzVector2D {
+            x: WzInt(v.x),
+            y: WzInt(v.y),
+        }
+        .write_le(&mut self.w)?;
+        Ok(())
+    }
+
+    fn write_prop(&mut self, obj: &ObjectVal) -> WzResult<()> {
+        0u16.write_le(&mut self.w)?;
+        WzInt(obj.0.len() as i32).write_le(&mut self.w)?;
+        for (k, v) in obj.0.iter() {
+            self.write_str(k, false)?;
+            self.write_prop_val(v).map_err(|err| err.in_parent(k))?;
+        }
+        Ok(())
+    }
+
+    fn write_prop_val(&mut self, val: &WzValue) -> WzResult<()> {
+        let val = match val {
+            WzValue::Null => WzPropValue::Null,
+            WzValue::Short(v) => WzPropValue::Short1(*v),
+            WzValue::Int(v) => WzPropValue::Int1(WzInt(*v)),
+            WzValue::Long(v) => WzPropValue::Long(WzLong(*v)),
+            WzValue::F32(v) => WzPropValue::F32(WzF32(*v)),
+            WzValue::F64(v) => WzPropValue::F64(*v),
+            WzValue::String(v) => {
+                8u8.write_le(&mut self.w)?;
+                return self.write_str(v, false);
+            }
+            obj => {
+                // Objects are prefixed with their length
+                9u8.write_le(&mut self.w)?;
+                let len_pos = self.pos();
+                0u32.write_le(&mut self.w)?;
+                self.write_obj(obj)?;
+                let end = self.pos();
+                self.w.set_position(len_pos);
+                ((end - len_pos - 4) as u32).write_le(&mut self.w)?;
+                self.w.set_position(end);
+                return Ok(());
+            }
+        };
+        val.write_le_args(&mut self.w, self.ctx)?;
+        Ok(())
+    }
+
+    fn write_obj(&mut self, val: &WzValue) -> WzResult<()> {
+        match val {
+            WzValue::Object(obj) => {
+                self.write_str("Property", true)?;
+                self.write_prop(obj)?;
+            }
+            WzValue::Canvas(canvas) => {
+                let hdr = &canvas.canvas;
+                self.write_str("Canvas", true)?;
+                let sub = canvas.sub.as_deref().and_then(WzValue::as_object);
+                (hdr.unknown, sub.is_some() as u8).write_le(&mut self.w)?;
+                if let Some(sub) = sub {
+                    self.write_prop(sub)?;
+                }
+                let data = self.payload(canvas.payload.as_ref(), hdr.len.pos + 4, hdr.len.val)?;
+                (
+                    hdr.width,
+                    hdr.height,
+                    WzInt::from(hdr.depth),
+                    hdr.scale.0,
+                    hdr.unknown1,
+                    data.len() as u32,
+                )
+                    .write_le(&mut self.w)?;
+                self.w.write_all(&data)?;
+            }
+            WzValue::Sound(sound) => {
+                self.write_str("Sound_DX8", true)?;
+                let data = self.payload(
+                    sound.payload.as_ref(),
+                    sound.sound.offset.pos,
+                    sound.sound.raw_size() as u32,
+                )?;
+                WzSound {
+                    size: WzInt(data.len() as i32),
+                    ..sound.sound.clone()
+                }
+                .write_le_args(&mut self.w, self.ctx)?;
+                self.w.write_all(&data)?;
+            }
+            WzValue::Link(link) => {
+                self.write_str("UOL", true)?;
+                0u8.write_le(&mut self.w)?;
+                self.write_str(link, false)?;
+            }
+            WzValue::Vec(v) => {
+                self.write_str("Shape2D#Vector2D", true)?;
+                self.write_vec2(v)?;
+            }
+            WzValue::Convex(vex) => {
+                self.write_str("Shape2D#Convex2D", true)?;
+                WzInt(vex.0.len() as i32).write_le(&mut self.w)?;
+                for v in vex.0.iter() {
+                    self.write_str("Shape2D#Vector2D", true)?;
+                    self.write_vec2(v)?;
+                }
+            }
+            _ => {
+                return Err(WzError::UnexpectedValue {
+                    expected: "Object",
+                    path: None,
+                })
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum EntryKind {
+    Dir(usize),
+    Img(usize),
+    // Image which re-uses the name of the entry at (dir, entry)
+    Link {
+        img: usize,
+        dir: usize,
+        entry: usize,
+    },
+}
+
+#[derive(Debug, Default)]
+struct Layout<'a> {
+    dirs: Vec<&'a WzWriterDir>,
+    imgs: Vec<&'a WzWriterImg>,
+    entries: Vec<Vec<EntryKind>>,
+    // Size and checksum for dirs and images
+    dir_blobs: Vec<(i32, i32)>,
+    img_blobs: Vec<(i32, i32)>,
+    // Encoded size of every dir listing entry
+    entry_sizes: Vec<Vec<u64>>,
+    dir_pos: Vec<u64>,
+    img_pos: Vec<u64>,
+}
+
+impl<'a> Layout<'a> {
+    fn new(root: &'a WzWriterDir) -> Self {
+        let mut layout = Self::default();
+        let mut names = HashMap::new();
+        let mut q = VecDeque::new();
+        layout.dirs.push(root);
+        q.push_back(0);
+
+        // Dirs are stored in BFS order, so every child dir has a higher index
+        while let Some(dir_ix) = q.pop_front() {
+            let dir = layout.dirs[dir_ix];
+            let mut entries = Vec::with_capacity(dir.entries.len());
+            for (entry_ix, node) in dir.entries.iter().enumerate() {
+                entries.push(match node {
+                    WzWriterNode::Dir(sub) => {
+                        layout.dirs.push(sub);
+                        q.push_back(layout.dirs.len() - 1);
+                        EntryKind::Dir(layout.dirs.len() - 1)
+                    }
+                    WzWriterNode::Img(img) => {
+                        layout.imgs.push(img);
+                        let img_ix = layout.imgs.len() - 1;
+                        // Re-use image names which were already written
+                        match names.get(img.name.as_str()) {
+                            Some(&(dir, entry)) => EntryKind::Link {
+                                img: img_ix,
+                                dir,
+                                entry,
+                            },
+                            None => {
+                                names.insert(img.name.as_str(), (dir_ix, entry_ix));
+                                EntryKind::Img(img_ix)
+                            }
+                        }
+                    }
+                });
+            }
+            layout.entries.push(entries);
+        }
+
+        layout.img_blobs = layout
+            .imgs
+            .iter()
+            .map(|img| (img.data.len() as i32, checksum(&img.data)))
+            .collect();
+        layout
+    }
+
+    fn blob(&self, kind: EntryKind) -> (i32, i32) {
+        match kind {
+            EntryKind::Dir(dir) => self.dir_blobs[dir],
+            EntryKind::Img(img) | EntryKind::Link { img, .. } => self.img_blobs[img],
+        }
+    }
+
+    fn entry_pos(&self, dir: usize, entry: usize) -> u64 {
+        let n = self.entries[dir].len();
+        self.dir_pos[dir]
+            + wz_int_size(n as i32)
+            + self.entry_sizes[dir][..entry].iter().sum::<u64>()
+    }
+
+    fn dir_node(&self, dir: usize, entry: usize, data_offset: u64) -> WzDirNode {
+        let kind = self.entries[dir][entry];
+        let name = WzStr::new(self.dirs[dir].entries[entry].name().to_string());
+        let (size, checksum) = self.blob(kind);
+        let blob_size = WzInt(size);
+        let checksum = WzInt(checksum);
+        // Offsets are only known after the sizes are calculated
+        let pos = |v: &[u64], ix: usize| WzOffset(v.get(ix).copied().unwrap_or(0) as u32);
+
+        match kind {
+            EntryKind::Dir(sub) => WzDirNode::Dir(WzDirHeader {
+                name,
+                blob_size,
+                checksum,
+                offset: pos(&self.dir_pos, sub),
+            }),
+            EntryKind::Img(img) => WzDirNode::Img(WzImgHeader {
+                name,
+                blob_size,
+                checksum,
+                offset: pos(&self.img_pos, img),
+            }),
+            EntryKind::Link {
+                img,
+                dir: link_dir,
+                entry: link_entry,
+            } => {
+                let link_offset = if self.dir_pos.is_empty() {
+                    0
+                } else {
+                    self.entry_pos(link_dir, link_entry) - data_offset
+                };
+                WzDirNode::Link(WzLinkHeader {
+                    link: WzLinkData {
+                        offset: link_offset as u32,
+                        link_img: WzImgHeader {
+                            name,
+                            blob_size,
+                            checksum,
+                            offset: pos(&self.img_pos, img),
+                        },
+                    },
+                    blob_size,
+                    checksum,
+                    offset: pos(&self.img_pos, img),
+                })
+            }
+        }
+    }
+
+    fn dir(&self, dir: usize, data_offset: u64) -> WzDir {
+        WzDir {
+            entries: WzVec(
+                (0..self.entries[dir].len())
+                    .map(|entry| self.dir_node(dir, entry, data_offset))
+                    .collect(),
+            ),
+        }
+    }
+
+    fn calc_sizes(&mut self, ctx: WzContext<'_>, data_offset: u64) -> WzResult<()> {
+        let n = self.dirs.len();
+        self.dir_blobs = vec![(0, 0); n];
+        self.entry_sizes = vec![Vec::new(); n];
+
+        // Walk backwards, so the blobs of all child dirs are known
+        for dir in (0..n).rev() {
+            let mut size = wz_int_size(self.entries[dir].len() as i32);
+            let mut sizes = Vec::with_capacity(self.entries[dir].len());
+            let mut blob_size = 0i32;
+            let mut blob_checksum = 0i32;
+
+            for entry in 0..self.entries[dir].len() {
+                // Offsets are encrypted based on the position, so It must be after the data offset
+                let mut buf = Cursor::new(Vec::new());
+                buf.set_position(data_offset);
+                self.dir_node(dir, entry, data_offset)
+                    .write_le_args(&mut buf, ctx)?;
+                let entry_size = buf.position() - data_offset;
+                size += entry_size;
+                sizes.push(entry_size);
+
+                let (sz, checksum) = self.blob(self.entries[dir][entry]);
+                blob_size = blob_size.wrapping_add(sz);
+                blob_checksum = blob_checksum.wrapping_add(checksum);
+            }
+
+            self.entry_sizes[dir] = sizes;
+            self.dir_blobs[dir] = (blob_size.wrapping_add(size as i32), blob_checksum);
+        }
+
+        Ok(())
+    }
+
+    fn calc_positions(&mut self, root_offset: u64) -> u64 {
+        let mut pos = root_offset;
+        for dir in 0..self.dirs.len() {
+            self.dir_pos.push(pos);
+            pos += self.entry_sizes[dir].iter().sum::<u64>()
+                + wz_int_size(self.entries[dir].len() as i32);
+        }
+
+        for img in self.imgs.iter() {
+            self.img_pos.push(pos);
+            pos += img.data.len() as u64;
+        }
+
+        pos
+    }
+}
+
+fn checksum(data: &[u8]) -> i32 {
+    data.iter().fold(0i32, |acc, &b| acc.wrapping_add(b as i32))
+}
+
+fn wz_int_size(v: i32) -> u64 {
+    match i8::try_from(v) {
+        Ok(v) if v != -128 => 1,
+        _ => 5,
+    }
+}
+
+/// Writer for `PKG1` archives, which can be read by the `WzReader`
+#[derive(Debug)]
+pub struct WzWriter {
+    region: WzRegion,
+    version: WzVersion,
+    desc: String,
+    version_header: bool,
+}
+
+impl WzWriter {
+    pub fn new(region: WzRegion, version: WzVersion) -> Self {
+        Self {
+            region,
+            version,
+            desc: WZ_DEFAULT_DESC.to_string(),
+            version_header: true,
+        }
+    }
+
+    /// Writer for archives without the encrypted version like later clients use
+    pub fn new_versionless(region: WzRegion, version: WzVersion) -> Self {
+        Self {
+            version_header: false,
+            ..Self::new(region, version)
+        }
+    }
+
+    pub fn data_offset(&self) -> u32 {
+        // Magic + file size + data offset + null terminated description
+        (4 + 8 + 4 + self.desc.len() + 1) as u32
+    }
+
+    pub fn write_file(&self, path: impl AsRef<Path>, root: &WzWriterDir) -> WzResult<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write(&mut w, root)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Writes the archive, the writer has to start at position 0
+    pub fn write<W: Write + Seek>(&self, w: &mut W, root: &WzWriterDir) -> WzResult<()> {
+        let data_offset = self.data_offset();
+        let crypto = WzCrypto::from_region(self.region, self.version, data_offset);
+        let str_table = WzStrTable::default();
+        let ctx = WzContext::new(&crypto, &str_table);
+
+        let mut layout = Layout::new(root);
+        layout.calc_sizes(ctx, data_offset as u64)?;
+        // Root dir starts after the encrypted version
+        let version_len = if self.version_header { 2 } else { 0 };
+        let end = layout.calc_positions(data_offset as u64 + version_len);
+
+        WzHeader {
+            file_size: end - data_offset as u64,
+            data_offset,
+            desc: NullString::from(self.desc.as_str()),
+        }
+        .write_le(w)?;
+        w.seek(SeekFrom::Start(data_offset as u64))?;
+        if self.version_header {
+            self.version.encrypted_version().write_le(w)?;
+        }
+
+        for dir in 0..layout.dirs.len() {
+            debug_assert_eq!(w.stream_position()?, layout.dir_pos[dir]);
+            layout.dir(dir, data_offset as u64).write_le_args(w, ctx)?;
+        }
+
+        for img in layout.imgs.iter() {
+            w.write_all(&img.data)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Cursor;
+
+    use binrw::BinWrite;
+
+    use crate::{
+        crypto::WzCrypto,
+        l0::{tree::WzTree, WzDirNode},
+        l1::{
+            prop::{WzPropValue, WzProperty, WzPropertyEntry},
+            WzUOLStr,
+        },
+        link::tests::{canvas, img},
+        ty::{WzInt, WzStr, WzVec},
+        util::{WzContext, WzStrTable},
+        val::{Vec2Val, Vex2Val, WzValue},
+        version::{WzRegion, WzVersion},
+        WzReader,
+    };
+
+    use super::{NoPayload, WzImgWriter, WzWriter, WzWriterDir};
+
+    pub(crate) fn int_img(region: WzRegion, v: i32) -> Vec<u8> {
+        // Images don't depend on the data offset
+        let crypto = WzCrypto::from_region(region, WzVersion(95), 0);
+        let str_table = WzStrTable::default();
+        let ctx = WzContext::new(&crypto, &str_table);
+
+        let mut w = Cursor::new(Vec::new());
+        WzUOLStr::StrTypeName(WzStr::new("Property".to_string()))
+            .write_le_args(&mut w, ctx)
+            .unwrap();
+        WzProperty {
+            unknown: 0,
+            entries: WzVec(vec![WzPropertyEntry {
+                name: WzUOLStr::Str(WzStr::new("level".to_string())),
+                val: WzPropValue::Int1(WzInt(v)),
+            }]),
+        }
+        .write_le_args(&mut w, ctx)
+        .unwrap();
+        w.into_inner()
+    }
+
+    #[test]
+    fn write_read() {
+        let region = WzRegion::GMS;
+        let mut root = WzWriterDir::new("Root");
+        let mut mob = WzWriterDir::new("Mob");
+        mob.add_img("100100.img", int_img(region, 1));
+        mob.add_img("100101.img", int_img(region, 2));
+        let mut sub = WzWriterDir::new("Sub");
+        // Same name in another dir gets linked
+        sub.add_img("100100.img", int_img(region, 3));
+        mob.add_dir(sub);
+        root.add_dir(mob);
+        root.add_img("a".repeat(200) + ".img", int_img(region, 4));
+
+        let mut w = Cursor::new(Vec::new());
+        WzWriter::new(region, WzVersion(95))
+            .write(&mut w, &root)
+            .unwrap();
+        w.set_position(0);
+
+        let mut r = WzReader::open(w, region, WzVersion(95)).unwrap();
+        let tree = WzTree::from_reader(&mut r, None).unwrap();
+        assert!(matches!(
+            tree.get_by_path("Mob/Sub/100100.img"),
+            Some(WzDirNode::Link(_))
+        ));
+
+        let imgs = r.traverse_images().collect::<Result<Vec<_>, _>>().unwrap();
+        let names = imgs
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names.len(), 4);
+        assert!(names.contains(&"/root/Mob/Sub/100100.img"));
+
+        for (name, img) in imgs.iter() {
+            let mut img = r.img_reader(img).unwrap();
+            let val = WzValue::read(&mut img).unwrap();
+            let level = val.get_path("level").unwrap().as_i32().unwrap();
+            let expected = match name.as_str() {
+                "/root/Mob/100100.img" => 1,
+                "/root/Mob/100101.img" => 2,
+                "/root/Mob/Sub/100100.img" => 3,
+                _ => 4,
+            };
+            assert_eq!(level, expected);
+        }
+
+        // Writing the read tree again must yield the same archive
+        let read_root = WzWriterDir::from_reader(&mut r).unwrap();
+        let mut w = Cursor::new(Vec::new());
+        WzWriter::new(region, WzVersion(95))
+            .write(&mut w, &read_root)
+            .unwrap();
+        let mut orig = Cursor::new(Vec::new());
+        WzWriter::new(region, WzVersion(95))
+            .write(&mut orig, &root)
+            .unwrap();
+        assert_eq!(w.into_inner(), orig.into_inner());
+    }
+
+    #[test]
+    fn write_value() {
+        let region = WzRegion::GMS;
+        let ver = WzVersion(95);
+        let crypto = WzCrypto::from_region(region, ver, 0);
+        let str_table = WzStrTable::default();
+        let ctx = WzContext::new(&crypto, &str_table);
+        let data = img(
+            ctx,
+            &[
+                ("info", int_img(region, 1)),
+                ("icon", canvas(ctx, [1, 2, 3, 4], &[("z", "front")])),
+            ],
+        );
+        let mut r = WzReader::open_img(Cursor::new(data), region, ver).unwrap();
+        let mut src = r.root_img_reader().unwrap();
+        let mut val = WzValue::read(&mut src).unwrap();
+
+        let WzValue::Object(root) = &mut val else {
+            panic!("root must be an object");
+        };
+        let WzValue::Object(info) = &mut root["info"] else {
+            panic!("info must be an object");
+        };
+        info["level"] = WzValue::Int(7);
+        info.0
+            .insert("name".to_string(), WzValue::String("Mushroom".to_string()));
+        info.0
+            .insert("desc".to_string(), WzValue::String("Mushroom".to_string()));
+        info.0
+            .insert("origin".to_string(), WzValue::Vec(Vec2Val { x: -3, y: 5 }));
+        info.0.insert(
+            "hit".to_string(),
+            WzValue::Convex(Vex2Val(vec![
+                Vec2Val { x: 1, y: 2 },
+                Vec2Val { x: 3, y: 4 },
+            ])),
+        );
+        root.0
+            .insert("alias".to_string(), WzValue::Link("info".to_string()));
+
+        let data = src.write_value(&val).unwrap();
+        let mut r = WzReader::open_img(Cursor::new(data.clone()), region, ver).unwrap();
+        let mut img = r.root_img_reader().unwrap();
+        let read = WzValue::read(&mut img).unwrap();
+        assert_eq!(read.get_path("info/level").unwrap().as_i32(), Some(7));
+        assert_eq!(
+            read.get_path("info/desc").unwrap().as_string(),
+            Some("Mushroom")
+        );
+        assert_eq!(read.get_path("info/origin").unwrap().as_vec().unwrap().y, 5);
+        assert_eq!(
+            read.get_path("info/hit").unwrap().as_convex().unwrap().0[1].x,
+            3
+        );
+        assert_eq!(
+            read.get_path_resolved("alias/name")
+                .unwrap()
+                .unwrap()
+                .as_string(),
+            Some("Mushroom")
+        );
+        assert_eq!(read.get_path("icon/z").unwrap().as_string(), Some("front"));
+        let icon = read.get_path("icon").unwrap().as_canvas().unwrap();
+        let orig = val.get_path("icon").unwrap().as_canvas().unwrap();
+        assert_eq!(
+            icon.read_canvas(&mut img).unwrap().data(),
+            orig.read_canvas(&mut src).unwrap().data()
+        );
+
+        // Writing the read value again yields the same image
+        assert_eq!(img.write_value(&read).unwrap(), data);
+
+        // Repeated strings are referenced
+        let strs = |a: &str, b: &str| {
+            let val = WzValue::Object(crate::val::ObjectVal(
+                [("a", a), ("b", b)]
+                    .into_iter()
+                    .map(|(k, v)| (k.to_string(), WzValue::String(v.to_string())))
+                    .collect(),
+            ));
+            WzImgWriter::write(&crypto, &val, &mut NoPayload).unwrap()
+        };
+        assert!(strs("Mushroom", "Mushroom").len() < strs("Mushroom", "Snailxxx").len());
+        assert!(WzImgWriter::write(&crypto, &val, &mut NoPayload).is_err());
+
+        // Replace the image in an archive
+        let mut root = WzWriterDir::new("Root");
+        let mut mob = WzWriterDir::new("Mob");
+        mob.add_img("100100.img", int_img(region, 1));
+        root.add_dir(mob);
+        let mut w = Cursor::new(Vec::new());
+        WzWriter::new(region, ver).write(&mut w, &root).unwrap();
+        w.set_position(0);
+
+        let mut r = WzReader::open(w, region, ver).unwrap();
+        let mut root = WzWriterDir::from_reader(&mut r).unwrap();
+        root.replace_img("Mob/100100.img", data).unwrap();
+        assert!(root.replace_img("Mob/100101.img", Vec::new()).is_err());
+        let mut w = Cursor::new(Vec::new());
+        WzWriter::new(region, ver).write(&mut w, &root).unwrap();
+        w.set_position(0);
+
+        let mut r = WzReader::open(w, region, ver).unwrap();
+        let tree = WzTree::from_reader(&mut r, None).unwrap();
+        let hdr = tree.get_img_by_path("Mob/100100.img").unwrap();
+        let val = WzValue::read(&mut r.img_reader(hdr).unwrap()).unwrap();
+        assert_eq!(val.get_path("info/level").unwrap().as_i32(), Some(7));
+    }
+}